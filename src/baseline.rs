@@ -0,0 +1,32 @@
+use std::collections::BTreeSet;
+
+use lsp_types::{CallHierarchyItem, Url};
+use serde::{Deserialize, Serialize};
+
+use crate::build_call_hierarchy_item_name;
+
+/// Problems known from an earlier run, which aren't reported again so only
+/// new problems stand out.
+///
+/// Items are identified by [`build_call_hierarchy_item_name`], which doesn't
+/// depend on the configured [`crate::NameFormat`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Baseline {
+    pub problems: BTreeSet<String>,
+}
+
+impl Baseline {
+    pub fn new<'a>(problems: impl IntoIterator<Item = &'a CallHierarchyItem>, root: &Url) -> Self {
+        Self {
+            problems: problems
+                .into_iter()
+                .map(|item| build_call_hierarchy_item_name(item, root))
+                .collect(),
+        }
+    }
+
+    pub fn contains(&self, item: &CallHierarchyItem, root: &Url) -> bool {
+        self.problems
+            .contains(&build_call_hierarchy_item_name(item, root))
+    }
+}
@@ -0,0 +1,335 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use lsp_types::{CallHierarchyItem, Url};
+
+use crate::{
+    find_items_with_different_depths, get_target_depths,
+    graph_util::{find_cycles, find_roots},
+    hashable_call_hierarchy_item::HashableCallHierarchyItem,
+    to_hashable_calls, try_get_function_depths, Call, CodeDepthError, Depths, NameFormat,
+};
+
+/// Groups of mutually recursive functions, the strongly connected components
+/// of the graph with more than one function, or a function calling itself.
+/// These are the report's `cycles`.
+///
+/// Recursion makes a function reachable at several depths, grouping the
+/// functions shows which ones to untangle together. Each group and the groups
+/// themselves are in the order of their first call.
+pub fn find_recursion_clusters(calls: &[Call]) -> Vec<Vec<&CallHierarchyItem>> {
+    let mut seen = HashSet::new();
+    let order = calls
+        .iter()
+        .flat_map(|call| [&call.caller, &call.callee])
+        .filter(|item| seen.insert(HashableCallHierarchyItem((*item).clone())))
+        .enumerate()
+        .map(|(index, item)| (HashableCallHierarchyItem(item.clone()), (index, item)))
+        .collect::<HashMap<_, _>>();
+
+    let mut clusters = find_cycles(&to_hashable_calls(calls))
+        .into_iter()
+        .map(|cycle| {
+            let mut members = cycle.iter().map(|item| order[item]).collect::<Vec<_>>();
+            members.sort_by_key(|(index, _)| *index);
+            members
+        })
+        .collect::<Vec<_>>();
+    clusters.sort_by_key(|members| members[0].0);
+
+    clusters
+        .into_iter()
+        .map(|members| members.into_iter().map(|(_, item)| item).collect())
+        .collect()
+}
+
+/// The calls between the analyzed functions, as returned by
+/// [`crate::get_function_calls`], with the queries the analysis runs on them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CallGraph {
+    calls: Vec<Call>,
+}
+
+impl From<Vec<Call>> for CallGraph {
+    fn from(calls: Vec<Call>) -> Self {
+        Self { calls }
+    }
+}
+
+impl CallGraph {
+    pub fn new(calls: Vec<Call>) -> Self {
+        calls.into()
+    }
+
+    pub fn calls(&self) -> &[Call] {
+        &self.calls
+    }
+
+    pub fn into_calls(self) -> Vec<Call> {
+        self.calls
+    }
+
+    /// The functions without callers, which depths are measured from, in the
+    /// order of their first call.
+    pub fn roots(&self) -> Vec<&CallHierarchyItem> {
+        let edges = to_hashable_calls(&self.calls);
+        let roots = find_roots(&edges);
+
+        self.unique_items(self.calls.iter().map(|call| &call.caller))
+            .into_iter()
+            .filter(|item| roots.contains(&HashableCallHierarchyItem((*item).clone())))
+            .collect()
+    }
+
+    /// The functions `item` calls, each one once.
+    pub fn neighbors(&self, item: &CallHierarchyItem) -> Vec<&CallHierarchyItem> {
+        let item = HashableCallHierarchyItem(item.clone());
+        let callees = self
+            .calls
+            .iter()
+            .filter(|call| HashableCallHierarchyItem(call.caller.clone()) == item)
+            .map(|call| &call.callee);
+
+        self.unique_items(callees)
+    }
+
+    /// The functions calling `item`, or with `transitive` also the functions
+    /// calling those, and so on. Nearest callers first, each one once.
+    ///
+    /// A recursive `item` is among its transitive callers.
+    pub fn callers_of(
+        &self,
+        item: &CallHierarchyItem,
+        transitive: bool,
+    ) -> Vec<&CallHierarchyItem> {
+        self.linked(item, transitive, |call| (&call.callee, &call.caller))
+    }
+
+    /// The functions `item` calls, or with `transitive` also the functions
+    /// those call, and so on. Nearest callees first, each one once, so the
+    /// walk ends in mutually recursive functions too.
+    ///
+    /// A recursive `item` is among its transitive callees.
+    pub fn callees_of(
+        &self,
+        item: &CallHierarchyItem,
+        transitive: bool,
+    ) -> Vec<&CallHierarchyItem> {
+        self.linked(item, transitive, |call| (&call.caller, &call.callee))
+    }
+
+    /// The functions linked to `item` by `step`, which maps a call to the
+    /// function it links from and the one it links to.
+    fn linked<'a>(
+        &'a self,
+        item: &CallHierarchyItem,
+        transitive: bool,
+        step: impl Fn(&'a Call) -> (&'a CallHierarchyItem, &'a CallHierarchyItem),
+    ) -> Vec<&'a CallHierarchyItem> {
+        let mut links = HashMap::<_, Vec<_>>::new();
+        for (from, to) in self.calls.iter().map(step) {
+            links
+                .entry(HashableCallHierarchyItem(from.clone()))
+                .or_default()
+                .push(to);
+        }
+
+        // a breadth first walk, visiting each function once so cycles end
+        let mut seen = HashSet::new();
+        let mut linked = vec![];
+        let mut queue = VecDeque::from([(item.clone(), 0)]);
+        while let Some((current, distance)) = queue.pop_front() {
+            if distance > 0 && !transitive {
+                break;
+            }
+
+            let Some(next) = links.get(&HashableCallHierarchyItem(current)) else {
+                continue;
+            };
+            for next in next {
+                if seen.insert(HashableCallHierarchyItem((*next).clone())) {
+                    linked.push(*next);
+                    queue.push_back(((*next).clone(), distance + 1));
+                }
+            }
+        }
+
+        linked
+    }
+
+    /// The paths from the roots to each function, see [`crate::get_function_depths`].
+    pub fn depths(&self) -> Depths<CallHierarchyItem> {
+        self.try_depths(None).expect("no max depth to exceed")
+    }
+
+    /// Like [`CallGraph::depths`], but fails as soon as a path deeper than
+    /// `max_depth` is found, see [`crate::try_get_function_depths`].
+    pub fn try_depths(
+        &self,
+        max_depth: Option<usize>,
+    ) -> Result<Depths<CallHierarchyItem>, CodeDepthError> {
+        try_get_function_depths(&self.calls, max_depth)
+    }
+
+    /// The depths of the function named `name` and of the functions on its
+    /// paths from the roots, see [`crate::get_target_depths`]. Empty if no
+    /// function has that name.
+    pub fn depths_for(
+        &self,
+        name: &str,
+        root: &Url,
+        name_format: &NameFormat,
+    ) -> Depths<CallHierarchyItem> {
+        get_target_depths::<_, HashableCallHierarchyItem>(self.depths(), |item| {
+            name_format.matches(item, root, name)
+        })
+    }
+
+    /// The functions reached at different depths, see
+    /// [`crate::find_items_with_different_depths`].
+    pub fn find_problems(&self) -> Vec<CallHierarchyItem> {
+        let depths = self.depths();
+        let problems = find_items_with_different_depths::<_, HashableCallHierarchyItem>(&depths);
+
+        depths
+            .into_iter()
+            .map(|(item, _)| item)
+            .filter(|item| problems.contains(&HashableCallHierarchyItem(item.clone())))
+            .collect()
+    }
+
+    /// Groups of mutually recursive functions, see [`find_recursion_clusters`].
+    pub fn recursion_clusters(&self) -> Vec<Vec<&CallHierarchyItem>> {
+        find_recursion_clusters(&self.calls)
+    }
+
+    /// `items` without duplicates, in their order.
+    fn unique_items<'a>(
+        &'a self,
+        items: impl Iterator<Item = &'a CallHierarchyItem>,
+    ) -> Vec<&'a CallHierarchyItem> {
+        let mut seen = HashSet::new();
+
+        items
+            .filter(|item| seen.insert(HashableCallHierarchyItem((*item).clone())))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{CallHierarchyItem, Url};
+
+    use super::CallGraph;
+    use crate::get_function_depths;
+    use crate::test_util::{call, item};
+
+    #[test]
+    fn test_call_graph() {
+        let (r1, r2, y, x) = (item("r1", 0), item("r2", 1), item("y", 2), item("x", 3));
+        let calls = vec![call(&r1, &x), call(&r2, &y), call(&y, &x), call(&r1, &x)];
+        let graph = CallGraph::new(calls.clone());
+
+        assert_eq!(graph.roots(), [&r1, &r2]);
+        assert_eq!(graph.neighbors(&r1), [&x]);
+        assert!(graph.neighbors(&x).is_empty());
+        assert_eq!(graph.find_problems(), [x]);
+
+        let sorted = |mut depths: Vec<(CallHierarchyItem, Vec<Vec<CallHierarchyItem>>)>| {
+            depths.sort_by_key(|(item, _)| item.selection_range.start);
+            depths
+                .iter_mut()
+                .for_each(|(_, paths)| paths.sort_by_key(Vec::len));
+            depths
+        };
+        assert_eq!(sorted(graph.depths()), sorted(get_function_depths(&calls)));
+
+        let root = Url::parse("file:///project").unwrap();
+        let target_depths = graph.depths_for("src/main.rs:y", &root, &Default::default());
+        assert_eq!(
+            sorted(target_depths),
+            vec![
+                (r2.clone(), vec![vec![r2.clone()]]),
+                (y.clone(), vec![vec![r2, y]])
+            ]
+        );
+        assert!(graph
+            .depths_for("src/main.rs:z", &root, &Default::default())
+            .is_empty());
+    }
+
+    #[test]
+    fn test_callers_of() {
+        let (main, a, b, c, d) = (
+            item("main", 0),
+            item("a", 1),
+            item("b", 2),
+            item("c", 3),
+            item("d", 4),
+        );
+        // `a` and `b` call each other
+        let calls = vec![
+            call(&main, &a),
+            call(&a, &b),
+            call(&b, &a),
+            call(&b, &c),
+            call(&d, &c),
+        ];
+        let graph = CallGraph::new(calls);
+
+        assert_eq!(graph.callers_of(&c, false), [&b, &d]);
+        assert_eq!(graph.callers_of(&c, true), [&b, &d, &a, &main]);
+        assert_eq!(graph.callers_of(&a, true), [&main, &b, &a]);
+        assert!(graph.callers_of(&main, true).is_empty());
+    }
+
+    #[test]
+    fn test_callees_of() {
+        let (main, a, b, c, d) = (
+            item("main", 0),
+            item("a", 1),
+            item("b", 2),
+            item("c", 3),
+            item("d", 4),
+        );
+        // `a` and `b` call each other
+        let calls = vec![
+            call(&main, &a),
+            call(&main, &d),
+            call(&a, &b),
+            call(&b, &a),
+            call(&b, &c),
+        ];
+        let graph = CallGraph::new(calls);
+
+        assert_eq!(graph.callees_of(&main, false), [&a, &d]);
+        assert_eq!(graph.callees_of(&main, true), [&a, &d, &b, &c]);
+        assert_eq!(graph.callees_of(&a, true), [&b, &a, &c]);
+        assert!(graph.callees_of(&c, true).is_empty());
+    }
+
+    #[test]
+    fn test_recursion_clusters() {
+        let (main, a, b, c, d) = (
+            item("main", 0),
+            item("a", 1),
+            item("b", 2),
+            item("c", 3),
+            item("d", 4),
+        );
+        // `a`, `b` and `c` call each other, `d` calls itself
+        let calls = vec![
+            call(&main, &a),
+            call(&a, &b),
+            call(&b, &c),
+            call(&c, &a),
+            call(&main, &d),
+            call(&d, &d),
+        ];
+        let graph = CallGraph::new(calls);
+
+        assert_eq!(graph.recursion_clusters(), [vec![&a, &b, &c], vec![&d]]);
+        assert!(CallGraph::new(vec![call(&main, &a)])
+            .recursion_clusters()
+            .is_empty());
+    }
+}
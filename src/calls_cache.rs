@@ -0,0 +1,90 @@
+use lsp_types::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::{CodeDepthError, FunctionCalls};
+
+/// version of the [`CallsCache`] format, caches of other versions are rejected
+pub const CALLS_CACHE_VERSION: u32 = 1;
+
+/// The calls a lang server found in a project, saved so later runs can skip
+/// querying the server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CallsCache {
+    pub version: u32,
+    /// the project the calls were found in
+    pub project_root: Url,
+    pub calls: FunctionCalls,
+}
+
+impl CallsCache {
+    pub fn new(calls: FunctionCalls, project_root: &Url) -> Self {
+        Self {
+            version: CALLS_CACHE_VERSION,
+            project_root: project_root.clone(),
+            calls,
+        }
+    }
+
+    /// The cached calls, if the cache is of the current version and was
+    /// written for `project_root`.
+    pub fn into_calls(self, project_root: &Url) -> Result<FunctionCalls, CodeDepthError> {
+        if self.version != CALLS_CACHE_VERSION {
+            return Err(CodeDepthError::InvalidCache(format!(
+                "cache version is {}, expected {}",
+                self.version, CALLS_CACHE_VERSION
+            )));
+        }
+
+        if &self.project_root != project_root {
+            return Err(CodeDepthError::InvalidCache(format!(
+                "cache is of project {}, not {}",
+                self.project_root, project_root
+            )));
+        }
+
+        Ok(self.calls)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{Position, Range, Url};
+
+    use super::{CallsCache, CALLS_CACHE_VERSION};
+    use crate::{test_util::item, Call, CodeDepthError, FunctionCalls};
+
+    #[test]
+    fn test_calls_cache() {
+        let root = Url::parse("file:///project").unwrap();
+        let (main, a) = (item("main", 0), item("a", 1));
+        let calls = FunctionCalls {
+            calls: vec![Call {
+                caller: main.clone(),
+                callee: a.clone(),
+                from_ranges: vec![Range::new(Position::new(0, 4), Position::new(0, 5))],
+            }],
+            incomplete: vec![a.clone()],
+            definitions: vec![main, a],
+        };
+
+        let json = serde_json::to_string(&CallsCache::new(calls.clone(), &root)).unwrap();
+        let cache = serde_json::from_str::<CallsCache>(&json).unwrap();
+        assert_eq!(cache.version, CALLS_CACHE_VERSION);
+        assert_eq!(cache.clone().into_calls(&root).unwrap(), calls);
+
+        let other_root = Url::parse("file:///other").unwrap();
+        assert!(matches!(
+            cache.clone().into_calls(&other_root),
+            Err(CodeDepthError::InvalidCache(_))
+        ));
+
+        let old_cache = CallsCache {
+            version: CALLS_CACHE_VERSION - 1,
+            ..cache
+        };
+        assert!(matches!(
+            old_cache.into_calls(&root),
+            Err(CodeDepthError::InvalidCache(_))
+        ));
+    }
+}
@@ -0,0 +1,111 @@
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+
+/// locations GitHub looks for a CODEOWNERS file in, relative to the project root
+pub const CODEOWNERS_LOCATIONS: &[&str] = &[".github/CODEOWNERS", "CODEOWNERS", "docs/CODEOWNERS"];
+
+/// Rules of a CODEOWNERS file, mapping path patterns to their owners.
+#[derive(Debug, Clone)]
+pub struct CodeOwners {
+    rules: Vec<(Regex, Vec<String>)>,
+}
+
+impl CodeOwners {
+    pub fn parse(contents: &str) -> Self {
+        let rules = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let mut parts = line.split_whitespace();
+                let pattern = pattern_to_regex(parts.next()?);
+                let owners = parts.map(str::to_string).collect();
+
+                Some((pattern, owners))
+            })
+            .collect();
+
+        Self { rules }
+    }
+
+    /// Find the CODEOWNERS file of the project, see [`CODEOWNERS_LOCATIONS`].
+    pub fn find(project_root: &Path) -> Option<PathBuf> {
+        CODEOWNERS_LOCATIONS
+            .iter()
+            .map(|location| project_root.join(location))
+            .find(|path| path.is_file())
+    }
+
+    /// Owners of a path relative to the project root, the last matching rule wins.
+    pub fn owners(&self, path: &str) -> &[String] {
+        let path = path.trim_start_matches('/');
+
+        self.rules
+            .iter()
+            .rev()
+            .find(|(pattern, _)| pattern.is_match(path))
+            .map_or(&[], |(_, owners)| owners)
+    }
+
+    pub fn is_owned_by(&self, path: &str, owner: &str) -> bool {
+        self.owners(path).iter().any(|o| o == owner)
+    }
+}
+
+/// Convert a gitignore style pattern to a regex matching the paths it covers,
+/// including all paths under a matched directory.
+fn pattern_to_regex(pattern: &str) -> Regex {
+    // patterns with a slash before their end are relative to the root,
+    // others match at any depth
+    let anchored = pattern.trim_end_matches('/').contains('/');
+    let dir_only = pattern.ends_with('/');
+    let pattern = pattern.trim_start_matches('/').trim_end_matches('/');
+
+    let mut re = String::from(if anchored { "^" } else { "^(.*/)?" });
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    re.push_str("(.*/)?");
+                } else {
+                    re.push_str(".*");
+                }
+            }
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push_str(if dir_only { "/.*$" } else { "(/.*)?$" });
+
+    Regex::new(&re).expect("escaped pattern is a valid regex")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CodeOwners;
+
+    #[test]
+    fn test_owners() {
+        let codeowners = CodeOwners::parse(
+            "
+            # default owners
+            *       @everyone
+            *.rs    @rust-team
+            /src/lsp/ @lsp-team @rust-team
+            docs/**/*.md @docs-team
+            ",
+        );
+
+        assert_eq!(codeowners.owners("README"), ["@everyone"]);
+        assert_eq!(codeowners.owners("/src/main.rs"), ["@rust-team"]);
+        assert!(codeowners.is_owned_by("src/lsp/json_rpc.rs", "@lsp-team"));
+        assert!(!codeowners.is_owned_by("tests/src/lsp/json_rpc.rs", "@lsp-team"));
+        assert!(codeowners.is_owned_by("docs/a/b/usage.md", "@docs-team"));
+        assert!(codeowners.is_owned_by("docs/usage.md", "@docs-team"));
+    }
+}
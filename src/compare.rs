@@ -0,0 +1,100 @@
+use std::collections::HashSet;
+
+use lsp_types::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::{hashable_call_hierarchy_item::HashableCallHierarchyItem, Call, NameFormat};
+
+/// Calls found by only one of two analyses of the same project.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallsDiff {
+    pub only_in_first: Vec<Call>,
+    pub only_in_second: Vec<Call>,
+}
+
+/// Diff two sets of calls by their (caller, callee) pairs, call sites are ignored.
+pub fn diff_calls(first: &[Call], second: &[Call]) -> CallsDiff {
+    CallsDiff {
+        only_in_first: missing_calls(first, second),
+        only_in_second: missing_calls(second, first),
+    }
+}
+
+fn missing_calls(calls: &[Call], other: &[Call]) -> Vec<Call> {
+    let other_edges = other.iter().map(to_edge).collect::<HashSet<_>>();
+
+    let mut seen = HashSet::new();
+    calls
+        .iter()
+        .filter(|call| {
+            let edge = to_edge(call);
+            !other_edges.contains(&edge) && seen.insert(edge)
+        })
+        .cloned()
+        .collect()
+}
+
+fn to_edge(call: &Call) -> (HashableCallHierarchyItem, HashableCallHierarchyItem) {
+    (call.caller.clone().into(), call.callee.clone().into())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ComparisonReport {
+    pub first_server: String,
+    pub second_server: String,
+    /// (caller, callee) names of calls only the first server found
+    pub only_in_first: Vec<(String, String)>,
+    /// (caller, callee) names of calls only the second server found
+    pub only_in_second: Vec<(String, String)>,
+}
+
+pub fn build_comparison_report(
+    diff: &CallsDiff,
+    first_server: &str,
+    second_server: &str,
+    root: &Url,
+    name_format: &NameFormat,
+) -> ComparisonReport {
+    let to_names = |calls: &[Call]| {
+        let mut names = calls
+            .iter()
+            .map(|call| {
+                (
+                    name_format.build(&call.caller, root),
+                    name_format.build(&call.callee, root),
+                )
+            })
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    };
+
+    ComparisonReport {
+        first_server: first_server.to_string(),
+        second_server: second_server.to_string(),
+        only_in_first: to_names(&diff.only_in_first),
+        only_in_second: to_names(&diff.only_in_second),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::Range;
+
+    use super::diff_calls;
+    use crate::test_util::{call, item};
+
+    #[test]
+    fn test_diff_calls() {
+        let (main, a, b) = (item("main", 0), item("a", 1), item("b", 2));
+        let first = vec![call(&main, &a), call(&a, &b)];
+        let mut second = vec![call(&main, &a), call(&main, &b), call(&main, &b)];
+        // the same edge from different call sites is still the same edge
+        second[0].from_ranges = vec![Range::default()];
+
+        let diff = diff_calls(&first, &second);
+
+        assert_eq!(diff.only_in_first, vec![call(&a, &b)]);
+        assert_eq!(diff.only_in_second, vec![call(&main, &b)]);
+    }
+}
@@ -0,0 +1,141 @@
+use std::path::{Path, PathBuf};
+
+use clap::{Arg, Command};
+use toml::{Table, Value};
+
+/// name of the config file looked for in the project root
+pub const CONFIG_FILE_NAME: &str = "code-depth.toml";
+
+/// The config file in `project_root`, if there is one.
+pub fn find(project_root: &Path) -> Option<PathBuf> {
+    Some(project_root.join(CONFIG_FILE_NAME)).filter(|path| path.is_file())
+}
+
+/// Convert the keys of a config file to the command line args of `command`
+/// they stand for, e.g. `max-depth = 3` becomes `--max-depth 3`.
+///
+/// Keys are the args' long names or field names (`max_depth`), arrays repeat
+/// the arg once per value, tables are passed as JSON, and `true` flags that
+/// take no value are passed bare (`false` ones are left out). Keys `command`
+/// has no long arg for are an error.
+pub fn to_cli_args(contents: &str, command: &Command) -> Result<Vec<String>, String> {
+    let table = contents.parse::<Table>().map_err(|e| e.to_string())?;
+
+    let mut args = vec![];
+    for (key, value) in &table {
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_id() == key.as_str() || arg.get_long() == Some(key.as_str()))
+            .filter(|arg| arg.get_long().is_some())
+            .ok_or_else(|| format!("unknown key '{}'", key))?;
+
+        let values = match value {
+            Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            push_arg(&mut args, arg, key, value)?;
+        }
+    }
+
+    Ok(args)
+}
+
+fn push_arg(args: &mut Vec<String>, arg: &Arg, key: &str, value: &Value) -> Result<(), String> {
+    let flag = format!(
+        "--{}",
+        arg.get_long().expect("only long args are looked up")
+    );
+    let value = match value {
+        Value::String(value) => value.clone(),
+        Value::Integer(value) => value.to_string(),
+        Value::Float(value) => value.to_string(),
+        Value::Boolean(value) if !arg.get_action().takes_values() => {
+            if *value {
+                args.push(flag);
+            }
+            return Ok(());
+        }
+        Value::Boolean(value) => value.to_string(),
+        Value::Table(_) => serde_json::to_string(value).map_err(|e| e.to_string())?,
+        _ => return Err(format!("unsupported value for key '{}': {}", key, value)),
+    };
+
+    args.push(flag);
+    args.push(value);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{Arg, ArgAction, Command};
+
+    use super::to_cli_args;
+
+    fn command() -> Command {
+        Command::new("code_depth")
+            .arg(Arg::new("max_depth").long("max-depth"))
+            .arg(
+                Arg::new("ignore_re")
+                    .long("ignore-re")
+                    .action(ArgAction::Append),
+            )
+            .arg(
+                Arg::new("no_ignore")
+                    .long("no-ignore")
+                    .action(ArgAction::SetTrue),
+            )
+            .arg(
+                Arg::new("with_stats")
+                    .long("with-stats")
+                    .action(ArgAction::SetTrue),
+            )
+            .arg(
+                Arg::new("fail_on_problems")
+                    .long("fail-on-problems")
+                    .action(ArgAction::Set),
+            )
+            .arg(Arg::new("init_options").long("init-options"))
+    }
+
+    #[test]
+    fn test_to_cli_args() {
+        let contents = r#"
+            max-depth = 3
+            ignore_re = ["tests/", "benches/"]
+            no-ignore = true
+            with_stats = false
+            fail-on-problems = false
+
+            [init-options]
+            cargo = { features = ["a"] }
+        "#;
+
+        let args = to_cli_args(contents, &command()).unwrap();
+
+        assert_eq!(
+            args,
+            [
+                "--fail-on-problems",
+                "false",
+                "--ignore-re",
+                "tests/",
+                "--ignore-re",
+                "benches/",
+                "--init-options",
+                r#"{"cargo":{"features":["a"]}}"#,
+                "--max-depth",
+                "3",
+                "--no-ignore",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_to_cli_args_unknown_key() {
+        let error = to_cli_args("max-dpeth = 3", &command()).unwrap_err();
+
+        assert_eq!(error, "unknown key 'max-dpeth'");
+    }
+}
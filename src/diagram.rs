@@ -0,0 +1,226 @@
+use std::collections::{BTreeMap, HashMap};
+
+use lsp_types::{CallHierarchyItem, Url};
+
+use crate::{
+    hashable_call_hierarchy_item::HashableCallHierarchyItem, report::AnalysisReport, Call, Depths,
+    NameFormat,
+};
+
+/// Functions and call hops of the paths in `depths`, keyed by their stable ids
+/// so the output doesn't depend on the order of the analysis.
+struct Graph {
+    /// label of each function and whether it's a problem
+    nodes: BTreeMap<String, (String, bool)>,
+    /// the number of call sites of each edge, see [`Call::num_call_sites`]
+    edges: BTreeMap<(String, String), usize>,
+}
+
+impl Graph {
+    fn new(
+        depths: &Depths<CallHierarchyItem>,
+        calls: &[Call],
+        report: &AnalysisReport,
+        root: &Url,
+        name_format: &NameFormat,
+    ) -> Self {
+        let to_id = |item: &CallHierarchyItem| HashableCallHierarchyItem(item.clone()).stable_id();
+        let problems = report.problem_items();
+
+        let mut call_sites = HashMap::<_, usize>::new();
+        for call in calls {
+            *call_sites
+                .entry((to_id(&call.caller), to_id(&call.callee)))
+                .or_default() += call.num_call_sites();
+        }
+
+        let mut nodes = BTreeMap::new();
+        let mut edges = BTreeMap::new();
+        for (item, paths) in depths {
+            let items = paths.iter().flatten().chain([item]);
+            for item in items {
+                nodes.entry(to_id(item)).or_insert_with(|| {
+                    let is_problem = problems.contains(item);
+                    (name_format.build(item, root), is_problem)
+                });
+            }
+
+            for path in paths {
+                for hop in path.windows(2) {
+                    let edge = (to_id(&hop[0]), to_id(&hop[1]));
+                    let num_call_sites = call_sites.get(&edge).copied().unwrap_or(1);
+                    edges.insert(edge, num_call_sites);
+                }
+            }
+        }
+
+        Self { nodes, edges }
+    }
+}
+
+/// Graphviz DOT digraph of the call paths in `depths`, with a node per function
+/// and an edge per call hop. The problems of `report` are red, and hops with
+/// several call sites in `calls` are labeled with their amount.
+pub fn to_dot(
+    depths: &Depths<CallHierarchyItem>,
+    calls: &[Call],
+    report: &AnalysisReport,
+    root: &Url,
+    name_format: &NameFormat,
+) -> String {
+    let graph = Graph::new(depths, calls, report, root, name_format);
+    let escape = |s: &str| s.replace('\\', "\\\\").replace('"', "\\\"");
+
+    let mut lines = vec!["digraph calls {".to_string()];
+    for (id, (label, is_problem)) in &graph.nodes {
+        let color = if *is_problem { ", color=red" } else { "" };
+        lines.push(format!(
+            "    \"{}\" [label=\"{}\"{}];",
+            id,
+            escape(label),
+            color
+        ));
+    }
+    for ((from, to), num_call_sites) in &graph.edges {
+        let label = match num_call_sites {
+            1 => String::new(),
+            n => format!(" [label=\"{}\"]", n),
+        };
+        lines.push(format!("    \"{}\" -> \"{}\"{};", from, to, label));
+    }
+    lines.push("}".to_string());
+
+    lines.join("\n")
+}
+
+/// Mermaid flowchart of the call paths in `depths`, like [`to_dot`]. The
+/// problems of `report` have the `problem` class.
+pub fn to_mermaid(
+    depths: &Depths<CallHierarchyItem>,
+    calls: &[Call],
+    report: &AnalysisReport,
+    root: &Url,
+    name_format: &NameFormat,
+) -> String {
+    let graph = Graph::new(depths, calls, report, root, name_format);
+    // mermaid ids can't contain most punctuation, and labels are quoted
+    let to_mermaid_id = |id: &str| format!("f{}", id);
+    let escape = |s: &str| s.replace('"', "#quot;");
+
+    let mut lines = vec![
+        "flowchart TD".to_string(),
+        "    classDef problem fill:#fdd,stroke:#d00".to_string(),
+    ];
+    for (id, (label, is_problem)) in &graph.nodes {
+        let class = if *is_problem { ":::problem" } else { "" };
+        lines.push(format!(
+            "    {}[\"{}\"]{}",
+            to_mermaid_id(id),
+            escape(label),
+            class
+        ));
+    }
+    for ((from, to), num_call_sites) in &graph.edges {
+        let label = match num_call_sites {
+            1 => String::new(),
+            n => format!("|{}|", n),
+        };
+        lines.push(format!(
+            "    {} -->{} {}",
+            to_mermaid_id(from),
+            label,
+            to_mermaid_id(to)
+        ));
+    }
+
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{CallHierarchyItem, Range, Url};
+
+    use regex::Regex;
+
+    use super::{to_dot, to_mermaid};
+    use crate::{
+        get_function_depths,
+        hashable_call_hierarchy_item::HashableCallHierarchyItem,
+        report::build_report,
+        test_util::{call, item},
+    };
+
+    #[test]
+    fn test_to_dot() {
+        let root = Url::parse("file:///project").unwrap();
+        let (r1, r2, y, x) = (item("r1", 0), item("r2", 1), item("y", 2), item("x", 3));
+        let mut calls = vec![call(&r1, &x), call(&r2, &y), call(&y, &x)];
+        calls[2].from_ranges = vec![Range::default(); 3];
+        let depths = get_function_depths(&calls);
+
+        let report = build_report(&calls, &depths, &root, &Default::default());
+
+        let dot = to_dot(&depths, &calls, &report, &root, &Default::default());
+
+        let id = |item: &CallHierarchyItem| HashableCallHierarchyItem(item.clone()).stable_id();
+        let lines = dot.lines().collect::<Vec<_>>();
+        assert_eq!(lines.first(), Some(&"digraph calls {"));
+        assert_eq!(lines.last(), Some(&"}"));
+        // 4 nodes, and the 3 edges once each even though `y -> x` is on a
+        // path of both `y` and `x`
+        assert_eq!(lines.len(), 2 + 4 + 3);
+        assert!(lines.contains(
+            &format!("    \"{}\" [label=\"/src/main.rs:x\", color=red];", id(&x)).as_str()
+        ));
+        assert!(lines.contains(&format!("    \"{}\" [label=\"/src/main.rs:y\"];", id(&y)).as_str()));
+        assert!(lines.contains(&format!("    \"{}\" -> \"{}\";", id(&r1), id(&x)).as_str()));
+        assert!(lines
+            .contains(&format!("    \"{}\" -> \"{}\" [label=\"3\"];", id(&y), id(&x)).as_str()));
+    }
+
+    #[test]
+    fn test_to_mermaid() {
+        let root = Url::parse("file:///project").unwrap();
+        let (r1, r2, x, y) = (item("r1", 0), item("r2", 1), item("x", 2), item("y", 3));
+        let mut calls = vec![call(&r1, &y), call(&r2, &x), call(&x, &y)];
+        calls[2].from_ranges = vec![Range::default(); 2];
+        let depths = get_function_depths(&calls);
+
+        let report = build_report(&calls, &depths, &root, &Default::default());
+
+        let mermaid = to_mermaid(&depths, &calls, &report, &root, &Default::default());
+
+        let mut lines = mermaid.lines();
+        assert_eq!(lines.next(), Some("flowchart TD"));
+        assert!(lines.next().unwrap().contains("classDef problem"));
+
+        let node_re = Regex::new(r#"^    (\w+)\["([^"]*)"\](:::problem)?$"#).unwrap();
+        let edge_re = Regex::new(r"^    (\w+) -->(\|\d+\|)? (\w+)$").unwrap();
+        let id_re = Regex::new("^[A-Za-z][A-Za-z0-9_]*$").unwrap();
+        let (mut nodes, mut edges, mut labels) = (vec![], 0, vec![]);
+        for line in lines {
+            if let Some(node) = node_re.captures(line) {
+                assert!(id_re.is_match(&node[1]), "invalid id in: {}", line);
+                nodes.push((node[2].to_string(), node.get(3).is_some()));
+            } else {
+                let edge = edge_re.captures(line).expect(line);
+                assert!(id_re.is_match(&edge[1]) && id_re.is_match(&edge[3]));
+                edges += 1;
+                labels.extend(edge.get(2).map(|label| label.as_str().to_string()));
+            }
+        }
+
+        nodes.sort();
+        assert_eq!(
+            nodes,
+            [
+                ("/src/main.rs:r1".to_string(), false),
+                ("/src/main.rs:r2".to_string(), false),
+                ("/src/main.rs:x".to_string(), false),
+                ("/src/main.rs:y".to_string(), true),
+            ]
+        );
+        assert_eq!(edges, 3);
+        assert_eq!(labels, ["|2|"]);
+    }
+}
@@ -0,0 +1,105 @@
+use std::{fmt::Display, time::Duration};
+
+use lsp_types::CallHierarchyItem;
+
+use crate::lsp::json_rpc::LspError;
+
+#[derive(Debug, Clone)]
+pub enum CodeDepthError {
+    /// the server responded to a request with an error
+    Lsp(LspError),
+    /// the server process exited before responding
+    ServerExited { status: String, stderr: String },
+    /// the connection to the server failed or was closed
+    Transport(String),
+    /// the server didn't respond to a request in time
+    Timeout { method: String, timeout: Duration },
+    /// the server doesn't support methods the analysis requires
+    MissingCapability { methods: Vec<String> },
+    /// the server responded with a result that isn't valid for its request
+    UnexpectedResponse { method: String, response: String },
+    /// the server was still indexing the project after waiting `waited`
+    ServerIndexing { waited: Duration },
+    /// reading the project's files failed
+    Io(String),
+    /// the analysis was given options it can't run with
+    InvalidOptions(String),
+    /// a calls cache can't be used, e.g. it was written for another project
+    InvalidCache(String),
+    /// a call path is deeper than the allowed max depth, `path` is the
+    /// offending path from its root
+    MaxDepthExceeded {
+        max_depth: usize,
+        path: Vec<CallHierarchyItem>,
+    },
+}
+
+impl Display for CodeDepthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CodeDepthError::Lsp(e) => write!(f, "lsp server error {}: {}", e.code, e.message),
+            CodeDepthError::ServerExited { status, stderr } => {
+                write!(f, "lsp server exited with {}", status)?;
+                if !stderr.is_empty() {
+                    write!(f, ", stderr:\n{}", stderr)?;
+                }
+
+                Ok(())
+            }
+            CodeDepthError::Transport(e) => write!(f, "lsp server connection failed: {}", e),
+            CodeDepthError::Timeout { method, timeout } => {
+                write!(
+                    f,
+                    "lsp server didn't respond to {} in {:?}",
+                    method, timeout
+                )
+            }
+            CodeDepthError::MissingCapability { methods } => {
+                write!(
+                    f,
+                    "lsp server doesn't support required methods: {}",
+                    methods.join(", ")
+                )
+            }
+            CodeDepthError::UnexpectedResponse { method, response } => {
+                write!(
+                    f,
+                    "lsp server sent an unexpected response to {}: {}",
+                    method, response
+                )
+            }
+            CodeDepthError::ServerIndexing { waited } => {
+                write!(f, "lsp server was still indexing after {:?}", waited)
+            }
+            CodeDepthError::Io(e) => write!(f, "failed to read project files: {}", e),
+            CodeDepthError::InvalidOptions(e) => write!(f, "invalid options: {}", e),
+            CodeDepthError::InvalidCache(e) => write!(f, "invalid calls cache: {}", e),
+            CodeDepthError::MaxDepthExceeded { max_depth, path } => {
+                let names = path
+                    .iter()
+                    .map(|item| item.name.as_str())
+                    .collect::<Vec<_>>();
+                write!(
+                    f,
+                    "call path exceeds max depth {}, this usually means a cycle or a misconfiguration: {}",
+                    max_depth,
+                    names.join(" -> ")
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for CodeDepthError {}
+
+impl From<LspError> for CodeDepthError {
+    fn from(e: LspError) -> Self {
+        Self::Lsp(e)
+    }
+}
+
+impl From<std::io::Error> for CodeDepthError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e.to_string())
+    }
+}
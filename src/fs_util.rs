@@ -0,0 +1,129 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+use futures::{stream, StreamExt};
+use ignore::WalkBuilder;
+
+/// Read all files, with at most `concurrency` reads in flight at once.
+pub async fn read_files(
+    paths: Vec<PathBuf>,
+    concurrency: usize,
+) -> HashMap<PathBuf, io::Result<String>> {
+    stream::iter(paths)
+        .map(|path| async move {
+            let text = tokio::fs::read_to_string(&path).await;
+            (path, text)
+        })
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await
+}
+
+/// Find all files under `root` recursively. Symlinks are not followed.
+///
+/// With `respect_ignore`, files excluded by `.gitignore`, `.ignore` and the
+/// global git excludes are skipped, hidden files are skipped unless
+/// `include_hidden`.
+pub fn find_files(
+    root: &Path,
+    respect_ignore: bool,
+    include_hidden: bool,
+) -> io::Result<Vec<PathBuf>> {
+    let walker = WalkBuilder::new(root)
+        .hidden(!include_hidden)
+        .ignore(respect_ignore)
+        .git_ignore(respect_ignore)
+        .git_global(respect_ignore)
+        .git_exclude(respect_ignore)
+        .parents(respect_ignore)
+        // projects that aren't a git repository still have their ignore files
+        .require_git(false)
+        .build();
+
+    let mut files = vec![];
+    for entry in walker {
+        let entry = entry.map_err(io::Error::other)?;
+        if entry
+            .file_type()
+            .is_some_and(|file_type| file_type.is_file())
+        {
+            files.push(entry.into_path());
+        }
+    }
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_files, read_files};
+
+    #[tokio::test]
+    async fn test_read_files() {
+        let dir = std::env::temp_dir();
+        let paths = (0..5)
+            .map(|i| {
+                let path = dir.join(format!("code_depth_test_read_files_{}.txt", i));
+                std::fs::write(&path, format!("file {}", i)).unwrap();
+                path
+            })
+            .collect::<Vec<_>>();
+        let missing = dir.join("code_depth_test_read_files_missing.txt");
+
+        let mut to_read = paths.clone();
+        to_read.push(missing.clone());
+
+        let files = read_files(to_read, 2).await;
+
+        assert_eq!(files.len(), 6);
+        for (i, path) in paths.iter().enumerate() {
+            assert_eq!(files[path].as_ref().unwrap(), &format!("file {}", i));
+        }
+        assert!(files[&missing].is_err());
+    }
+
+    #[test]
+    fn test_find_files() {
+        let root = std::env::temp_dir().join("code_depth_test_find_files");
+        let _ = std::fs::remove_dir_all(&root);
+        for dir in ["src/nested", ".git", "target"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in [
+            "main.rs",
+            "src/lib.rs",
+            "src/nested/mod.rs",
+            ".hidden.rs",
+            ".git/HEAD",
+            "target/build.rs",
+        ] {
+            std::fs::write(root.join(file), "").unwrap();
+        }
+        std::fs::write(root.join(".gitignore"), "target/\n").unwrap();
+
+        let find = |respect_ignore, include_hidden| {
+            let mut files = find_files(&root, respect_ignore, include_hidden).unwrap();
+            files.sort();
+            files
+        };
+
+        assert_eq!(
+            find(true, false),
+            ["main.rs", "src/lib.rs", "src/nested/mod.rs"].map(|file| root.join(file))
+        );
+        assert_eq!(
+            find(false, false),
+            [
+                "main.rs",
+                "src/lib.rs",
+                "src/nested/mod.rs",
+                "target/build.rs"
+            ]
+            .map(|file| root.join(file))
+        );
+        assert!(find(true, true).contains(&root.join(".hidden.rs")));
+    }
+}
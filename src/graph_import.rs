@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+
+use lsp_types::{CallHierarchyItem, Position, Range, SymbolKind, Url};
+use serde::{Deserialize, Serialize};
+
+use crate::{Call, CodeDepthError, NameFormat};
+
+/// A call graph made by another tool, analyzed instead of the calls a lang
+/// server finds.
+///
+/// Functions are identified by their short names (`src/db.rs:execute`), or
+/// by any other string without the name separator (`execute`), e.g.
+/// `{"edges": [["src/main.rs:main", "src/db.rs:execute"]]}`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImportedGraph {
+    /// calls from the first function to the second one
+    pub edges: Vec<(String, String)>,
+}
+
+impl ImportedGraph {
+    /// The edges as calls between items in the project at `root`, with the
+    /// same short names as the identifiers.
+    pub fn into_calls(
+        self,
+        root: &Url,
+        name_format: &NameFormat,
+    ) -> Result<Vec<Call>, CodeDepthError> {
+        let mut items = HashMap::<String, CallHierarchyItem>::new();
+        let mut to_item = |id: String| -> Result<CallHierarchyItem, CodeDepthError> {
+            // items are identified by their location, so each function gets
+            // its own line
+            let line = items.len() as u32;
+            if let Some(item) = items.get(&id) {
+                return Ok(item.clone());
+            }
+
+            let item = build_item(&id, line, root, name_format)?;
+            items.insert(id, item.clone());
+
+            Ok(item)
+        };
+
+        self.edges
+            .into_iter()
+            .map(|(caller, callee)| {
+                Ok(Call {
+                    caller: to_item(caller)?,
+                    callee: to_item(callee)?,
+                    from_ranges: vec![],
+                })
+            })
+            .collect()
+    }
+}
+
+fn build_item(
+    id: &str,
+    line: u32,
+    root: &Url,
+    name_format: &NameFormat,
+) -> Result<CallHierarchyItem, CodeDepthError> {
+    let (uri, name) = match name_format.split(id) {
+        Some((path, name)) => {
+            let uri = format!(
+                "{}/{}",
+                root.as_str().trim_end_matches('/'),
+                path.trim_start_matches('/')
+            );
+            let uri = Url::parse(&uri).map_err(|e| {
+                CodeDepthError::InvalidOptions(format!("invalid path in '{}': {}", id, e))
+            })?;
+
+            (uri, name)
+        }
+        None => (root.clone(), id),
+    };
+    let range = Range::new(Position::new(line, 0), Position::new(line, 0));
+
+    Ok(CallHierarchyItem {
+        name: name.to_string(),
+        kind: SymbolKind::FUNCTION,
+        tags: None,
+        detail: None,
+        uri,
+        range,
+        selection_range: range,
+        data: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::Url;
+
+    use super::ImportedGraph;
+    use crate::{get_function_depths, NameFormat};
+
+    #[test]
+    fn test_imported_graph() {
+        let root = Url::parse("file:///project").unwrap();
+        let name_format = NameFormat {
+            separator: ":".to_string(),
+            style: Default::default(),
+        };
+        let graph: ImportedGraph = serde_json::from_str(
+            r#"{"edges": [
+                ["src/main.rs:main", "src/db.rs:execute"],
+                ["src/main.rs:main", "helper"],
+                ["helper", "src/db.rs:execute"]
+            ]}"#,
+        )
+        .unwrap();
+
+        let calls = graph.into_calls(&root, &name_format).unwrap();
+
+        let names = calls
+            .iter()
+            .map(|call| {
+                (
+                    name_format.build(&call.caller, &root),
+                    name_format.build(&call.callee, &root),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                (
+                    "/src/main.rs:main".to_string(),
+                    "/src/db.rs:execute".to_string()
+                ),
+                ("/src/main.rs:main".to_string(), ":helper".to_string()),
+                (":helper".to_string(), "/src/db.rs:execute".to_string()),
+            ]
+        );
+
+        let mut depths = get_function_depths(&calls)
+            .into_iter()
+            .map(|(item, paths)| (item.name, paths.len()))
+            .collect::<Vec<_>>();
+        depths.sort();
+        assert_eq!(
+            depths,
+            [
+                ("execute".to_string(), 1),
+                ("helper".to_string(), 1),
+                ("main".to_string(), 1)
+            ]
+        );
+
+        let unknown_field = serde_json::from_str::<ImportedGraph>(r#"{"edges": [], "nodes": []}"#);
+        assert!(unknown_field.is_err());
+    }
+}
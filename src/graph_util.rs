@@ -4,16 +4,139 @@ use std::{
     hash::Hash,
 };
 
-use petgraph::Graph;
+use petgraph::{algo::tarjan_scc, graph::NodeIndex, Graph};
 
 type ItemPathFromRoot<T> = (T, Vec<T>);
-pub fn get_depths<T>(edges: &Vec<(T, T)>) -> Vec<(T, Vec<ItemPathFromRoot<T>>)>
+pub type RootPaths<T> = (T, Vec<ItemPathFromRoot<T>>);
+
+/// How deep [`get_limited_depths_from`] follows paths.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DepthLimit {
+    /// stop and return the first path deeper than this
+    pub abort: Option<usize>,
+    /// stop expanding paths this deep, so the traversal never goes deeper
+    pub truncate: Option<usize>,
+}
+
+/// The paths of [`get_limited_depths_from`], and the nodes they stopped at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LimitedDepths<T: Hash + Eq> {
+    pub paths: Vec<RootPaths<T>>,
+    /// nodes whose neighbors weren't expanded because their path reached
+    /// [`DepthLimit::truncate`]
+    pub truncated: HashSet<T>,
+    /// nodes whose path to a neighbor they share a cycle with ended there,
+    /// because the neighbor was already reached, instead of following the cycle
+    pub cycle_ends: HashSet<T>,
+}
+
+/// the paths from a single root, and the nodes they were truncated and ended
+/// a cycle at
+type LimitedRootPaths<T> = (Vec<ItemPathFromRoot<T>>, HashSet<T>, HashSet<T>);
+
+/// Get the path from each root to each node reachable from it.
+///
+/// Each node is expanded once per root, along its shortest path, so cycles
+/// end the traversal instead of being followed again. See
+/// [`LimitedDepths::cycle_ends`] for where they ended, and [`find_cycles`] for
+/// the cycles themselves.
+///
+/// If `max_depth` is given and any path is deeper than it, stop and return the
+/// offending path instead.
+pub fn get_depths<T>(
+    edges: &Vec<(T, T)>,
+    max_depth: Option<usize>,
+) -> Result<Vec<RootPaths<T>>, Vec<T>>
 where
     T: Clone + Hash + Eq + Debug,
 {
-    // find all roots and execute a bfs from each one to get depths
-    // of each node from each root
-    let targets = edges.iter().map(|e| e.1.clone()).collect::<HashSet<_>>();
+    get_depths_from(edges, find_roots(edges), max_depth)
+}
+
+/// Like [`get_depths`], but from the given `roots` instead of the nodes
+/// without incoming edges. Each root must be a node of `edges`.
+pub fn get_depths_from<'a, T>(
+    edges: &Vec<(T, T)>,
+    roots: impl IntoIterator<Item = &'a T>,
+    max_depth: Option<usize>,
+) -> Result<Vec<RootPaths<T>>, Vec<T>>
+where
+    T: 'a + Clone + Hash + Eq + Debug,
+{
+    let limit = DepthLimit {
+        abort: max_depth,
+        truncate: None,
+    };
+
+    Ok(get_limited_depths_from(edges, roots, limit)?.paths)
+}
+
+/// Like [`get_depths_from`], but paths reaching `limit.truncate` aren't
+/// expanded further, so the traversal is bounded even in huge graphs. Nodes
+/// only reachable by deeper paths get no paths.
+pub fn get_limited_depths_from<'a, T>(
+    edges: &Vec<(T, T)>,
+    roots: impl IntoIterator<Item = &'a T>,
+    limit: DepthLimit,
+) -> Result<LimitedDepths<T>, Vec<T>>
+where
+    T: 'a + Clone + Hash + Eq + Debug,
+{
+    let mut truncated = HashSet::new();
+    let mut cycle_ends = HashSet::new();
+
+    // execute a bfs from each root to get depths of each node from each root
+    let paths = roots
+        .into_iter()
+        .map(|r| {
+            let (paths, root_truncated, root_cycle_ends) = get_root_paths(r, edges, limit)?;
+            truncated.extend(root_truncated);
+            cycle_ends.extend(root_cycle_ends);
+
+            Ok((r.clone(), paths))
+        })
+        .collect::<Result<_, Vec<T>>>()?;
+
+    Ok(LimitedDepths {
+        paths,
+        truncated,
+        cycle_ends,
+    })
+}
+
+/// Find all nodes reachable from `roots`, including the roots.
+pub fn find_reachable<'a, T>(
+    edges: &Vec<(T, T)>,
+    roots: impl IntoIterator<Item = &'a T>,
+) -> HashSet<T>
+where
+    T: 'a + Clone + Hash + Eq + Debug,
+{
+    let (graph, to_graph_node) = build_graph(edges);
+
+    let mut stack = roots
+        .into_iter()
+        .filter_map(|root| to_graph_node.get(root).copied())
+        .collect::<Vec<_>>();
+    let mut visited = HashSet::new();
+    while let Some(node) = stack.pop() {
+        if visited.insert(node) {
+            stack.extend(graph.neighbors(node));
+        }
+    }
+
+    visited
+        .into_iter()
+        .map(|node| graph.node_weight(node).unwrap().clone())
+        .collect()
+}
+
+/// Find all nodes that have outgoing edges but no incoming edges.
+pub fn find_roots<T>(edges: &[(T, T)]) -> HashSet<&T>
+where
+    T: Hash + Eq,
+{
+    let targets = edges.iter().map(|e| &e.1).collect::<HashSet<_>>();
     let mut roots = HashSet::new();
     for (s, _) in edges {
         if !targets.contains(s) {
@@ -22,16 +145,58 @@ where
     }
 
     roots
-        .iter()
-        .map(|&r| (r.clone(), get_root_paths(r, edges)))
+}
+
+/// Find all groups of nodes that (possibly indirectly) call each other,
+/// including single nodes that call themselves.
+pub fn find_cycles<T>(edges: &Vec<(T, T)>) -> Vec<Vec<T>>
+where
+    T: Clone + Hash + Eq + Debug,
+{
+    let (graph, _) = build_graph(edges);
+
+    tarjan_scc(&graph)
+        .into_iter()
+        .filter(|scc| scc.len() > 1 || graph.contains_edge(scc[0], scc[0]))
+        .map(|scc| {
+            scc.iter()
+                .map(|n| graph.node_weight(*n).unwrap().clone())
+                .collect()
+        })
         .collect()
 }
 
-fn get_root_paths<T>(root: &T, edges: &Vec<(T, T)>) -> Vec<(T, Vec<T>)>
+/// Find the minimum amount of edges needed to get from `source` to each
+/// reachable node.
+pub fn get_distances<T>(edges: &Vec<(T, T)>, source: &T) -> HashMap<T, usize>
+where
+    T: Clone + Hash + Eq + Debug,
+{
+    let (graph, to_graph_node) = build_graph(edges);
+
+    let mut distances = HashMap::new();
+    let Some(&source_node) = to_graph_node.get(source) else {
+        return distances;
+    };
+
+    let mut bfs = petgraph::visit::Bfs::new(&graph, source_node);
+    let mut node_distances = HashMap::from([(source_node, 0)]);
+    while let Some(node) = bfs.next(&graph) {
+        let distance = node_distances[&node];
+        for neighbor in graph.neighbors(node) {
+            node_distances.entry(neighbor).or_insert(distance + 1);
+        }
+
+        distances.insert(graph.node_weight(node).unwrap().clone(), distance);
+    }
+
+    distances
+}
+
+fn build_graph<T>(edges: &Vec<(T, T)>) -> (Graph<T, ()>, HashMap<&T, NodeIndex>)
 where
     T: Clone + Hash + Eq + Debug,
 {
-    // build graph
     let mut to_graph_node = HashMap::new();
     let mut graph: Graph<T, ()> = Graph::new();
 
@@ -51,19 +216,63 @@ where
         );
     }
 
+    (graph, to_graph_node)
+}
+
+fn get_root_paths<T>(
+    root: &T,
+    edges: &Vec<(T, T)>,
+    limit: DepthLimit,
+) -> Result<LimitedRootPaths<T>, Vec<T>>
+where
+    T: Clone + Hash + Eq + Debug,
+{
+    let (graph, to_graph_node) = build_graph(edges);
+
     // run bfs
     let mut graph_depths = vec![];
 
     let mut paths = vec![vec![*to_graph_node.get(root).unwrap()]];
     let mut visited = HashSet::new();
+    // the heads paths stopped at, with the neighbors they weren't expanded to
+    let mut stopped = vec![];
+    // the heads whose already visited neighbor they share a cycle with, which
+    // would have been followed around it
+    let mut cycle_ends = HashSet::new();
+    let components = tarjan_scc(&graph)
+        .into_iter()
+        .enumerate()
+        .flat_map(|(component, nodes)| nodes.into_iter().map(move |node| (node, component)))
+        .collect::<HashMap<_, _>>();
     while !paths.is_empty() {
         let mut new_paths = vec![];
         for path in paths {
             let path_head = *path.last().unwrap();
-            if !visited.contains(&path_head) {
+            if visited.contains(&path_head) {
+                if let [.., caller, _] = path[..] {
+                    if components[&caller] == components[&path_head] {
+                        cycle_ends.insert(caller);
+                    }
+                }
+            } else {
+                if limit
+                    .abort
+                    .is_some_and(|max_depth| path.len() - 1 > max_depth)
+                {
+                    return Err(path
+                        .iter()
+                        .map(|p| graph.node_weight(*p).unwrap().clone())
+                        .collect());
+                }
+
                 graph_depths.push((path_head, path.clone()));
                 visited.insert(path_head);
 
+                if limit.truncate == Some(path.len() - 1) {
+                    stopped.extend(graph.neighbors(path_head).map(|n| (path_head, n)));
+                    continue;
+                }
+
                 for neighbor in graph.neighbors(path_head) {
                     let mut new_path = path.clone();
                     new_path.push(neighbor);
@@ -75,8 +284,21 @@ where
         paths = new_paths;
     }
 
+    // only neighbors that no path reached are cut off, paths to visited ones
+    // would've ended at them anyway
+    let truncated = stopped
+        .into_iter()
+        .filter(|(_, neighbor)| !visited.contains(neighbor))
+        .map(|(head, _)| graph.node_weight(head).unwrap().clone())
+        .collect();
+
+    let cycle_ends = cycle_ends
+        .into_iter()
+        .map(|head| graph.node_weight(head).unwrap().clone())
+        .collect();
+
     // convert graph nodes to real nodes
-    graph_depths
+    let paths = graph_depths
         .iter()
         .map(|(n, d)| {
             (
@@ -86,17 +308,26 @@ where
                     .collect::<Vec<_>>(),
             )
         })
-        .collect::<Vec<_>>()
+        .collect::<Vec<_>>();
+
+    Ok((paths, truncated, cycle_ends))
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::get_depths;
+    use std::collections::HashMap;
+
+    use std::collections::HashSet;
+
+    use super::{
+        find_cycles, find_reachable, find_roots, get_depths, get_depths_from, get_distances,
+        get_limited_depths_from, DepthLimit, LimitedDepths,
+    };
 
     #[test]
     fn test_get_depths() {
         assert_eq!(
-            get_depths(&(vec![(0, 1), (1, 2), (2, 3)])),
+            get_depths(&(vec![(0, 1), (1, 2), (2, 3)]), None).unwrap(),
             vec![(
                 0,
                 vec![
@@ -111,14 +342,18 @@ mod tests {
 
     #[test]
     fn test_get_depths_2_roots() {
-        let depths = get_depths(&vec![
-            (0, 1), // root 1
-            (1, 2),
-            (2, 3),
-            (10, 11), // root 2
-            (11, 12),
-            (12, 13),
-        ]);
+        let depths = get_depths(
+            &vec![
+                (0, 1), // root 1
+                (1, 2),
+                (2, 3),
+                (10, 11), // root 2
+                (11, 12),
+                (12, 13),
+            ],
+            None,
+        )
+        .unwrap();
 
         assert!(depths.contains(&(
             0,
@@ -140,16 +375,141 @@ mod tests {
         )));
     }
 
+    #[test]
+    fn test_get_depths_from() {
+        // 1 has a caller, but still is the only root
+        assert_eq!(
+            get_depths_from(&(vec![(0, 1), (1, 2), (2, 3)]), &[1], None).unwrap(),
+            vec![(1, vec![(1, vec![1]), (2, vec![1, 2]), (3, vec![1, 2, 3])])]
+        );
+    }
+
     #[test]
     fn test_get_depths_loop() {
         assert_eq!(
-            get_depths(&(vec![(0, 1), (0, 2), (1, 2), (2, 1)])),
+            get_depths(&(vec![(0, 1), (0, 2), (1, 2), (2, 1)]), None).unwrap(),
             vec![(0, vec![(0, vec![0]), (2, vec![0, 2]), (1, vec![0, 1]),])]
         );
     }
 
+    #[test]
+    fn test_get_depths_direct_cycle() {
+        assert_eq!(
+            get_depths(&(vec![(0, 1), (1, 2), (2, 1)]), None).unwrap(),
+            vec![(0, vec![(0, vec![0]), (1, vec![0, 1]), (2, vec![0, 1, 2])])]
+        );
+    }
+
+    #[test]
+    fn test_get_depths_three_node_cycle() {
+        assert_eq!(
+            get_depths(&(vec![(0, 1), (1, 2), (2, 3), (3, 1)]), Some(3)).unwrap(),
+            vec![(
+                0,
+                vec![
+                    (0, vec![0]),
+                    (1, vec![0, 1]),
+                    (2, vec![0, 1, 2]),
+                    (3, vec![0, 1, 2, 3]),
+                ]
+            )]
+        );
+    }
+
     #[test]
     fn test_top_level_loop() {
-        assert_eq!(get_depths(&(vec![(0, 1), (1, 0)])), vec![]);
+        assert_eq!(get_depths(&(vec![(0, 1), (1, 0)]), None).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_get_depths_max_depth() {
+        let edges = vec![(0, 1), (1, 2), (2, 3)];
+
+        assert!(get_depths(&edges, Some(3)).is_ok());
+        assert_eq!(get_depths(&edges, Some(2)), Err(vec![0, 1, 2, 3]));
+    }
+
+    #[test]
+    fn test_get_limited_depths_from() {
+        // 2 is also reached directly from 0, 3 only through 2
+        let edges = vec![(0, 1), (1, 2), (2, 3), (0, 2)];
+        let limit = DepthLimit {
+            abort: None,
+            truncate: Some(1),
+        };
+
+        let LimitedDepths {
+            paths, truncated, ..
+        } = get_limited_depths_from(&edges, &[0], limit).unwrap();
+        assert_eq!(
+            paths,
+            vec![(0, vec![(0, vec![0]), (2, vec![0, 2]), (1, vec![0, 1])])]
+        );
+        // 1's neighbor 2 was reached by another path, so only 2 was cut
+        assert_eq!(truncated, HashSet::from([2]));
+        assert_eq!(find_reachable(&edges, &[0]), HashSet::from([0, 1, 2, 3]));
+
+        // aborting still applies to the paths followed
+        let limit = DepthLimit {
+            abort: Some(0),
+            truncate: Some(1),
+        };
+        assert_eq!(
+            get_limited_depths_from(&edges, &[0], limit),
+            Err(vec![0, 2])
+        );
+    }
+
+    #[test]
+    fn test_get_limited_depths_cycle_ends() {
+        let cycle_ends = |edges: Vec<(i32, i32)>| {
+            get_limited_depths_from(&edges, &[0], DepthLimit::default())
+                .unwrap()
+                .cycle_ends
+        };
+
+        // the call closing a cycle ends the path
+        assert_eq!(cycle_ends(vec![(0, 1), (1, 2), (2, 1)]), HashSet::from([2]));
+        assert_eq!(
+            cycle_ends(vec![(0, 1), (1, 2), (2, 3), (3, 1)]),
+            HashSet::from([3])
+        );
+        assert_eq!(cycle_ends(vec![(0, 1), (1, 1)]), HashSet::from([1]));
+        // entering a cycle from both sides ends a path at each
+        assert_eq!(
+            cycle_ends(vec![(0, 1), (0, 2), (1, 2), (2, 1)]),
+            HashSet::from([1, 2])
+        );
+        // a node reached again by another path isn't a cycle
+        assert!(cycle_ends(vec![(0, 1), (0, 2), (1, 3), (2, 3)]).is_empty());
+    }
+
+    #[test]
+    fn test_find_cycles() {
+        let mut cycles = find_cycles(&vec![(0, 1), (1, 2), (2, 1), (2, 3), (3, 3)]);
+        cycles.iter_mut().for_each(|cycle| cycle.sort());
+        cycles.sort();
+
+        assert_eq!(cycles, vec![vec![1, 2], vec![3]]);
+    }
+
+    #[test]
+    fn test_find_roots() {
+        let edges = vec![(0, 1), (1, 2), (3, 2), (4, 5), (5, 4)];
+        let mut roots = find_roots(&edges).into_iter().copied().collect::<Vec<_>>();
+        roots.sort();
+
+        assert_eq!(roots, vec![0, 3]);
+    }
+
+    #[test]
+    fn test_get_distances() {
+        let edges = vec![(0, 1), (1, 2), (0, 2), (2, 3), (3, 0), (4, 0)];
+
+        assert_eq!(
+            get_distances(&edges, &0),
+            HashMap::from([(0, 0), (1, 1), (2, 1), (3, 2)])
+        );
+        assert_eq!(get_distances(&edges, &5), HashMap::new());
     }
 }
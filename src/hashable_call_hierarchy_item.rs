@@ -6,6 +6,33 @@ use lsp_types::CallHierarchyItem;
 #[derive(Clone)]
 pub struct HashableCallHierarchyItem(pub CallHierarchyItem);
 
+impl HashableCallHierarchyItem {
+    /// Short id derived from the same fields that identify the item when hashing,
+    /// unlike `Hash` this is guaranteed to stay the same across runs and builds.
+    pub fn stable_id(&self) -> String {
+        // 64 bit FNV-1a
+        let mut hash: u64 = 0xcbf29ce484222325;
+        let mut update = |bytes: &[u8]| {
+            for byte in bytes {
+                hash ^= *byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        };
+
+        update(self.0.uri.as_str().as_bytes());
+        for n in [
+            self.0.range.start.line,
+            self.0.range.start.character,
+            self.0.range.end.line,
+            self.0.range.end.character,
+        ] {
+            update(&n.to_le_bytes());
+        }
+
+        format!("{:016x}", hash)
+    }
+}
+
 impl std::fmt::Debug for HashableCallHierarchyItem {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_fmt(format_args!("HashableCallHierarchyItem({})", self.0.name))
@@ -49,3 +76,21 @@ impl From<HashableCallHierarchyItem> for CallHierarchyItem {
         hashable_call_hierarchy_item.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::HashableCallHierarchyItem;
+    use crate::test_util::item;
+
+    #[test]
+    fn test_stable_id() {
+        let a = HashableCallHierarchyItem(item("a", 1));
+
+        assert_eq!(a.stable_id(), a.stable_id());
+        assert_eq!(a.stable_id(), "13f9be2ebe3cc456");
+        assert_ne!(
+            a.stable_id(),
+            HashableCallHierarchyItem(item("a", 2)).stable_id()
+        );
+    }
+}
@@ -1,27 +1,147 @@
+pub mod baseline;
+pub mod call_graph;
+pub mod calls_cache;
+pub mod codeowners;
+pub mod compare;
+pub mod config_file;
+pub mod diagram;
+pub mod error;
+mod fs_util;
+pub mod graph_import;
 mod graph_util;
 pub mod hashable_call_hierarchy_item;
 pub mod lsp;
+pub mod overrides;
+pub mod preset;
+pub mod project_root;
+pub mod reachability;
+pub mod render;
+pub mod report;
+pub mod sarif;
+pub mod snapshot;
+pub mod snippets;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+#[cfg(test)]
+pub(crate) mod test_util;
 
 use std::{
     collections::{HashMap, HashSet},
-    error::Error,
     hash::Hash,
     time::Duration,
 };
 
-use log::debug;
+use clap::ValueEnum;
+use globset::GlobSet;
+use log::{debug, warn};
 use lsp_types::{
-    CallHierarchyItem, ClientCapabilities, DocumentSymbolClientCapabilities, InitializeParams,
-    InitializeResult, SymbolKind, TextDocumentClientCapabilities, Url,
+    request::{
+        CallHierarchyIncomingCalls, CallHierarchyOutgoingCalls, CallHierarchyPrepare, Request,
+    },
+    CallHierarchyIncomingCallsParams, CallHierarchyItem, CallHierarchyOutgoingCallsParams,
+    CallHierarchyPrepareParams, ClientCapabilities, DocumentSymbolClientCapabilities,
+    FileChangeType, FileEvent, InitializeParams, InitializeResult, Range, SymbolInformation,
+    SymbolKind, TextDocumentClientCapabilities, TextDocumentIdentifier, TextDocumentPositionParams,
+    Url, WindowClientCapabilities, WorkspaceClientCapabilities, WorkspaceFolder,
 };
+use serde::{Deserialize, Serialize};
 
-use graph_util::get_depths;
+use graph_util::{find_roots, get_depths, DepthLimit, LimitedDepths, RootPaths};
 use hashable_call_hierarchy_item::HashableCallHierarchyItem;
 use lsp::{json_rpc::LspError, LspClient};
+use project_root::{is_in_project, is_in_workspace, relative_path};
 
-pub async fn init(client: &mut LspClient, root_uri: Url) -> Result<InitializeResult, LspError> {
+pub use call_graph::CallGraph;
+pub use error::CodeDepthError;
+
+/// Direction the call graph is built in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, ValueEnum)]
+pub enum CallDirection {
+    /// from the callers of each definition, see [`get_function_calls`]
+    #[default]
+    #[value(alias = "callers")]
+    Incoming,
+    /// from the callees of each definition, see [`get_function_callees`]
+    #[value(alias = "callees")]
+    Outgoing,
+}
+
+impl CallDirection {
+    /// The call hierarchy request building the graph in this direction.
+    pub fn method(&self) -> &'static str {
+        match self {
+            CallDirection::Incoming => CallHierarchyIncomingCalls::METHOD,
+            CallDirection::Outgoing => CallHierarchyOutgoingCalls::METHOD,
+        }
+    }
+}
+
+/// Initialize the server, and check it supports the requests the analysis
+/// sends, returning [`CodeDepthError::MissingCapability`] if it doesn't.
+///
+/// The `initialized` notification is sent right after the server responds,
+/// before any other request, see [`LspClient::initialize`].
+pub async fn init(
+    client: &mut LspClient,
+    root_uri: Url,
+) -> Result<InitializeResult, CodeDepthError> {
+    init_for_directions(client, root_uri, &[CallDirection::Incoming]).await
+}
+
+/// Like [`init`], but check support for building the call graph in each of
+/// `directions` instead of only from incoming calls.
+pub async fn init_for_directions(
+    client: &mut LspClient,
+    root_uri: Url,
+    directions: &[CallDirection],
+) -> Result<InitializeResult, CodeDepthError> {
+    init_with_options(client, root_uri, directions, &InitOptions::default()).await
+}
+
+/// The last segment of the folder's path, e.g. `api` for `file:///repo/api`.
+fn workspace_folder_name(folder: &Url) -> String {
+    folder
+        .path_segments()
+        .and_then(|mut segments| segments.rfind(|segment| !segment.is_empty()))
+        .map_or_else(|| folder.to_string(), |name| name.to_string())
+}
+
+/// How the lang server is initialized, see [`init_with_options`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InitOptions {
+    /// server specific settings, e.g. rust-analyzer's `cargo` settings, sent
+    /// as the `initializationOptions`. Some servers don't index correctly
+    /// without them
+    pub initialization_options: Option<serde_json::Value>,
+    /// the folders of the project the server should treat as separate
+    /// workspaces, e.g. the packages of a monorepo. The root is the only
+    /// folder when empty
+    pub workspace_folders: Vec<Url>,
+}
+
+/// Like [`init_for_directions`], initializing the server with `options`.
+pub async fn init_with_options(
+    client: &mut LspClient,
+    root_uri: Url,
+    directions: &[CallDirection],
+    options: &InitOptions,
+) -> Result<InitializeResult, CodeDepthError> {
+    let workspace_folders = match options.workspace_folders.as_slice() {
+        [] => vec![root_uri.clone()],
+        folders => folders.to_vec(),
+    };
     let params = InitializeParams {
         root_uri: Some(root_uri),
+        workspace_folders: Some(
+            workspace_folders
+                .into_iter()
+                .map(|uri| WorkspaceFolder {
+                    name: workspace_folder_name(&uri),
+                    uri,
+                })
+                .collect(),
+        ),
+        initialization_options: options.initialization_options.clone(),
         capabilities: ClientCapabilities {
             text_document: Some(TextDocumentClientCapabilities {
                 document_symbol: Some(DocumentSymbolClientCapabilities {
@@ -30,95 +150,195 @@ pub async fn init(client: &mut LspClient, root_uri: Url) -> Result<InitializeRes
                 }),
                 ..Default::default()
             }),
+            // lets servers report their indexing, see `get_workspace_files`
+            window: Some(WindowClientCapabilities {
+                work_done_progress: Some(true),
+                ..Default::default()
+            }),
+            workspace: Some(WorkspaceClientCapabilities {
+                workspace_folders: Some(true),
+                ..Default::default()
+            }),
             ..Default::default()
         },
         ..Default::default()
     };
 
-    let result = client.initialize(&params).await;
+    let result = client.initialize(&params).await?;
 
     // make sure server has the desired capabilities
-    if let Ok(result) = &result {
-        let mut required_methods = HashSet::new();
-
-        for required_method in [
-            "workspace/symbol",
-            "textDocument/documentSymbol",
-            "callHierarchy/incomingCalls",
-        ] {
-            required_methods.insert(required_method);
-        }
+    let mut required_methods = HashSet::new();
 
-        let mut supported_methods = HashSet::new();
+    for required_method in ["workspace/symbol", "textDocument/documentSymbol"] {
+        required_methods.insert(required_method);
+    }
 
-        if match &result.capabilities.workspace_symbol_provider {
-            Some(provider) => match provider {
-                lsp_types::OneOf::Left(enabled) => *enabled,
-                lsp_types::OneOf::Right(_) => true,
-            },
-            None => false,
-        } {
-            supported_methods.insert("workspace/symbol");
-        }
+    for direction in directions {
+        required_methods.insert(direction.method());
+    }
 
-        if match &result.capabilities.document_symbol_provider {
-            Some(provider) => match provider {
-                lsp_types::OneOf::Left(enabled) => *enabled,
-                lsp_types::OneOf::Right(_) => true,
-            },
-            None => false,
-        } {
-            supported_methods.insert("textDocument/documentSymbol");
-        }
+    let mut supported_methods = HashSet::new();
 
-        if match &result.capabilities.call_hierarchy_provider {
-            Some(provider) => match provider {
-                lsp_types::CallHierarchyServerCapability::Simple(enabled) => *enabled,
-                lsp_types::CallHierarchyServerCapability::Options(_) => true,
-            },
-            None => false,
-        } {
-            supported_methods.insert("callHierarchy/incomingCalls");
+    if match &result.capabilities.workspace_symbol_provider {
+        Some(provider) => match provider {
+            lsp_types::OneOf::Left(enabled) => *enabled,
+            lsp_types::OneOf::Right(_) => true,
+        },
+        None => false,
+    } {
+        supported_methods.insert("workspace/symbol");
+    }
+
+    if match &result.capabilities.document_symbol_provider {
+        Some(provider) => match provider {
+            lsp_types::OneOf::Left(enabled) => *enabled,
+            lsp_types::OneOf::Right(_) => true,
+        },
+        None => false,
+    } {
+        supported_methods.insert("textDocument/documentSymbol");
+    }
+
+    if match &result.capabilities.call_hierarchy_provider {
+        Some(provider) => match provider {
+            lsp_types::CallHierarchyServerCapability::Simple(enabled) => *enabled,
+            lsp_types::CallHierarchyServerCapability::Options(_) => true,
+        },
+        None => false,
+    } {
+        // the capability covers the requests of both directions
+        for direction in [CallDirection::Incoming, CallDirection::Outgoing] {
+            if required_methods.contains(direction.method()) {
+                supported_methods.insert(direction.method());
+            }
         }
+    }
 
-        assert_eq!(
-            required_methods,
-            supported_methods,
-            "missing support for required methods {:?}",
-            required_methods.difference(&supported_methods)
-        );
+    let mut missing_methods = required_methods
+        .difference(&supported_methods)
+        .map(|method| method.to_string())
+        .collect::<Vec<_>>();
+    if !missing_methods.is_empty() {
+        missing_methods.sort();
+        return Err(CodeDepthError::MissingCapability {
+            methods: missing_methods,
+        });
+    }
+
+    Ok(result)
+}
+
+/// time to wait for a server to start new work after its reported work ended,
+/// or to start reporting progress at all
+const PROGRESS_GRACE_PERIOD: Duration = Duration::from_secs(1);
+
+/// lsp error code for a request invalidated by a change in the server's state
+const CONTENT_MODIFIED: isize = -32801;
+/// lsp error code for a request the server cancelled, e.g. because it was busy
+const SERVER_CANCELLED: isize = -32802;
+
+/// A `workspace/symbol` query used to find the workspace files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SymbolQuery {
+    /// a fixed query, e.g. `#` which makes rust-analyzer report definitions
+    Fixed(String),
+    /// the empty query, which many servers answer with all their symbols
+    Empty,
+    /// a query for each letter, for servers capping each response
+    Alphabet,
+}
+
+/// Parse a symbol query: `empty`, `alphabet`, or any other fixed query.
+pub fn parse_symbol_query(query: &str) -> Result<SymbolQuery, String> {
+    match query {
+        "" => Err("empty symbol query, use 'empty' for the empty query".to_string()),
+        "empty" => Ok(SymbolQuery::Empty),
+        "alphabet" => Ok(SymbolQuery::Alphabet),
+        query => Ok(SymbolQuery::Fixed(query.to_string())),
+    }
+}
+
+/// The queries sent to find the workspace files, the files of the symbols of
+/// all queries are combined.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolQueryStrategy(pub Vec<SymbolQuery>);
+
+impl Default for SymbolQueryStrategy {
+    fn default() -> Self {
+        Self(vec![
+            SymbolQuery::Fixed("#".to_string()),
+            SymbolQuery::Empty,
+            SymbolQuery::Alphabet,
+        ])
     }
+}
 
-    result
+impl SymbolQueryStrategy {
+    /// Each query string to send, in order.
+    pub fn query_strings(&self) -> Vec<String> {
+        self.0
+            .iter()
+            .flat_map(|query| match query {
+                SymbolQuery::Fixed(query) => vec![query.clone()],
+                SymbolQuery::Empty => vec![String::new()],
+                SymbolQuery::Alphabet => ('a'..='z').map(String::from).collect(),
+            })
+            .collect()
+    }
 }
 
+/// Find the workspace files from the symbols the server reports for each
+/// query of `strategy`.
+///
+/// The first query is retried while the server is still indexing, the others
+/// are sent once and their errors ignored. `max_duration` is the budget of
+/// the whole wait for the indexing, after which
+/// [`CodeDepthError::ServerIndexing`] is returned.
 pub async fn get_workspace_files(
     client: &mut lsp::LspClient,
     project_root: &Url,
+    strategy: &SymbolQueryStrategy,
     max_duration: Duration,
-) -> Result<HashSet<Url>, Box<dyn Error>> {
-    let retry_sleep_duration = 100;
-    let retry_amount = max_duration.as_millis() / retry_sleep_duration;
-    let mut retries_left = retry_amount;
-
-    // for rust-analyzer we need to append '#' to get function definitions
-    // this might not be good for all LSP servers
-    // TODO: add option to set query string by lsp server, and maybe this is the default?
-    let mut result = client.workspace_symbol("#").await;
-
-    // wait for server to index project
-    // TODO: add 'lsp-server-ready' check instead of this hack
+) -> Result<HashSet<Url>, CodeDepthError> {
+    let queries = strategy.query_strings();
+    let Some((first_query, other_queries)) = queries.split_first() else {
+        return Err(CodeDepthError::InvalidOptions(
+            "no workspace symbol queries to find the workspace files with".to_string(),
+        ));
+    };
+
+    let retry_sleep_duration = Duration::from_millis(100);
+    let deadline = tokio::time::Instant::now() + max_duration;
+
+    // wait for the server to index the project, if it reports its progress
+    if client
+        .wait_for_progress(PROGRESS_GRACE_PERIOD, max_duration)
+        .await?
+    {
+        debug!("waited for the progress the server reported");
+    }
+
+    let mut result = client.workspace_symbol(first_query).await;
+
+    // servers not reporting their progress might still be indexing, retry
+    // until they stop saying so
     while let Err(e) = result {
         // make sure the error just means the server is still indexing
-        assert_eq!(e.code, -32801, "got unexpected error from lsp server");
-        retries_left -= 1;
-        if retries_left == 0 {
-            return Err(format!("max retries exceeded: {:?}", e).into());
+        match &e {
+            CodeDepthError::Lsp(lsp_error) if lsp_error.code == CONTENT_MODIFIED => {}
+            _ => return Err(e),
+        }
+
+        let now = tokio::time::Instant::now();
+        if now >= deadline {
+            return Err(CodeDepthError::ServerIndexing {
+                waited: max_duration,
+            });
         }
 
-        std::thread::sleep(Duration::from_millis(retry_sleep_duration as u64));
+        tokio::time::sleep_until(deadline.min(now + retry_sleep_duration)).await;
 
-        result = client.workspace_symbol("#").await;
+        result = client.workspace_symbol(first_query).await;
     }
 
     let mut symbols = vec![];
@@ -126,16 +346,8 @@ pub async fn get_workspace_files(
         symbols.append(result);
     }
 
-    // try empty query strategy
-    let mut result = client.workspace_symbol("").await;
-
-    if let Ok(Some(result)) = &mut result {
-        symbols.append(result);
-    }
-
-    // try letter by letter strategy
-    for letter in "abcdefghijklmnopqrstuvwxyz".chars() {
-        let mut result = client.workspace_symbol(&letter.to_string()).await;
+    for query in other_queries {
+        let mut result = client.workspace_symbol(query).await;
 
         if let Ok(Some(result)) = &mut result {
             symbols.append(result);
@@ -144,10 +356,9 @@ pub async fn get_workspace_files(
 
     let mut workspace_files = HashSet::new();
 
-    let project_root_str = project_root.as_str();
     for symbol in symbols {
         let symbol_file = symbol.location.uri;
-        if symbol_file.as_str().starts_with(project_root_str) {
+        if is_in_project(&symbol_file, project_root) {
             workspace_files.insert(symbol_file);
         }
     }
@@ -155,188 +366,2068 @@ pub async fn get_workspace_files(
     Ok(workspace_files)
 }
 
-pub async fn get_function_calls(
+/// How the files to analyze are found.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum DiscoveryMode {
+    /// files of the symbols the server finds, see [`get_workspace_files`]
+    #[default]
+    WorkspaceSymbol,
+    /// files under the project root, see [`scan_workspace_files`]
+    Filesystem,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DiscoveryOptions {
+    pub mode: DiscoveryMode,
+    /// extensions of the files to analyze, e.g. `rs`, required when scanning
+    /// the filesystem
+    pub extensions: Option<Vec<String>>,
+    /// only analyze files matching these globs, all files when empty
+    pub include: GlobSet,
+    /// don't analyze files matching these globs, or under a directory matching
+    /// them, even when included
+    pub exclude: GlobSet,
+    /// also scan the files ignored by `.gitignore` and `.ignore` files
+    pub no_ignore: bool,
+    /// queries finding the files of [`DiscoveryMode::WorkspaceSymbol`]
+    pub symbol_query: SymbolQueryStrategy,
+    /// only analyze files in these folders of the project, all of its files
+    /// when empty
+    pub workspace_folders: Vec<Url>,
+}
+
+/// Find the files to analyze, see [`DiscoveryMode`].
+pub async fn discover_workspace_files(
     client: &mut LspClient,
-    workspace_files: &HashSet<Url>,
     project_root: &Url,
-) -> Result<Vec<(CallHierarchyItem, CallHierarchyItem)>, Box<dyn Error>> {
-    // get exact location of each definition's name
-    let mut exact_definitions = vec![];
+    options: &DiscoveryOptions,
+    max_duration: Duration,
+) -> Result<HashSet<Url>, CodeDepthError> {
+    let files = match options.mode {
+        DiscoveryMode::WorkspaceSymbol => {
+            let files =
+                get_workspace_files(client, project_root, &options.symbol_query, max_duration)
+                    .await?;
 
-    for file in workspace_files.iter() {
-        // get file symbols
-        let result = client.document_symbol(file.clone()).await.unwrap().unwrap();
+            match &options.extensions {
+                Some(extensions) => filter_files_by_suffix(files, extensions),
+                None => files,
+            }
+        }
+        DiscoveryMode::Filesystem => {
+            // the files are known, but the server still has to index them
+            client
+                .wait_for_progress(PROGRESS_GRACE_PERIOD, max_duration)
+                .await?;
 
-        match result {
-            // we need DocumentSymbol for the precise location of the function name
-            lsp_types::DocumentSymbolResponse::Flat(_) => return Err("got flat".into()),
-            lsp_types::DocumentSymbolResponse::Nested(symbols) => {
-                update_exact_definitions(symbols, file, &mut exact_definitions);
+            // the folders may share a far away ancestor, only scan them
+            match options.workspace_folders.as_slice() {
+                [] => scan_workspace_files(project_root, options)?,
+                folders => folders
+                    .iter()
+                    .map(|folder| scan_workspace_files(folder, options))
+                    .collect::<Result<Vec<_>, _>>()?
+                    .into_iter()
+                    .flatten()
+                    .collect(),
+            }
+        }
+    };
+
+    let files = files
+        .into_iter()
+        .filter(|file| is_in_workspace(file, project_root, &options.workspace_folders))
+        .collect();
+
+    Ok(filter_files_by_globs(
+        files,
+        project_root,
+        &options.include,
+        &options.exclude,
+    ))
+}
+
+/// Find the files under the project root with one of the analyzed extensions,
+/// without asking the server.
+///
+/// Ignored files are skipped unless [`DiscoveryOptions::no_ignore`], hidden
+/// files are skipped unless an include glob matches them.
+pub fn scan_workspace_files(
+    project_root: &Url,
+    options: &DiscoveryOptions,
+) -> Result<HashSet<Url>, CodeDepthError> {
+    let Some(extensions) = &options.extensions else {
+        return Err(CodeDepthError::InvalidOptions(
+            "scanning the filesystem requires file extensions".to_string(),
+        ));
+    };
+    let root = project_root.to_file_path().map_err(|_| {
+        CodeDepthError::InvalidOptions(format!("project root {} is not a local path", project_root))
+    })?;
+
+    let include_hidden = !options.include.is_empty();
+    let files = fs_util::find_files(&root, !options.no_ignore, include_hidden)?
+        .into_iter()
+        .filter(|path| {
+            let path = path.strip_prefix(&root).unwrap_or(path);
+            let hidden = path
+                .components()
+                .any(|component| component.as_os_str().to_string_lossy().starts_with('.'));
+
+            !hidden || options.include.is_match(path)
+        })
+        .filter_map(|path| Url::from_file_path(path).ok())
+        .collect();
+
+    Ok(filter_files_by_suffix(files, extensions))
+}
+
+/// Keep only the files with one of the given extensions, e.g. `rs`.
+pub fn filter_files_by_suffix<S: AsRef<str>>(files: HashSet<Url>, suffixes: &[S]) -> HashSet<Url> {
+    files
+        .into_iter()
+        .filter(|file| {
+            suffixes.iter().any(|suffix| {
+                let suffix = suffix.as_ref().trim_start_matches('.');
+                file.path()
+                    .rsplit_once('.')
+                    .is_some_and(|(_, extension)| extension == suffix)
+            })
+        })
+        .collect()
+}
+
+/// Keep only the files matching `include` (all of them when it's empty) and not
+/// matching `exclude`, both are matched against paths relative to the project
+/// root. A file under a directory matching `exclude` is excluded too.
+pub fn filter_files_by_globs(
+    files: HashSet<Url>,
+    project_root: &Url,
+    include: &GlobSet,
+    exclude: &GlobSet,
+) -> HashSet<Url> {
+    let root = project_root.to_file_path().unwrap_or_default();
+
+    files
+        .into_iter()
+        .filter(|file| {
+            let Ok(path) = file.to_file_path() else {
+                return include.is_empty();
+            };
+            let path = path.strip_prefix(&root).unwrap_or(&path);
+
+            (include.is_empty() || include.is_match(path))
+                && !path.ancestors().any(|path| exclude.is_match(path))
+        })
+        .collect()
+}
+
+/// Open each file on the server with its full text, lazy servers only analyze
+/// open files. Files already open are skipped, files that can't be read are
+/// logged and skipped.
+pub async fn open_workspace_files(
+    client: &mut LspClient,
+    files: &HashSet<Url>,
+    io_concurrency: usize,
+) {
+    let paths = files
+        .iter()
+        .filter(|file| !client.is_open(file))
+        .filter_map(|file| match file.to_file_path() {
+            Ok(path) => Some((path, file)),
+            Err(()) => {
+                debug!("skipping opening non file url {}", file);
+                None
             }
+        })
+        .collect::<HashMap<_, _>>();
+
+    let texts = fs_util::read_files(paths.keys().cloned().collect(), io_concurrency).await;
+
+    // open in a stable order, so runs send the same messages
+    let mut texts = texts.into_iter().collect::<Vec<_>>();
+    texts.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    for (path, text) in texts {
+        let file = paths[&path];
+        match text {
+            Ok(text) => client.did_open(file.clone(), language_id(file), text).await,
+            Err(e) => warn!("failed to read {}, not opening it: {}", path.display(), e),
         }
     }
+}
 
-    let mut calls = vec![];
-    for (file, definition) in exact_definitions {
-        // get definition call hierarchy item
-        let target_item = lsp_types::CallHierarchyItem {
-            name: definition.name,
-            kind: definition.kind,
-            tags: definition.tags,
-            detail: definition.detail,
-            uri: file,
-            range: definition.range,
-            selection_range: definition.selection_range,
-            data: None,
-        };
+/// Tell the server each of `files` changed on disk: open files get their new
+/// text, the others are reported as changed, or deleted when they can't be
+/// read anymore.
+pub async fn notify_changed_files(
+    client: &mut LspClient,
+    files: &HashSet<Url>,
+    io_concurrency: usize,
+) {
+    let paths = files
+        .iter()
+        .filter_map(|file| file.to_file_path().ok().map(|path| (path, file)))
+        .collect::<HashMap<_, _>>();
 
-        let result = client
-            .call_hierarchy_incoming_calls(target_item.clone())
-            .await;
+    let texts = fs_util::read_files(paths.keys().cloned().collect(), io_concurrency).await;
 
-        match result {
-            Ok(Some(response)) => {
-                for source_item in response {
-                    // filter out calls from outside our project
-                    if source_item
-                        .from
-                        .uri
-                        .as_str()
-                        .starts_with(project_root.as_str())
-                    {
-                        calls.push((source_item.from, target_item.clone()));
-                    }
-                }
+    let mut texts = texts.into_iter().collect::<Vec<_>>();
+    texts.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let mut events = vec![];
+    for (path, text) in texts {
+        let file = paths[&path].clone();
+        let typ = match text {
+            Ok(text) if client.is_open(&file) => {
+                client.did_change(file, text).await;
+                continue;
             }
-            Ok(None) => {}
+            Ok(_) => FileChangeType::CHANGED,
             Err(e) => {
                 debug!(
-                    "got jsonRpcError for {:?}: {:?} {:?}",
-                    (
-                        &target_item
-                            .uri
-                            .as_str()
-                            .trim_start_matches(project_root.as_str()),
-                        &target_item.name,
-                        &target_item.selection_range.start
-                    ),
-                    e.code,
-                    e.message
+                    "can't read changed file {}, assuming it was deleted: {}",
+                    path.display(),
+                    e
                 );
+                FileChangeType::DELETED
             }
-        }
+        };
+        events.push(FileEvent::new(file, typ));
     }
 
-    Ok(calls)
+    if !events.is_empty() {
+        client.did_change_watched_files(events).await;
+    }
 }
 
-fn update_exact_definitions(
-    symbols: Vec<lsp_types::DocumentSymbol>,
-    file: &Url,
-    exact_definitions: &mut Vec<(Url, lsp_types::DocumentSymbol)>,
-) {
-    for symbol in symbols {
-        if symbol.kind == SymbolKind::FUNCTION || symbol.kind == SymbolKind::METHOD {
-            exact_definitions.push((file.to_owned(), symbol.clone()));
-        }
+/// LSP language identifier of a file by its extension.
+fn language_id(file: &Url) -> &'static str {
+    let extension = file
+        .path()
+        .rsplit_once('.')
+        .map_or("", |(_, extension)| extension);
 
-        if let Some(children) = symbol.children {
-            update_exact_definitions(children, file, exact_definitions);
-        }
+    match extension {
+        "rs" => "rust",
+        "java" => "java",
+        "c" => "c",
+        "cc" | "cpp" | "cxx" | "h" | "hh" | "hpp" | "hxx" => "cpp",
+        "go" => "go",
+        "py" => "python",
+        "js" => "javascript",
+        "jsx" => "javascriptreact",
+        "ts" => "typescript",
+        "tsx" => "typescriptreact",
+        "kt" => "kotlin",
+        "scala" => "scala",
+        "cs" => "csharp",
+        "rb" => "ruby",
+        "swift" => "swift",
+        _ => "plaintext",
     }
 }
 
-pub fn get_function_depths(
-    calls: Vec<(CallHierarchyItem, CallHierarchyItem)>,
-) -> Vec<(CallHierarchyItem, Vec<Vec<CallHierarchyItem>>)> {
-    // convert call items into hashable call items
-    let hashable_calls = calls
-        .iter()
-        .map(|(s, t)| (s.clone().into(), t.clone().into()))
-        .collect::<Vec<(HashableCallHierarchyItem, HashableCallHierarchyItem)>>();
+/// A call from `caller` to `callee`, `from_ranges` are the call sites inside `caller`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Call {
+    pub caller: CallHierarchyItem,
+    pub callee: CallHierarchyItem,
+    pub from_ranges: Vec<Range>,
+}
+
+impl Call {
+    /// The number of times `caller` calls `callee`. Calls without call sites
+    /// (e.g. imported ones, see [`graph_import`]) are counted once.
+    pub fn num_call_sites(&self) -> usize {
+        self.from_ranges.len().max(1)
+    }
+}
 
-    let depths_by_root = get_depths(&hashable_calls);
+/// separator qualifying function names with their containers by default, e.g.
+/// `Cache.get`
+pub const DEFAULT_QUALIFIED_NAME_SEPARATOR: &str = ".";
 
-    // get item paths from each root
-    let mut item_paths_from_roots = HashMap::new();
-    for (_, items) in depths_by_root {
-        for (item, item_path) in items {
-            let item_path_from_root: &mut Vec<Vec<CallHierarchyItem>> =
-                item_paths_from_roots.entry(item).or_default();
+pub struct FunctionCallsOptions {
+    /// drop edges where the caller and the callee are the same definition
+    pub drop_self_loops: bool,
+    /// document symbol kinds that count as definitions
+    pub symbol_kinds: Vec<SymbolKind>,
+    /// qualify definition names with the names of their containing symbols,
+    /// e.g. `Class::Class` with `"::"`, so same-named methods of different
+    /// classes in a file get different names. `None` keeps the server's names
+    pub qualified_name_separator: Option<String>,
+    pub retry: RetryOptions,
+    /// only analyze calls to these functions, by their short name
+    /// (`src/main.rs:foo`) or just the function name (`foo`)
+    pub functions: Option<HashSet<String>>,
+    /// format of the short names in `functions`
+    pub name_format: NameFormat,
+    /// max amount of files read from disk at once
+    pub io_concurrency: usize,
+    /// max amount of document symbol requests waiting for the server at once
+    pub concurrency: usize,
+    /// drop calls from and to functions outside these folders of the
+    /// project, like the calls of functions outside the project when empty
+    pub workspace_folders: Vec<Url>,
+}
 
-            let mut converted_item_path: Vec<CallHierarchyItem> = vec![];
-            for hop in item_path {
-                converted_item_path.push(hop.into());
-            }
+/// Retries of requests the server cancelled or invalidated, which servers
+/// under load do to individual requests that would succeed when sent again.
+#[derive(Debug, Clone)]
+pub struct RetryOptions {
+    pub max_retries: u32,
+    /// delay before the first retry, doubled on each following retry
+    pub backoff: Duration,
+}
 
-            item_path_from_root.push(converted_item_path);
+impl Default for RetryOptions {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            backoff: Duration::from_millis(100),
         }
     }
-
-    item_paths_from_roots
-        .into_iter()
-        .map(|(k, v)| (k.into(), v))
-        .collect()
 }
 
-pub fn build_short_fn_depths(
-    root: &Url,
-    depths: &Vec<(CallHierarchyItem, Vec<Vec<CallHierarchyItem>>)>,
-) -> Depths<String> {
-    let mut short_item_depths = vec![];
+impl RetryOptions {
+    fn should_retry(&self, error: &CodeDepthError, attempt: u32) -> bool {
+        let transient = matches!(
+            error,
+            CodeDepthError::Lsp(e) if e.code == CONTENT_MODIFIED || e.code == SERVER_CANCELLED
+        );
 
-    for (item, paths_from_roots) in depths {
-        let item_name = build_call_hierarchy_item_name(item, root);
+        transient && attempt < self.max_retries
+    }
 
-        let mut short_paths = vec![];
-        for path in paths_from_roots {
-            let mut short_path = vec![];
-            for hop in path {
-                short_path.push(build_call_hierarchy_item_name(hop, root));
-            }
+    fn delay(&self, attempt: u32) -> Duration {
+        self.backoff.saturating_mul(2u32.saturating_pow(attempt))
+    }
+}
 
-            short_paths.push(short_path);
+impl Default for FunctionCallsOptions {
+    fn default() -> Self {
+        Self {
+            drop_self_loops: true,
+            symbol_kinds: vec![SymbolKind::FUNCTION, SymbolKind::METHOD],
+            qualified_name_separator: None,
+            retry: RetryOptions::default(),
+            functions: None,
+            name_format: NameFormat::default(),
+            io_concurrency: 16,
+            concurrency: 8,
+            workspace_folders: vec![],
         }
+    }
+}
 
-        short_item_depths.push((item_name, short_paths));
+/// Calls found in the project, see [`collect_function_calls`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FunctionCalls {
+    pub calls: Vec<Call>,
+    /// definitions the server failed to report the callers of, so their
+    /// callers might be missing from `calls`
+    pub incomplete: Vec<CallHierarchyItem>,
+    /// every definition analyzed, including the ones without any calls
+    pub definitions: Vec<CallHierarchyItem>,
+}
+
+/// Merge the calls found by several servers, e.g. each analyzing the files of
+/// a different language, into a single graph.
+impl FromIterator<FunctionCalls> for FunctionCalls {
+    fn from_iter<I: IntoIterator<Item = FunctionCalls>>(iter: I) -> Self {
+        iter.into_iter()
+            .fold(FunctionCalls::default(), |mut merged, calls| {
+                merged.calls.extend(calls.calls);
+                merged.incomplete.extend(calls.incomplete);
+                merged.definitions.extend(calls.definitions);
+                merged
+            })
     }
+}
 
-    short_item_depths
+pub async fn get_function_calls(
+    client: &mut LspClient,
+    workspace_files: &HashSet<Url>,
+    project_root: &Url,
+    options: &FunctionCallsOptions,
+) -> Result<Vec<Call>, CodeDepthError> {
+    Ok(
+        collect_function_calls(client, workspace_files, project_root, options)
+            .await?
+            .calls,
+    )
 }
 
-pub fn build_call_hierarchy_item_name(item: &CallHierarchyItem, root: &Url) -> String {
-    format!(
-        "{}:{}",
-        item.uri.as_str().trim_start_matches(root.as_str()),
-        item.name.split('(').next().unwrap()
+/// Like [`get_function_calls`], but also reports which definitions are
+/// incomplete.
+pub async fn collect_function_calls(
+    client: &mut LspClient,
+    workspace_files: &HashSet<Url>,
+    project_root: &Url,
+    options: &FunctionCallsOptions,
+) -> Result<FunctionCalls, CodeDepthError> {
+    let target_items = get_definitions(client, workspace_files, options).await?;
+
+    // callers are reported by the server with its own naming, use our definition
+    // items instead so each function is named the same way as a caller and a callee
+    let definitions = to_definitions_by_item(&target_items);
+
+    let target_items = target_items
+        .into_iter()
+        .filter(|item| is_analyzed_function(item, project_root, options))
+        .collect::<Vec<_>>();
+
+    let mut calls = vec![];
+    let mut incomplete = vec![];
+    for target_item in target_items.iter().cloned() {
+        let params = CallHierarchyIncomingCallsParams {
+            item: prepare_item(client, &target_item, &options.retry).await?,
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let result =
+            call_with_retry::<CallHierarchyIncomingCalls>(client, &params, &options.retry).await;
+
+        match result {
+            Ok(Some(response)) => {
+                for source_item in response {
+                    // filter out calls from outside our project
+                    if !is_in_workspace(
+                        &source_item.from.uri,
+                        project_root,
+                        &options.workspace_folders,
+                    ) {
+                        continue;
+                    }
+
+                    // servers sometimes report a function calling itself due to how
+                    // overloads or generics resolve, these edges only pollute the graph
+                    if options.drop_self_loops && is_self_loop(&source_item.from, &target_item) {
+                        continue;
+                    }
+
+                    let caller = definitions
+                        .get(&source_item.from.clone().into())
+                        .cloned()
+                        .unwrap_or(source_item.from);
+
+                    calls.push(Call {
+                        caller,
+                        callee: target_item.clone(),
+                        from_ranges: source_item.from_ranges,
+                    });
+                }
+            }
+            Ok(None) => {}
+            Err(CodeDepthError::Lsp(e)) => {
+                log_request_error(&target_item, project_root, &e);
+                incomplete.push(target_item);
+            }
+            // a single stuck request shouldn't fail the whole analysis
+            Err(e @ CodeDepthError::Timeout { .. }) => {
+                debug!("skipping {}: {}", target_item.name, e);
+                incomplete.push(target_item);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(FunctionCalls {
+        calls,
+        incomplete,
+        definitions: target_items,
+    })
+}
+
+/// Like [`get_function_calls`], but built from the calls each definition makes
+/// instead of the calls each definition receives, see
+/// [`CallDirection::Outgoing`].
+///
+/// With complete server data both directions produce the same graph.
+pub async fn get_function_callees(
+    client: &mut LspClient,
+    workspace_files: &HashSet<Url>,
+    project_root: &Url,
+    options: &FunctionCallsOptions,
+) -> Result<Vec<Call>, CodeDepthError> {
+    Ok(
+        collect_function_callees(client, workspace_files, project_root, options)
+            .await?
+            .calls,
     )
 }
 
-pub type Depths<T> = Vec<(T, Vec<Vec<T>>)>;
-pub fn find_items_with_different_depths<T, H>(depths: &Depths<T>) -> HashSet<H>
-where
-    T: PartialEq + Into<H> + Clone,
-    H: Hash + Eq,
-{
-    depths
-        .iter()
-        .filter(|(item, item_paths_from_roots)| {
-            let total_unique_depths = item_paths_from_roots
-                .iter()
-                .map(|path| path.len())
-                .collect::<HashSet<_>>()
-                .len();
-
-            let mut all_hops: HashSet<H> = HashSet::new();
-            let paths_are_unique = item_paths_from_roots.iter().all(|path| {
-                path.iter().filter(|&hop| hop != item).all(|hop| {
-                    let h_hop: H = hop.clone().into();
-                    all_hops.insert(h_hop)
-                })
-            });
+/// Like [`get_function_callees`], but also reports the analyzed definitions.
+/// No definition is incomplete, as every callee's callers aren't expected to
+/// be found.
+pub async fn collect_function_callees(
+    client: &mut LspClient,
+    workspace_files: &HashSet<Url>,
+    project_root: &Url,
+    options: &FunctionCallsOptions,
+) -> Result<FunctionCalls, CodeDepthError> {
+    let source_items = get_definitions(client, workspace_files, options).await?;
+    let definitions = to_definitions_by_item(&source_items);
 
-            total_unique_depths > 1 && paths_are_unique
-        })
+    let mut calls = vec![];
+    for source_item in source_items.iter().cloned() {
+        let params = CallHierarchyOutgoingCallsParams {
+            item: prepare_item(client, &source_item, &options.retry).await?,
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let result =
+            call_with_retry::<CallHierarchyOutgoingCalls>(client, &params, &options.retry).await;
+
+        match result {
+            Ok(Some(response)) => {
+                for target_item in response {
+                    // filter out calls to outside our project
+                    if !is_in_workspace(
+                        &target_item.to.uri,
+                        project_root,
+                        &options.workspace_folders,
+                    ) {
+                        continue;
+                    }
+
+                    if options.drop_self_loops && is_self_loop(&source_item, &target_item.to) {
+                        continue;
+                    }
+
+                    let callee = definitions
+                        .get(&target_item.to.clone().into())
+                        .cloned()
+                        .unwrap_or(target_item.to);
+
+                    // every definition's calls are asked for, but only the calls to
+                    // the analyzed functions are kept, like the incoming direction
+                    if !is_analyzed_function(&callee, project_root, options) {
+                        continue;
+                    }
+
+                    calls.push(Call {
+                        caller: source_item.clone(),
+                        callee,
+                        from_ranges: target_item.from_ranges,
+                    });
+                }
+            }
+            Ok(None) => {}
+            Err(CodeDepthError::Lsp(e)) => log_request_error(&source_item, project_root, &e),
+            Err(e @ CodeDepthError::Timeout { .. }) => {
+                debug!("skipping {}: {}", source_item.name, e)
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(FunctionCalls {
+        calls,
+        incomplete: vec![],
+        definitions: source_items
+            .into_iter()
+            .filter(|item| is_analyzed_function(item, project_root, options))
+            .collect(),
+    })
+}
+
+/// get the call hierarchy item of each definition in the workspace files
+async fn get_definitions(
+    client: &mut LspClient,
+    workspace_files: &HashSet<Url>,
+    options: &FunctionCallsOptions,
+) -> Result<Vec<CallHierarchyItem>, CodeDepthError> {
+    open_workspace_files(client, workspace_files, options.io_concurrency).await;
+
+    // get exact location of each definition's name
+    let mut exact_definitions = vec![];
+    let mut flat_definitions = vec![];
+
+    let mut files = workspace_files.iter().cloned().collect::<Vec<_>>();
+    files.sort();
+
+    // get file symbols
+    let results = client
+        .document_symbols(files.clone(), options.concurrency)
+        .await;
+
+    for (file, result) in files.iter().zip(results) {
+        // a file the server can't handle, e.g. one that isn't source code,
+        // shouldn't stop the analysis of the others
+        let result = match result {
+            Ok(Some(result)) => result,
+            Ok(None) => {
+                debug!("got no symbols for {}", file);
+                continue;
+            }
+            Err(CodeDepthError::Lsp(e)) => {
+                debug!("failed to get symbols of {}: {:?}", file, e);
+                continue;
+            }
+            Err(e @ CodeDepthError::Timeout { .. }) => {
+                debug!("failed to get symbols of {}: {}", file, e);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+
+        match result {
+            // without DocumentSymbol only the whole definition's location is
+            // known, still enough to ask for its calls
+            lsp_types::DocumentSymbolResponse::Flat(symbols) => {
+                flat_definitions.extend(to_flat_definitions(symbols, options));
+            }
+            lsp_types::DocumentSymbolResponse::Nested(symbols) => {
+                update_exact_definitions(symbols, file, options, &[], &mut exact_definitions);
+            }
+        }
+    }
+
+    let definitions = exact_definitions
+        .into_iter()
+        .map(|(file, definition)| CallHierarchyItem {
+            name: definition.name,
+            kind: definition.kind,
+            tags: definition.tags,
+            detail: definition.detail,
+            uri: file,
+            range: definition.range,
+            selection_range: definition.selection_range,
+            data: None,
+        })
+        .chain(flat_definitions)
+        .collect();
+
+    Ok(definitions)
+}
+
+/// Definitions of a flat document symbol response, their `location.range` is
+/// used as both their range and selection range.
+fn to_flat_definitions(
+    symbols: Vec<SymbolInformation>,
+    options: &FunctionCallsOptions,
+) -> Vec<CallHierarchyItem> {
+    symbols
+        .into_iter()
+        .filter(|symbol| options.symbol_kinds.contains(&symbol.kind))
+        .map(|symbol| {
+            let name = match (&options.qualified_name_separator, symbol.container_name) {
+                (Some(separator), Some(container)) if !container.is_empty() => {
+                    format!("{}{}{}", container, separator, symbol.name)
+                }
+                _ => symbol.name,
+            };
+
+            CallHierarchyItem {
+                name,
+                kind: symbol.kind,
+                tags: symbol.tags,
+                detail: None,
+                uri: symbol.location.uri,
+                range: symbol.location.range,
+                selection_range: symbol.location.range,
+                data: None,
+            }
+        })
+        .collect()
+}
+
+fn to_definitions_by_item(
+    definitions: &[CallHierarchyItem],
+) -> HashMap<HashableCallHierarchyItem, CallHierarchyItem> {
+    definitions
+        .iter()
+        .map(|item| (item.clone().into(), item.clone()))
+        .collect()
+}
+
+fn log_request_error(item: &CallHierarchyItem, project_root: &Url, e: &LspError) {
+    debug!(
+        "got jsonRpcError for {:?}: {:?} {:?}",
+        (
+            item_path(&item.uri, project_root),
+            &item.name,
+            &item.selection_range.start
+        ),
+        e.code,
+        e.message
+    );
+}
+
+/// The server's own item for a definition, some servers need the `data` of the
+/// items they prepared to answer call hierarchy requests about them.
+///
+/// Falls back to `item` itself when the server prepares no item for it.
+async fn prepare_item(
+    client: &mut LspClient,
+    item: &CallHierarchyItem,
+    retry: &RetryOptions,
+) -> Result<CallHierarchyItem, CodeDepthError> {
+    let params = CallHierarchyPrepareParams {
+        text_document_position_params: TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier {
+                uri: item.uri.clone(),
+            },
+            position: item.selection_range.start,
+        },
+        work_done_progress_params: Default::default(),
+    };
+
+    let prepared = match call_with_retry::<CallHierarchyPrepare>(client, &params, retry).await {
+        Ok(prepared) => prepared,
+        Err(e @ (CodeDepthError::Lsp(_) | CodeDepthError::Timeout { .. })) => {
+            debug!("failed to prepare {}: {}", item.name, e);
+            None
+        }
+        Err(e) => return Err(e),
+    };
+
+    Ok(prepared
+        .and_then(|prepared| prepared.into_iter().next())
+        .unwrap_or_else(|| item.clone()))
+}
+
+async fn call_with_retry<R: Request>(
+    client: &mut LspClient,
+    params: &R::Params,
+    retry: &RetryOptions,
+) -> Result<R::Result, CodeDepthError> {
+    let mut attempt = 0;
+    loop {
+        match client.call::<R>(params).await {
+            Err(e) if retry.should_retry(&e, attempt) => {
+                debug!("retrying {} after: {}", R::METHOD, e);
+                tokio::time::sleep(retry.delay(attempt)).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Whether calls to `item` are analyzed, with [`FunctionCallsOptions::functions`].
+fn is_analyzed_function(
+    item: &CallHierarchyItem,
+    root: &Url,
+    options: &FunctionCallsOptions,
+) -> bool {
+    options
+        .functions
+        .as_ref()
+        .is_none_or(|functions| is_listed_function(functions, item, root, &options.name_format))
+}
+
+fn is_listed_function(
+    functions: &HashSet<String>,
+    item: &CallHierarchyItem,
+    root: &Url,
+    name_format: &NameFormat,
+) -> bool {
+    let short_name = name_format.build(item, root);
+    // listing functions by their compact names works with any name style
+    let compact_name = name_format.compact().build(item, root);
+
+    [short_name.as_str(), compact_name.as_str()]
+        .iter()
+        .any(|name| functions.contains(*name) || functions.contains(name.trim_start_matches('/')))
+        || name_format
+            .split(&short_name)
+            .is_some_and(|(_, function_name)| functions.contains(function_name))
+}
+
+fn is_self_loop(caller: &CallHierarchyItem, callee: &CallHierarchyItem) -> bool {
+    HashableCallHierarchyItem::from(caller.clone())
+        == HashableCallHierarchyItem::from(callee.clone())
+}
+
+fn update_exact_definitions(
+    symbols: Vec<lsp_types::DocumentSymbol>,
+    file: &Url,
+    options: &FunctionCallsOptions,
+    containers: &[String],
+    exact_definitions: &mut Vec<(Url, lsp_types::DocumentSymbol)>,
+) {
+    // walk the symbols depth first with a stack of the remaining siblings at
+    // each level instead of recursing, so deep nesting can't overflow the
+    // call stack. `containers` has the names of the symbols being walked into
+    let mut containers = containers.to_vec();
+    let mut stack = vec![symbols.into_iter()];
+    while let Some(siblings) = stack.last_mut() {
+        let Some(mut symbol) = siblings.next() else {
+            stack.pop();
+            if !stack.is_empty() {
+                containers.pop();
+            }
+            continue;
+        };
+        let children = symbol.children.take();
+
+        if options.symbol_kinds.contains(&symbol.kind) {
+            let mut definition = symbol.clone();
+            if let Some(separator) = &options.qualified_name_separator {
+                let mut names = containers.clone();
+                names.push(symbol.name.clone());
+                definition.name = names.join(separator);
+            }
+
+            exact_definitions.push((file.to_owned(), definition));
+        }
+
+        if let Some(children) = children {
+            containers.push(symbol.name);
+            stack.push(children.into_iter());
+        }
+    }
+}
+
+pub fn get_function_depths(
+    calls: &[Call],
+) -> Vec<(CallHierarchyItem, Vec<Vec<CallHierarchyItem>>)> {
+    try_get_function_depths(calls, None).expect("no max depth to exceed")
+}
+
+/// default of the depth [`get_truncated_function_depths`] stops paths at, deep
+/// enough for any reasonable call graph
+pub const DEFAULT_MAX_DEPTH: usize = 100;
+
+/// The functions [`get_truncated_function_depths`] stopped paths at.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Truncation {
+    pub max_depth: usize,
+    /// functions at `max_depth` whose callees were cut off
+    pub truncated: HashSet<HashableCallHierarchyItem>,
+    /// functions only reached deeper than `max_depth`, left without depths
+    pub cut_off: HashSet<HashableCallHierarchyItem>,
+    /// functions whose calls back into a cycle ended their paths, see
+    /// [`graph_util::LimitedDepths::cycle_ends`]
+    pub cycle_ends: HashSet<HashableCallHierarchyItem>,
+}
+
+impl Truncation {
+    /// No path was cut at `max_depth`, paths ending at cycles aren't cut.
+    pub fn is_empty(&self) -> bool {
+        self.truncated.is_empty() && self.cut_off.is_empty()
+    }
+}
+
+/// Like [`try_get_function_depths`], or like
+/// [`try_get_function_depths_from_roots`] with `roots`, but paths aren't
+/// expanded deeper than `max_depth`, so long chains (or cycles missed during
+/// development) can't blow up the traversal or the report.
+///
+/// Functions only reachable by deeper paths have no depths, see
+/// [`Truncation::cut_off`].
+pub fn get_truncated_function_depths(
+    calls: &[Call],
+    roots: Option<&HashSet<HashableCallHierarchyItem>>,
+    max_depth_abort: Option<usize>,
+    max_depth: usize,
+) -> Result<(Depths<CallHierarchyItem>, Truncation), CodeDepthError> {
+    let hashable_calls = to_hashable_calls(calls);
+    let roots = match roots {
+        Some(roots) => hashable_calls
+            .iter()
+            .flat_map(|(caller, callee)| [caller, callee])
+            .filter(|item| roots.contains(item))
+            .collect::<HashSet<_>>(),
+        None => find_roots(&hashable_calls),
+    };
+    let limit = DepthLimit {
+        abort: max_depth_abort,
+        truncate: Some(max_depth),
+    };
+
+    let LimitedDepths {
+        paths: depths_by_root,
+        truncated,
+        cycle_ends,
+    } = graph_util::get_limited_depths_from(&hashable_calls, roots.iter().copied(), limit)
+        .map_err(|path| max_depth_exceeded(path, max_depth_abort))?;
+
+    // the functions past the limit were never visited, but are still reached
+    let mut cut_off = graph_util::find_reachable(&hashable_calls, roots.iter().copied());
+    for (_, items) in &depths_by_root {
+        for (item, _) in items {
+            cut_off.remove(item);
+        }
+    }
+
+    let depths = to_item_depths(Ok(depths_by_root), max_depth_abort)?;
+
+    Ok((
+        depths,
+        Truncation {
+            max_depth,
+            truncated,
+            cut_off,
+            cycle_ends,
+        },
+    ))
+}
+
+/// Like [`get_function_depths`], but fails with
+/// [`CodeDepthError::MaxDepthExceeded`] as soon as a path deeper than
+/// `max_depth` is found.
+pub fn try_get_function_depths(
+    calls: &[Call],
+    max_depth: Option<usize>,
+) -> Result<Depths<CallHierarchyItem>, CodeDepthError> {
+    let hashable_calls = to_hashable_calls(calls);
+
+    to_item_depths(get_depths(&hashable_calls, max_depth), max_depth)
+}
+
+/// Like [`try_get_function_depths`], but the depths are measured from the
+/// functions matching `is_root`, instead of from the functions without
+/// callers. Functions no matching function reaches have no depths.
+pub fn try_get_function_depths_from_roots<F>(
+    calls: &[Call],
+    is_root: F,
+    max_depth: Option<usize>,
+) -> Result<Depths<CallHierarchyItem>, CodeDepthError>
+where
+    F: Fn(&CallHierarchyItem) -> bool,
+{
+    let hashable_calls = to_hashable_calls(calls);
+    let roots = hashable_calls
+        .iter()
+        .flat_map(|(caller, callee)| [caller, callee])
+        .filter(|item| is_root(&item.0))
+        .collect::<HashSet<_>>();
+
+    to_item_depths(
+        graph_util::get_depths_from(&hashable_calls, roots, max_depth),
+        max_depth,
+    )
+}
+
+fn max_depth_exceeded(
+    path: Vec<HashableCallHierarchyItem>,
+    max_depth: Option<usize>,
+) -> CodeDepthError {
+    CodeDepthError::MaxDepthExceeded {
+        max_depth: max_depth.unwrap_or_default(),
+        path: path.into_iter().map(Into::into).collect(),
+    }
+}
+
+fn to_item_depths(
+    depths_by_root: Result<
+        Vec<RootPaths<HashableCallHierarchyItem>>,
+        Vec<HashableCallHierarchyItem>,
+    >,
+    max_depth: Option<usize>,
+) -> Result<Depths<CallHierarchyItem>, CodeDepthError> {
+    let depths_by_root = depths_by_root.map_err(|path| max_depth_exceeded(path, max_depth))?;
+
+    // get item paths from each root
+    let mut item_paths_from_roots = HashMap::new();
+    for (_, items) in depths_by_root {
+        for (item, item_path) in items {
+            let item_path_from_root: &mut Vec<Vec<CallHierarchyItem>> =
+                item_paths_from_roots.entry(item).or_default();
+
+            let mut converted_item_path: Vec<CallHierarchyItem> = vec![];
+            for hop in item_path {
+                converted_item_path.push(hop.into());
+            }
+
+            item_path_from_root.push(converted_item_path);
+        }
+    }
+
+    Ok(item_paths_from_roots
+        .into_iter()
+        .map(|(k, v)| (k.into(), v))
+        .collect())
+}
+
+/// Replace all roots (functions without callers) matching `should_merge` with
+/// a single synthetic root, so the depths of their callees are measured from
+/// one merged entry point.
+pub fn merge_roots<F>(calls: Vec<Call>, project_root: &Url, should_merge: F) -> Vec<Call>
+where
+    F: Fn(&CallHierarchyItem) -> bool,
+{
+    let hashable_calls = to_hashable_calls(&calls);
+    let merged_roots = find_roots(&hashable_calls)
+        .into_iter()
+        .filter(|root| should_merge(&root.0))
+        .cloned()
+        .collect::<HashSet<_>>();
+
+    if merged_roots.is_empty() {
+        return calls;
+    }
+
+    let merged_root = CallHierarchyItem {
+        name: MERGED_ROOTS_NAME.to_string(),
+        kind: SymbolKind::FUNCTION,
+        tags: None,
+        detail: Some(format!("{} merged roots", merged_roots.len())),
+        uri: project_root.clone(),
+        range: Range::default(),
+        selection_range: Range::default(),
+        data: None,
+    };
+
+    calls
+        .into_iter()
+        .map(|mut call| {
+            if merged_roots.contains(&call.caller.clone().into()) {
+                call.caller = merged_root.clone();
+            }
+
+            call
+        })
+        .collect()
+}
+
+/// name of the synthetic root created by [`merge_roots`]
+pub const MERGED_ROOTS_NAME: &str = "<merged roots>";
+
+/// convert calls into hashable (caller, callee) edges
+fn to_hashable_calls(
+    calls: &[Call],
+) -> Vec<(HashableCallHierarchyItem, HashableCallHierarchyItem)> {
+    calls
+        .iter()
+        .map(|call| (call.caller.clone().into(), call.callee.clone().into()))
+        .collect()
+}
+
+pub fn build_short_fn_depths(
+    root: &Url,
+    depths: &Vec<(CallHierarchyItem, Vec<Vec<CallHierarchyItem>>)>,
+) -> Depths<String> {
+    let mut short_item_depths = vec![];
+
+    for (item, paths_from_roots) in depths {
+        let item_name = build_call_hierarchy_item_name(item, root);
+
+        let mut short_paths = vec![];
+        for path in paths_from_roots {
+            let mut short_path = vec![];
+            for hop in path {
+                short_path.push(build_call_hierarchy_item_name(hop, root));
+            }
+
+            short_paths.push(short_path);
+        }
+
+        short_item_depths.push((item_name, short_paths));
+    }
+
+    short_item_depths
+}
+
+/// The path of `uri` in short item names, relative to `root` with a leading
+/// slash (e.g. `/src/main.rs`), or the whole uri when it's outside the root.
+pub(crate) fn item_path(uri: &Url, root: &Url) -> String {
+    match relative_path(uri, root) {
+        // the root itself, e.g. of imported items without a path
+        Some(path) if path.is_empty() => path,
+        Some(path) => format!("/{}", path),
+        None => uri.to_string(),
+    }
+}
+
+pub fn build_call_hierarchy_item_name(item: &CallHierarchyItem, root: &Url) -> String {
+    NameFormat::default().build(item, root)
+}
+
+/// What short item names are made of, see [`NameFormat`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum NameStyle {
+    /// the path and the function name, e.g. `src/main.rs:main`
+    #[default]
+    Compact,
+    /// the compact name followed by the 1-based line and column the function's
+    /// name starts at, e.g. `src/main.rs:main:42:8`, telling apart functions
+    /// of the same name in a file and recognized as a location by editors
+    Location,
+}
+
+/// How short item names are built from the item's path relative to the
+/// project root and its function name, e.g. `src/main.rs:main`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameFormat {
+    /// separates the path from the function name, defaults to `:` except on
+    /// windows where paths can contain drive letters (`C:`), there it is `#`
+    pub separator: String,
+    pub style: NameStyle,
+}
+
+impl Default for NameFormat {
+    fn default() -> Self {
+        Self {
+            separator: if cfg!(windows) { "#" } else { ":" }.to_string(),
+            style: NameStyle::default(),
+        }
+    }
+}
+
+impl NameFormat {
+    pub fn build(&self, item: &CallHierarchyItem, root: &Url) -> String {
+        let name = format!(
+            "{}{}{}",
+            item_path(&item.uri, root),
+            self.separator,
+            item.name.split('(').next().unwrap()
+        );
+
+        match self.style {
+            NameStyle::Compact => name,
+            NameStyle::Location => {
+                let start = item.selection_range.start;
+                let separator = &self.separator;
+                let (line, column) = (start.line + 1, start.character + 1);
+                format!("{}{}{}{}{}", name, separator, line, separator, column)
+            }
+        }
+    }
+
+    /// Split a short name into its path and function name, without the
+    /// location of [`NameStyle::Location`] names. The path ends at the first
+    /// separator after its last `/`, as both the path (e.g. `c:` drives) and
+    /// the function name (e.g. `ns::f`) may contain the separator.
+    pub fn split<'a>(&self, name: &'a str) -> Option<(&'a str, &'a str)> {
+        let name = match self.style {
+            NameStyle::Compact => name,
+            NameStyle::Location => strip_location(name, &self.separator),
+        };
+
+        let dirs_len = name.rsplit_once('/').map_or(0, |(dirs, _)| dirs.len() + 1);
+        let (file, function_name) = name[dirs_len..].split_once(self.separator.as_str())?;
+
+        Some((&name[..dirs_len + file.len()], function_name))
+    }
+
+    /// Whether `name` names `item`, in this format or the compact one, with or
+    /// without the leading slash.
+    pub fn matches(&self, item: &CallHierarchyItem, root: &Url, name: &str) -> bool {
+        [self.build(item, root), self.compact().build(item, root)]
+            .iter()
+            .any(|short_name| short_name == name || short_name.trim_start_matches('/') == name)
+    }
+
+    /// The same format with the [`NameStyle::Compact`] style.
+    pub fn compact(&self) -> Self {
+        Self {
+            style: NameStyle::Compact,
+            ..self.clone()
+        }
+    }
+}
+
+/// `name` without a trailing `line` and `column` after `separator`, if it has
+/// them.
+fn strip_location<'a>(name: &'a str, separator: &str) -> &'a str {
+    let mut parts = name.rsplitn(3, separator);
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(column), Some(line), Some(rest))
+            if [column, line]
+                .iter()
+                .all(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit())) =>
+        {
+            rest
+        }
+        _ => name,
+    }
+}
+
+pub type Depths<T> = Vec<(T, Vec<Vec<T>>)>;
+pub fn find_items_with_different_depths<T, H>(depths: &Depths<T>) -> HashSet<H>
+where
+    T: PartialEq + Into<H> + Clone,
+    H: Hash + Eq,
+{
+    find_items_with_depth_spread(depths, 0)
+}
+
+/// Like [`find_items_with_different_depths`], but only the items whose max
+/// depth is more than `max_depth_spread` deeper than their min depth.
+pub fn find_items_with_depth_spread<T, H>(depths: &Depths<T>, max_depth_spread: usize) -> HashSet<H>
+where
+    T: PartialEq + Into<H> + Clone,
+    H: Hash + Eq,
+{
+    depths
+        .iter()
+        .filter(|(_, item_paths_from_roots)| {
+            let lengths = item_paths_from_roots.iter().map(|path| path.len());
+            let depth_spread =
+                lengths.clone().max().unwrap_or_default() - lengths.min().unwrap_or_default();
+
+            // paths of different roots may share hops, but a path through a
+            // function twice loops, and doesn't measure a depth
+            let paths_are_simple = item_paths_from_roots.iter().all(|path| {
+                let mut hops: HashSet<H> = HashSet::new();
+                path.iter().all(|hop| hops.insert(hop.clone().into()))
+            });
+
+            depth_spread > max_depth_spread && paths_are_simple
+        })
         .map(|(item, _)| item.clone().into())
         .collect()
 }
+
+/// Keep only the problems whose different depths aren't inherited from an
+/// upstream problem, e.g. of `A→B→C→D` and `E→C→D` only `C` is kept.
+///
+/// A problem inherits its depths from another problem on every one of its
+/// paths, that is the same distance away from it on all of them.
+pub fn find_root_cause_problems<T, H>(problems: &HashSet<H>, depths: &Depths<T>) -> HashSet<H>
+where
+    T: Into<H> + Clone,
+    H: Hash + Eq + Clone,
+{
+    depths
+        .iter()
+        .filter_map(|(item, paths)| {
+            let item: H = item.clone().into();
+            if !problems.contains(&item) {
+                return None;
+            }
+
+            // distance of each upstream problem to the item on each path
+            let mut upstream = paths.iter().map(|path| {
+                path.iter()
+                    .rev()
+                    .enumerate()
+                    .skip(1)
+                    .map(|(distance, hop)| (hop.clone().into(), distance))
+                    .filter(|(hop, _)| problems.contains(hop))
+                    .collect::<HashMap<H, usize>>()
+            });
+
+            let mut common = upstream.next().unwrap_or_default();
+            for path_upstream in upstream {
+                common.retain(|hop, distance| path_upstream.get(hop) == Some(distance));
+            }
+
+            common.is_empty().then_some(item)
+        })
+        .collect()
+}
+
+/// The depths of the items `is_target` matches and of the items on their
+/// paths from the roots, for investigating a single function instead of the
+/// whole project. The depths keep their order.
+pub fn get_target_depths<T, H>(depths: Depths<T>, is_target: impl Fn(&T) -> bool) -> Depths<T>
+where
+    T: Into<H> + Clone,
+    H: Hash + Eq,
+{
+    let on_target_paths = depths
+        .iter()
+        .filter(|(item, _)| is_target(item))
+        .flat_map(|(_, paths)| paths.iter().flatten())
+        .map(|hop| hop.clone().into())
+        .collect::<HashSet<H>>();
+
+    depths
+        .into_iter()
+        .filter(|(item, _)| is_target(item) || on_target_paths.contains(&item.clone().into()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, time::Duration};
+
+    use globset::{Glob, GlobSetBuilder};
+    use lsp_types::{
+        CallHierarchyItem, DocumentSymbol, Position, Range, SymbolInformation, SymbolKind, Url,
+    };
+    use serde_json::{json, Value};
+
+    use super::{
+        filter_files_by_globs, filter_files_by_suffix, find_items_with_different_depths,
+        find_root_cause_problems, get_function_depths, get_target_depths,
+        get_truncated_function_depths, is_listed_function, merge_roots, parse_symbol_query,
+        to_flat_definitions, try_get_function_depths_from_roots, update_exact_definitions, Call,
+        CodeDepthError, FunctionCalls, FunctionCallsOptions, HashableCallHierarchyItem, LspError,
+        NameFormat, NameStyle, RetryOptions, SymbolQuery, SymbolQueryStrategy, MERGED_ROOTS_NAME,
+    };
+    use crate::lsp::{test_server, LspClient};
+    use crate::test_util::{call, item};
+
+    #[test]
+    fn test_try_get_function_depths_from_roots() {
+        // `stub` is a root only because its callers weren't analyzed
+        let (main, stub, a, b) = (item("main", 0), item("stub", 1), item("a", 2), item("b", 3));
+        let calls = vec![call(&main, &a), call(&a, &b), call(&stub, &b)];
+
+        let mut depths =
+            try_get_function_depths_from_roots(&calls, |item| item.name == "main", None).unwrap();
+        depths.sort_by_key(|(item, _)| item.selection_range.start);
+
+        assert_eq!(
+            depths,
+            vec![
+                (main.clone(), vec![vec![main.clone()]]),
+                (a.clone(), vec![vec![main.clone(), a.clone()]]),
+                (b.clone(), vec![vec![main, a, b]]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_get_truncated_function_depths() {
+        let (main, a, b, c) = (item("main", 0), item("a", 1), item("b", 2), item("c", 3));
+        // `b` is also reached by a short path from `main`
+        let calls = [call(&main, &a), call(&a, &b), call(&b, &c), call(&main, &b)];
+
+        let (depths, truncation) = get_truncated_function_depths(&calls, None, None, 1).unwrap();
+
+        let mut names = depths
+            .iter()
+            .map(|(item, paths)| (item.name.as_str(), paths.len()))
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, [("a", 1), ("b", 1), ("main", 1)]);
+        assert_eq!(
+            truncation.truncated,
+            [HashableCallHierarchyItem(b.clone())].into()
+        );
+        assert_eq!(truncation.cut_off, [HashableCallHierarchyItem(c)].into());
+
+        let (_, truncation) = get_truncated_function_depths(&calls, None, None, 2).unwrap();
+        assert!(truncation.is_empty());
+        assert!(truncation.cycle_ends.is_empty());
+
+        // a cycle ends paths without truncating them
+        let cycle = [call(&main, &a), call(&a, &b), call(&b, &a)];
+        let (_, truncation) = get_truncated_function_depths(&cycle, None, None, 3).unwrap();
+        assert!(truncation.is_empty());
+        assert_eq!(
+            truncation.cycle_ends,
+            [HashableCallHierarchyItem(b.clone())].into()
+        );
+
+        // from other roots, and still aborting at the abort depth
+        let roots = HashSet::from([HashableCallHierarchyItem(a.clone())]);
+        let (depths, truncation) =
+            get_truncated_function_depths(&calls, Some(&roots), None, 1).unwrap();
+        assert_eq!(depths.len(), 2);
+        assert_eq!(truncation.truncated, [HashableCallHierarchyItem(b)].into());
+        assert!(matches!(
+            get_truncated_function_depths(&calls, None, Some(0), 1),
+            Err(CodeDepthError::MaxDepthExceeded { max_depth: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn test_merge_function_calls() {
+        let (main, a) = (item("main", 0), item("a", 1));
+        let mut py_main = item("main", 0);
+        py_main.uri = Url::parse("file:///project/main.py").unwrap();
+
+        let merged = [
+            FunctionCalls {
+                calls: vec![call(&main, &a)],
+                incomplete: vec![],
+                definitions: vec![main.clone(), a.clone()],
+            },
+            FunctionCalls {
+                calls: vec![],
+                incomplete: vec![py_main.clone()],
+                definitions: vec![py_main.clone()],
+            },
+        ]
+        .into_iter()
+        .collect::<FunctionCalls>();
+
+        assert_eq!(
+            merged,
+            FunctionCalls {
+                calls: vec![call(&main, &a)],
+                incomplete: vec![py_main.clone()],
+                definitions: vec![main, a, py_main],
+            }
+        );
+    }
+
+    #[test]
+    fn test_merge_roots() {
+        let root = Url::parse("file:///project").unwrap();
+        let (stub_1, stub_2, main) = (item("stub_1", 0), item("stub_2", 1), item("main", 2));
+        let (a, b) = (item("a", 3), item("b", 4));
+        let calls = vec![
+            call(&stub_1, &a),
+            call(&stub_2, &b),
+            call(&main, &a),
+            call(&a, &b),
+        ];
+
+        let merged = merge_roots(calls, &root, |item| item.name.starts_with("stub"));
+
+        let callers = merged
+            .iter()
+            .map(|call| call.caller.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            callers,
+            vec![MERGED_ROOTS_NAME, MERGED_ROOTS_NAME, "main", "a"]
+        );
+    }
+
+    #[allow(deprecated)]
+    fn symbol(name: &str, kind: SymbolKind, children: Vec<DocumentSymbol>) -> DocumentSymbol {
+        DocumentSymbol {
+            name: name.to_string(),
+            detail: None,
+            kind,
+            tags: None,
+            deprecated: None,
+            range: Range::default(),
+            selection_range: Range::default(),
+            children: Some(children),
+        }
+    }
+
+    #[test]
+    fn test_qualified_constructor_names() {
+        let file = Url::parse("file:///project/src/widget.cpp").unwrap();
+        let symbols = vec![symbol(
+            "Widget",
+            SymbolKind::CLASS,
+            vec![
+                symbol("Widget", SymbolKind::CONSTRUCTOR, vec![]),
+                symbol("~Widget", SymbolKind::CONSTRUCTOR, vec![]),
+                symbol("draw", SymbolKind::METHOD, vec![]),
+            ],
+        )];
+        let options = FunctionCallsOptions {
+            symbol_kinds: vec![SymbolKind::CONSTRUCTOR],
+            qualified_name_separator: Some("::".to_string()),
+            ..Default::default()
+        };
+
+        let mut definitions = vec![];
+        update_exact_definitions(symbols, &file, &options, &[], &mut definitions);
+
+        let names = definitions
+            .iter()
+            .map(|(_, symbol)| symbol.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["Widget::Widget", "Widget::~Widget"]);
+    }
+
+    #[test]
+    fn test_deeply_nested_definitions() {
+        let file = Url::parse("file:///project/src/main.rs").unwrap();
+        let depth = 10_000;
+        let mut nested = symbol("f0", SymbolKind::FUNCTION, vec![]);
+        for i in 1..depth {
+            let sibling = symbol(&format!("m{}", i), SymbolKind::METHOD, vec![]);
+            nested = symbol(
+                &format!("f{}", i),
+                SymbolKind::FUNCTION,
+                vec![nested, sibling],
+            );
+        }
+        let options = FunctionCallsOptions {
+            symbol_kinds: vec![SymbolKind::FUNCTION],
+            ..Default::default()
+        };
+
+        let mut definitions = vec![];
+        update_exact_definitions(vec![nested], &file, &options, &[], &mut definitions);
+
+        assert_eq!(definitions.len(), depth);
+        // outer symbols come before the symbols nested in them
+        assert_eq!(definitions[0].1.name, format!("f{}", depth - 1));
+        assert_eq!(definitions[depth - 1].1.name, "f0");
+    }
+
+    #[test]
+    fn test_flat_definitions() {
+        let file = Url::parse("file:///project/src/widget.cpp").unwrap();
+        let range = Range::new(Position::new(3, 0), Position::new(5, 1));
+        #[allow(deprecated)]
+        let flat_symbol = |name: &str, kind, container: Option<&str>| SymbolInformation {
+            name: name.to_string(),
+            kind,
+            tags: None,
+            deprecated: None,
+            location: lsp_types::Location::new(file.clone(), range),
+            container_name: container.map(str::to_string),
+        };
+        let symbols = vec![
+            flat_symbol("Widget", SymbolKind::CLASS, None),
+            flat_symbol("draw", SymbolKind::METHOD, Some("Widget")),
+            flat_symbol("main", SymbolKind::FUNCTION, None),
+        ];
+        let options = FunctionCallsOptions {
+            qualified_name_separator: Some("::".to_string()),
+            ..Default::default()
+        };
+
+        let definitions = to_flat_definitions(symbols, &options);
+
+        let names = definitions
+            .iter()
+            .map(|item| item.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["Widget::draw", "main"]);
+        assert_eq!(definitions[0].uri, file);
+        assert_eq!(definitions[0].range, range);
+        assert_eq!(definitions[0].selection_range, range);
+    }
+
+    #[test]
+    fn test_same_named_methods_of_different_classes() {
+        let root = Url::parse("file:///project").unwrap();
+        let file = Url::parse("file:///project/src/cache.ts").unwrap();
+        let options = FunctionCallsOptions {
+            qualified_name_separator: Some(super::DEFAULT_QUALIFIED_NAME_SEPARATOR.to_string()),
+            ..Default::default()
+        };
+        let names = |definitions: Vec<CallHierarchyItem>| {
+            definitions
+                .iter()
+                .map(|item| super::build_call_hierarchy_item_name(item, &root))
+                .collect::<Vec<_>>()
+        };
+
+        let symbols = vec![
+            symbol(
+                "Cache",
+                SymbolKind::CLASS,
+                vec![symbol("get", SymbolKind::METHOD, vec![])],
+            ),
+            symbol(
+                "Store",
+                SymbolKind::CLASS,
+                vec![symbol("get", SymbolKind::METHOD, vec![])],
+            ),
+        ];
+        let mut definitions = vec![];
+        update_exact_definitions(symbols, &file, &options, &[], &mut definitions);
+        let definitions = definitions
+            .into_iter()
+            .map(|(uri, symbol)| CallHierarchyItem {
+                name: symbol.name,
+                kind: symbol.kind,
+                tags: None,
+                detail: None,
+                uri,
+                range: symbol.range,
+                selection_range: symbol.selection_range,
+                data: None,
+            })
+            .collect();
+        assert_eq!(
+            names(definitions),
+            ["/src/cache.ts:Cache.get", "/src/cache.ts:Store.get"]
+        );
+
+        #[allow(deprecated)]
+        let flat_symbol = |container: &str| SymbolInformation {
+            name: "get()".to_string(),
+            kind: SymbolKind::METHOD,
+            tags: None,
+            deprecated: None,
+            location: lsp_types::Location::new(file.clone(), Range::default()),
+            container_name: Some(container.to_string()),
+        };
+        let definitions =
+            to_flat_definitions(vec![flat_symbol("Cache"), flat_symbol("Store")], &options);
+        assert_eq!(
+            names(definitions),
+            ["/src/cache.ts:Cache.get", "/src/cache.ts:Store.get"]
+        );
+
+        // the old unqualified names collapse
+        let unqualified = to_flat_definitions(
+            vec![flat_symbol("Cache"), flat_symbol("Store")],
+            &Default::default(),
+        );
+        assert_eq!(
+            names(unqualified),
+            ["/src/cache.ts:get", "/src/cache.ts:get"]
+        );
+    }
+
+    #[test]
+    fn test_retry_options() {
+        let retry = RetryOptions {
+            max_retries: 2,
+            backoff: Duration::from_millis(10),
+        };
+        let lsp_error = |code| {
+            CodeDepthError::Lsp(LspError {
+                code,
+                message: String::new(),
+            })
+        };
+
+        assert!(retry.should_retry(&lsp_error(-32802), 0));
+        assert!(retry.should_retry(&lsp_error(-32801), 1));
+        assert!(!retry.should_retry(&lsp_error(-32802), 2));
+        assert!(!retry.should_retry(&lsp_error(-32601), 0));
+        assert_eq!(retry.delay(0), Duration::from_millis(10));
+        assert_eq!(retry.delay(2), Duration::from_millis(40));
+    }
+
+    #[test]
+    fn test_filter_files_by_suffix() {
+        let files = ["main.rs", "build.gradle", "lib.rs.bak", "mod.rs"]
+            .iter()
+            .map(|file| Url::parse(&format!("file:///project/src/{}", file)).unwrap())
+            .collect();
+
+        let mut kept = filter_files_by_suffix(files, &[".rs"])
+            .into_iter()
+            .map(|file| file.path().to_string())
+            .collect::<Vec<_>>();
+        kept.sort();
+
+        assert_eq!(kept, vec!["/project/src/main.rs", "/project/src/mod.rs"]);
+    }
+
+    #[test]
+    fn test_symbol_query_strategy() {
+        let queries = ["#", "empty", "alphabet"]
+            .map(|query| parse_symbol_query(query).unwrap())
+            .to_vec();
+        assert_eq!(SymbolQueryStrategy(queries), SymbolQueryStrategy::default());
+
+        let strategy = SymbolQueryStrategy(vec![
+            SymbolQuery::Fixed("#".to_string()),
+            SymbolQuery::Empty,
+        ]);
+        assert_eq!(strategy.query_strings(), ["#", ""]);
+        assert_eq!(SymbolQueryStrategy::default().query_strings().len(), 28);
+        assert!(parse_symbol_query("").is_err());
+    }
+
+    #[test]
+    fn test_filter_files_by_globs() {
+        let root = Url::parse("file:///project").unwrap();
+        let files = || {
+            [
+                "src/main.rs",
+                "src/gen/api.rs",
+                "vendor/lib/mod.rs",
+                "build.rs",
+            ]
+            .iter()
+            .map(|file| Url::parse(&format!("file:///project/{}", file)).unwrap())
+            .collect()
+        };
+        let globs = |patterns: &[&str]| {
+            let mut builder = GlobSetBuilder::new();
+            for pattern in patterns {
+                builder.add(Glob::new(pattern).unwrap());
+            }
+
+            builder.build().unwrap()
+        };
+        let kept = |include: &[&str], exclude: &[&str]| {
+            let mut kept = filter_files_by_globs(files(), &root, &globs(include), &globs(exclude))
+                .into_iter()
+                .map(|file| file.path().trim_start_matches("/project/").to_string())
+                .collect::<Vec<_>>();
+            kept.sort();
+
+            kept
+        };
+
+        assert_eq!(
+            kept(&[], &["vendor", "src/gen/**"]),
+            vec!["build.rs", "src/main.rs"]
+        );
+        assert_eq!(
+            kept(&["src/**"], &[]),
+            vec!["src/gen/api.rs", "src/main.rs"]
+        );
+        // excludes win over includes
+        assert_eq!(kept(&["src/**"], &["**/gen"]), vec!["src/main.rs"]);
+    }
+
+    #[test]
+    fn test_find_items_with_different_depths() {
+        let problems = |calls: &[Call]| {
+            let mut names = find_items_with_different_depths::<_, HashableCallHierarchyItem>(
+                &get_function_depths(calls),
+            )
+            .into_iter()
+            .map(|item| item.0.name)
+            .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+        let (a, b, c, d) = (item("a", 0), item("b", 1), item("c", 2), item("d", 3));
+
+        // a diamond reaches `d` at the same depth through different hops
+        let diamond = [call(&a, &b), call(&a, &c), call(&b, &d), call(&c, &d)];
+        assert!(problems(&diamond).is_empty());
+
+        // the paths of `d` from `a` and `e` share `b`, and differ in length
+        let e = item("e", 4);
+        let shared_hop = [call(&a, &b), call(&b, &d), call(&e, &c), call(&c, &b)];
+        assert_eq!(problems(&shared_hop), ["b", "d"]);
+    }
+
+    #[test]
+    fn test_get_target_depths() {
+        let (main, a, b, c, d) = (0, 1, 2, 3, 4);
+        let depths = vec![
+            (main, vec![vec![main]]),
+            (a, vec![vec![main, a]]),
+            (b, vec![vec![main, a, b], vec![main, b]]),
+            (c, vec![vec![main, c]]),
+            (d, vec![vec![main, a, b, d], vec![main, b, d]]),
+        ];
+
+        let target_depths = get_target_depths::<_, i32>(depths.clone(), |item| *item == b);
+        let items = target_depths
+            .iter()
+            .map(|(item, _)| *item)
+            .collect::<Vec<_>>();
+        assert_eq!(items, [main, a, b]);
+        assert_eq!(target_depths[2], depths[2]);
+
+        assert!(get_target_depths::<_, i32>(depths, |item| *item == 5).is_empty());
+    }
+
+    #[test]
+    fn test_find_root_cause_problems() {
+        let (a, b, c, d, e, f) = (0, 1, 2, 3, 4, 5);
+        let problems = [c, d].into();
+        let mut depths = vec![
+            (c, vec![vec![a, b, c], vec![e, c]]),
+            (d, vec![vec![a, b, c, d], vec![e, c, d]]),
+        ];
+
+        assert_eq!(
+            find_root_cause_problems::<_, i32>(&problems, &depths),
+            [c].into()
+        );
+
+        // a path to `d` skipping `c` makes `d` a cause of its own
+        depths[1].1.push(vec![f, d]);
+        assert_eq!(
+            find_root_cause_problems::<_, i32>(&problems, &depths),
+            [c, d].into()
+        );
+    }
+
+    #[test]
+    fn test_is_listed_function() {
+        let root = Url::parse("file:///project").unwrap();
+        let listed = |names: &[&str]| {
+            let functions = names.iter().map(|name| name.to_string()).collect();
+            is_listed_function(&functions, &item("foo", 0), &root, &NameFormat::default())
+        };
+
+        assert!(listed(&["foo"]));
+        assert!(listed(&["src/main.rs:foo"]));
+        assert!(listed(&["/src/main.rs:foo"]));
+        assert!(!listed(&["src/lib.rs:foo", "bar"]));
+        assert!(!listed(&["src/main.rs"]));
+    }
+
+    #[test]
+    fn test_name_format() {
+        let root = Url::parse("file:///project").unwrap();
+        let name_format = NameFormat {
+            separator: "#".to_string(),
+            style: NameStyle::Compact,
+        };
+        let widget = CallHierarchyItem {
+            name: "Widget::Widget(int)".to_string(),
+            ..item("Widget", 0)
+        };
+
+        let name = name_format.build(&widget, &root);
+
+        assert_eq!(name, "/src/main.rs#Widget::Widget");
+        assert_eq!(
+            name_format.split(&name),
+            Some(("/src/main.rs", "Widget::Widget"))
+        );
+
+        let name_format = NameFormat {
+            style: NameStyle::Location,
+            ..name_format
+        };
+        let widget = CallHierarchyItem {
+            selection_range: Range::new(Position::new(41, 7), Position::new(41, 13)),
+            ..widget
+        };
+
+        let name = name_format.build(&widget, &root);
+
+        assert_eq!(name, "/src/main.rs#Widget::Widget#42#8");
+        assert_eq!(
+            name_format.split(&name),
+            Some(("/src/main.rs", "Widget::Widget"))
+        );
+        assert_eq!(
+            name_format.compact().build(&widget, &root),
+            "/src/main.rs#Widget::Widget"
+        );
+
+        // only paths inside the root are relative
+        let sibling = CallHierarchyItem {
+            uri: Url::parse("file:///project2/src/main.rs").unwrap(),
+            ..item("main", 0)
+        };
+        assert_eq!(
+            name_format.compact().build(&sibling, &root),
+            "file:///project2/src/main.rs#main"
+        );
+
+        // the path is split off after its last `/`, even when the path or the
+        // function name contains the separator
+        let name_format = NameFormat {
+            separator: ":".to_string(),
+            style: NameStyle::Compact,
+        };
+        assert_eq!(
+            name_format.split("file:///c:/project2/a.cpp:ns::f"),
+            Some(("file:///c:/project2/a.cpp", "ns::f"))
+        );
+        assert_eq!(name_format.split("src/a.cpp"), None);
+    }
+
+    #[tokio::test]
+    async fn test_init_sends_initialized() {
+        let (mut client, server_stream) = test_server::connect();
+        let server = tokio::spawn(test_server::serve(server_stream, |message| {
+            match message["method"].as_str() {
+                Some("initialize") => Some(Ok(json!({ "capabilities": {
+                    "workspaceSymbolProvider": true,
+                    "documentSymbolProvider": true,
+                    "callHierarchyProvider": true,
+                }}))),
+                Some("workspace/symbol") => Some(Ok(json!([]))),
+                _ => None,
+            }
+        }));
+
+        super::init(&mut client, Url::parse("file:///project").unwrap())
+            .await
+            .unwrap();
+        client.workspace_symbol("").await.unwrap();
+        client.notify::<lsp_types::notification::Exit>(&()).await;
+
+        let messages = server.await.unwrap();
+        let methods = messages
+            .iter()
+            .map(|message| message["method"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            methods,
+            ["initialize", "initialized", "workspace/symbol", "exit"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_init_missing_capability() {
+        let (mut client, server_stream) = test_server::connect();
+        tokio::spawn(test_server::serve(server_stream, |_| {
+            Some(Ok(
+                json!({ "capabilities": { "workspaceSymbolProvider": true } }),
+            ))
+        }));
+
+        let error = super::init(&mut client, Url::parse("file:///project").unwrap())
+            .await
+            .unwrap_err();
+
+        assert!(
+            matches!(
+                &error,
+                CodeDepthError::MissingCapability { methods }
+                    if methods == &["callHierarchy/incomingCalls", "textDocument/documentSymbol"]
+            ),
+            "unexpected error: {}",
+            error
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_server_exit_reports_stderr() {
+        let server = tokio::process::Command::new("sh")
+            .arg("-c")
+            .arg("echo 'panicked at src/main.rs' >&2; exit 101")
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut client = LspClient::stdio_client(server);
+
+        let root = Url::parse("file:///project").unwrap();
+        let files = [Url::parse("file:///project/src/main.rs").unwrap()].into();
+        let error = super::get_function_calls(&mut client, &files, &root, &Default::default())
+            .await
+            .unwrap_err();
+
+        assert!(
+            error.to_string().contains("panicked at src/main.rs"),
+            "unexpected error: {}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn test_open_workspace_files() {
+        let dir = std::env::temp_dir();
+        let path = dir.join("code_depth_test_open_workspace_files.rs");
+        std::fs::write(&path, "fn main() {}").unwrap();
+        let missing = dir.join("code_depth_test_open_workspace_files_missing.rs");
+        let files = [
+            Url::from_file_path(&path).unwrap(),
+            Url::from_file_path(&missing).unwrap(),
+        ]
+        .into();
+
+        let (mut client, server_stream) = test_server::connect();
+        let server = tokio::spawn(test_server::serve(server_stream, |_| None));
+
+        super::open_workspace_files(&mut client, &files, 2).await;
+        // opening again doesn't reopen
+        super::open_workspace_files(&mut client, &files, 2).await;
+        client.notify::<lsp_types::notification::Exit>(&()).await;
+
+        let opened = server
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|message| message["method"] == "textDocument/didOpen")
+            .map(|message| message["params"]["textDocument"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(opened.len(), 1);
+        assert_eq!(opened[0]["languageId"], "rust");
+        assert_eq!(opened[0]["text"], "fn main() {}");
+    }
+
+    #[tokio::test]
+    async fn test_incoming_calls_use_prepared_item() {
+        let file = Url::parse("file:///project/code_depth_test_prepared_item.rs").unwrap();
+        let range = Range::new(Position::new(0, 0), Position::new(0, 12));
+        let selection_range = Range::new(Position::new(0, 3), Position::new(0, 7));
+
+        let server_file = file.clone();
+        let (mut client, server_stream) = test_server::connect();
+        let server = tokio::spawn(test_server::serve(server_stream, move |message| {
+            let result = match message["method"].as_str().unwrap() {
+                "textDocument/documentSymbol" => json!([{
+                    "name": "main",
+                    "kind": 12,
+                    "range": range,
+                    "selectionRange": selection_range,
+                }]),
+                "textDocument/prepareCallHierarchy" => {
+                    assert_eq!(message["params"]["position"], json!(selection_range.start));
+                    json!([{
+                        "name": "main",
+                        "kind": 12,
+                        "uri": server_file,
+                        "range": range,
+                        "selectionRange": selection_range,
+                        "data": { "id": 7 },
+                    }])
+                }
+                "callHierarchy/incomingCalls" => json!([]),
+                _ => return None,
+            };
+
+            Some(Ok(result))
+        }));
+
+        let root = Url::parse("file:///project").unwrap();
+        let calls =
+            super::collect_function_calls(&mut client, &[file].into(), &root, &Default::default())
+                .await
+                .unwrap();
+        client.notify::<lsp_types::notification::Exit>(&()).await;
+
+        assert!(calls.calls.is_empty());
+        // the graph keeps our own items, only the requests use the prepared ones
+        assert_eq!(calls.definitions.len(), 1);
+        assert_eq!(calls.definitions[0].data, None);
+        let requested_items = server
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|message| message["method"] == "callHierarchy/incomingCalls")
+            .map(|message| message["params"]["item"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(requested_items.len(), 1);
+        assert_eq!(requested_items[0]["data"], json!({ "id": 7 }));
+    }
+
+    #[tokio::test]
+    async fn test_files_without_symbols_are_skipped() {
+        let range = Range::new(Position::new(0, 0), Position::new(0, 12));
+        let files = ["a.rs", "b.txt", "c.bin"]
+            .map(|file| Url::parse(&format!("file:///project/code_depth_test_{}", file)).unwrap());
+
+        let (mut client, server_stream) = test_server::connect();
+        tokio::spawn(test_server::serve(server_stream, move |message| {
+            let uri = message["params"]["textDocument"]["uri"].as_str();
+
+            match message["method"].as_str().unwrap() {
+                "textDocument/documentSymbol" if uri.unwrap().ends_with(".rs") => {
+                    Some(Ok(json!([{
+                        "name": "main",
+                        "kind": 12,
+                        "range": range,
+                        "selectionRange": range,
+                    }])))
+                }
+                "textDocument/documentSymbol" if uri.unwrap().ends_with(".txt") => {
+                    Some(Ok(Value::Null))
+                }
+                "textDocument/documentSymbol" => Some(Err(
+                    json!({ "code": -32603, "message": "not a source file" }),
+                )),
+                "textDocument/prepareCallHierarchy" => Some(Ok(Value::Null)),
+                "callHierarchy/incomingCalls" => Some(Ok(json!([]))),
+                _ => None,
+            }
+        }));
+
+        let root = Url::parse("file:///project").unwrap();
+        let calls =
+            super::collect_function_calls(&mut client, &files.into(), &root, &Default::default())
+                .await
+                .unwrap();
+
+        assert_eq!(calls.definitions.len(), 1);
+        assert_eq!(calls.definitions[0].name, "main");
+    }
+
+    #[tokio::test]
+    async fn test_get_workspace_files_server_indexing() {
+        let (mut client, server_stream) = test_server::connect();
+        tokio::spawn(test_server::serve(server_stream, |_| {
+            Some(Err(
+                json!({ "code": super::CONTENT_MODIFIED, "message": "indexing" }),
+            ))
+        }));
+
+        let root = Url::parse("file:///project").unwrap();
+        let max_duration = Duration::from_millis(300);
+        let error =
+            super::get_workspace_files(&mut client, &root, &Default::default(), max_duration)
+                .await
+                .unwrap_err();
+
+        assert!(
+            matches!(error, CodeDepthError::ServerIndexing { waited } if waited == max_duration),
+            "unexpected error: {}",
+            error
+        );
+    }
+}
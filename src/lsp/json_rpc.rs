@@ -1,11 +1,10 @@
-use std::error::Error;
-
 use lsp_types::{notification::Notification, request::Request};
-use regex::Regex;
 use serde::Deserialize;
 use serde_json::{json, Value};
 use tokio::io::{AsyncRead, AsyncReadExt};
 
+use crate::error::CodeDepthError;
+
 pub const JSON_RPC_VERSION: &str = "2.0";
 
 #[derive(Debug, Clone, Deserialize)]
@@ -64,43 +63,148 @@ pub fn build_notification<N: Notification>(params: &N::Params) -> Vec<u8> {
         .into()
 }
 
-pub async fn get_next_response<R>(reader: &mut R) -> Result<Vec<u8>, Box<dyn Error>>
+/// response to a request the server sent
+pub fn build_response(id: &Value, result: &Value) -> Vec<u8> {
+    let j = json!({
+            "jsonrpc": JSON_RPC_VERSION,
+            "result": result,
+            "id": id,
+    });
+
+    let json_str = j.to_string();
+
+    format!("Content-Length: {}\r\n\r\n{}", json_str.len(), json_str)
+        .as_bytes()
+        .into()
+}
+
+/// error response to a request the server sent
+pub fn build_error_response(id: &Value, code: isize, message: &str) -> Vec<u8> {
+    let j = json!({
+            "jsonrpc": JSON_RPC_VERSION,
+            "error": { "code": code, "message": message },
+            "id": id,
+    });
+
+    let json_str = j.to_string();
+
+    format!("Content-Length: {}\r\n\r\n{}", json_str.len(), json_str)
+        .as_bytes()
+        .into()
+}
+
+/// json rpc error code for a message that isn't valid json rpc
+pub const PARSE_ERROR: isize = -32700;
+
+/// max size of a message's headers, a server writing more isn't speaking json rpc
+const MAX_HEADERS_LEN: usize = 64 * 1024;
+
+/// Read the next message from `reader`, returning its content.
+///
+/// Reads exactly one message, so any bytes of the next messages stay in the
+/// reader. Use a buffered reader, since the headers are read byte by byte.
+pub async fn get_next_response<R>(reader: &mut R) -> Result<Vec<u8>, CodeDepthError>
 where
     R: AsyncRead + std::marker::Unpin,
 {
-    // match content-length: \d+
-    // and also match the separating \r\n\r\n to the actual content
-    // so that when we read the message we just read 'content-length' bytes
-    let re = Regex::new(r"Content-Length: (\d+)(?:\r\nContent-Type: [^\r]*)?\r\n\r\n").unwrap();
-    let mut buf = vec![];
-
-    // get content-length
-    let content_length = loop {
-        let byte = reader.read_u8().await?;
-        buf.push(byte);
-        let text = std::str::from_utf8(&buf)?;
-
-        if let Some(matches) = re.captures(text) {
-            let first_match = matches.get(1).ok_or("failed to extract content-length")?;
-            break first_match.as_str().parse::<usize>()?;
+    let mut headers = vec![];
+    while !headers.ends_with(b"\r\n\r\n") {
+        if headers.len() == MAX_HEADERS_LEN {
+            return Err(frame_error("headers are too long"));
         }
-    };
 
-    // read the rest of the message
-    let mut msg_buf = Vec::with_capacity(content_length);
-
-    if let Err(e) = reader.read_buf(&mut msg_buf).await {
-        return Err(Box::new(e));
+        headers.push(reader.read_u8().await.map_err(transport_error)?);
     }
 
-    while msg_buf.len() < content_length {
-        let mut next_buf = Vec::with_capacity(content_length - msg_buf.len());
-        if let Err(e) = reader.read_buf(&mut next_buf).await {
-            return Err(Box::new(e));
+    let content_length = parse_content_length(&headers)?;
+
+    let mut content = vec![0; content_length];
+    reader
+        .read_exact(&mut content)
+        .await
+        .map_err(transport_error)?;
+
+    Ok(content)
+}
+
+/// Parse the `Content-Length` of a message's headers, other headers (like
+/// `Content-Type`) and lines that aren't headers are ignored.
+fn parse_content_length(headers: &[u8]) -> Result<usize, CodeDepthError> {
+    let headers = String::from_utf8_lossy(headers);
+
+    let mut content_length = None;
+    for line in headers.lines() {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+
+        if name.trim().eq_ignore_ascii_case("content-length") {
+            let value = value.trim();
+            let length = value
+                .parse::<usize>()
+                .map_err(|_| frame_error(&format!("invalid Content-Length '{}'", value)))?;
+            content_length = Some(length);
         }
+    }
+
+    content_length.ok_or_else(|| frame_error("missing Content-Length"))
+}
+
+fn transport_error(e: std::io::Error) -> CodeDepthError {
+    CodeDepthError::Transport(e.to_string())
+}
+
+fn frame_error(message: &str) -> CodeDepthError {
+    CodeDepthError::Lsp(LspError {
+        code: PARSE_ERROR,
+        message: message.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncWriteExt, BufReader};
+
+    use super::{get_next_response, PARSE_ERROR};
+    use crate::error::CodeDepthError;
+
+    const MESSAGES: &[u8] = b"Content-Length: 2\r\n\r\n{}content-length: 4\r\nContent-Type: application/vscode-jsonrpc; charset=utf-8\r\n\r\nnull";
 
-        msg_buf.append(&mut next_buf);
+    #[tokio::test]
+    async fn test_get_next_response_byte_by_byte() {
+        // a single byte at a time makes the reader see every possible split
+        let (mut writer, reader) = tokio::io::duplex(1);
+        tokio::spawn(async move { writer.write_all(MESSAGES).await.unwrap() });
+
+        let mut reader = BufReader::new(reader);
+        assert_eq!(get_next_response(&mut reader).await.unwrap(), b"{}");
+        assert_eq!(get_next_response(&mut reader).await.unwrap(), b"null");
+    }
+
+    #[tokio::test]
+    async fn test_get_next_response_single_read() {
+        let mut reader = BufReader::new(MESSAGES);
+
+        assert_eq!(get_next_response(&mut reader).await.unwrap(), b"{}");
+        assert_eq!(get_next_response(&mut reader).await.unwrap(), b"null");
+        assert!(get_next_response(&mut reader).await.is_err());
     }
 
-    Ok(msg_buf)
+    #[tokio::test]
+    async fn test_get_next_response_invalid_frames() {
+        for frame in [
+            &b"Content-Type: text\r\n\r\n{}"[..],
+            &b"Content-Length: two\r\n\r\n{}"[..],
+        ] {
+            let error = get_next_response(&mut BufReader::new(frame))
+                .await
+                .unwrap_err();
+
+            assert!(
+                matches!(error, CodeDepthError::Lsp(ref e) if e.code == PARSE_ERROR),
+                "unexpected error: {}",
+                error
+            );
+        }
+    }
 }
@@ -1,49 +1,170 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::Duration,
+};
+
 use log::{debug, error, warn};
 use lsp_types::{
-    notification::{Initialized, Notification},
+    notification::{
+        Cancel, DidChangeTextDocument, DidChangeWatchedFiles, DidOpenTextDocument,
+        DidSaveTextDocument, Exit, Initialized, Notification, Progress,
+    },
     request::{
-        CallHierarchyIncomingCalls, DocumentSymbolRequest, Initialize, Request, WorkspaceSymbol,
+        CallHierarchyIncomingCalls, CallHierarchyOutgoingCalls, CallHierarchyPrepare,
+        DocumentSymbolRequest, GotoImplementation, GotoImplementationParams,
+        GotoImplementationResponse, Initialize, RegisterCapability, Request, Shutdown,
+        WorkDoneProgressCreate, WorkspaceConfiguration, WorkspaceFoldersRequest, WorkspaceSymbol,
     },
     CallHierarchyIncomingCall, CallHierarchyIncomingCallsParams, CallHierarchyItem,
-    DocumentSymbolParams, DocumentSymbolResponse, InitializeParams, InitializeResult,
-    InitializedParams, PartialResultParams, SymbolInformation, TextDocumentIdentifier, Url,
-    WorkDoneProgressParams, WorkspaceSymbolParams,
+    CallHierarchyOutgoingCall, CallHierarchyOutgoingCallsParams, CallHierarchyPrepareParams,
+    CancelParams, DidChangeTextDocumentParams, DidChangeWatchedFilesParams,
+    DidOpenTextDocumentParams, DidSaveTextDocumentParams, DocumentSymbolParams,
+    DocumentSymbolResponse, FileEvent, InitializeParams, InitializeResult, InitializedParams,
+    NumberOrString, PartialResultParams, Position, ProgressParams, ProgressParamsValue,
+    ProgressToken, SymbolInformation, TextDocumentContentChangeEvent, TextDocumentIdentifier,
+    TextDocumentItem, TextDocumentPositionParams, Url, VersionedTextDocumentIdentifier,
+    WorkDoneProgress, WorkDoneProgressParams, WorkspaceFolder, WorkspaceSymbolParams,
 };
-use serde_json::{json, Value};
+use serde_json::Value;
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream,
     process::Child,
     sync::mpsc,
+    task::JoinHandle,
+};
+
+#[cfg(unix)]
+use tokio::net::UnixStream;
+
+use crate::{
+    error::CodeDepthError,
+    lsp::json_rpc::{LspResponse, ResponseContents},
 };
 
-use crate::lsp::json_rpc::{LspResponse, ResponseContents};
+use super::json_rpc::{self, build_notification, build_request};
+
+/// amount of stderr lines kept to explain why the server exited
+const STDERR_TAIL_LINES: usize = 20;
+
+/// json rpc error code for a request of an unknown method
+const METHOD_NOT_FOUND: isize = -32601;
 
-use super::json_rpc::{self, build_notification, build_request, LspError};
+/// max time to wait for the server to acknowledge a shutdown
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub enum ServerMessage {
+    /// a json rpc message sent by the server
+    Message(Value),
+    /// a line the server wrote to its stderr
+    Stderr(String),
+    /// the server exited, no more messages will be received
+    Exited(String),
+    /// the connection to the server closed, no more messages will be received
+    Disconnected(String),
+}
 
 pub struct LspClient {
     to_server: mpsc::UnboundedSender<Vec<u8>>,
-    from_server: mpsc::UnboundedReceiver<Result<Value, Value>>,
+    from_server: mpsc::UnboundedReceiver<ServerMessage>,
     request_count: usize,
+    stderr_tail: VecDeque<String>,
+    exited: Option<CodeDepthError>,
+    shut_down: bool,
+    /// the server wasn't started for this client (e.g. one listening on a
+    /// socket), and may serve others after it, so it isn't shut down
+    attached: bool,
+    /// default timeout of each request, `None` waits forever
+    request_timeout: Option<Duration>,
+    /// tokens of the work the server reported progress for that didn't end yet
+    active_progress: HashSet<ProgressToken>,
+    /// the server reported any progress
+    seen_progress: bool,
+    progress_subscribers: Vec<mpsc::UnboundedSender<ProgressParams>>,
+    /// the version of each open document, bumped by each change
+    open_documents: HashMap<Url, i32>,
+    /// the folders the client was initialized with, reported to the server
+    /// when it asks for them
+    workspace_folders: Option<Vec<WorkspaceFolder>>,
 }
 
 impl LspClient {
     pub fn new(
         to_server: mpsc::UnboundedSender<Vec<u8>>,
-        from_server: mpsc::UnboundedReceiver<Result<Value, Value>>,
+        from_server: mpsc::UnboundedReceiver<ServerMessage>,
     ) -> Self {
         Self {
             to_server,
             from_server,
             request_count: 0,
+            stderr_tail: VecDeque::new(),
+            exited: None,
+            shut_down: false,
+            attached: false,
+            request_timeout: None,
+            active_progress: HashSet::new(),
+            seen_progress: false,
+            progress_subscribers: vec![],
+            open_documents: HashMap::new(),
+            workspace_folders: None,
         }
     }
 
+    /// Fail requests the server didn't respond to within `timeout`, instead of
+    /// waiting for them forever.
+    pub fn with_request_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
     pub fn stdio_client(server: Child) -> LspClient {
-        let (to_server, from_server) = start_io_threads(server);
+        LspClient::named_stdio_client(server, "server")
+    }
+
+    /// Like [`LspClient::stdio_client`], logging the server's stderr with its name.
+    pub fn named_stdio_client(server: Child, name: &str) -> LspClient {
+        let (to_server, from_server) = start_io_threads(server, name);
 
         LspClient::new(to_server, from_server)
     }
 
+    /// Talk to a server over any connection, e.g. a socket split into halves.
+    pub fn connection_client<R, W>(reader: R, writer: W) -> LspClient
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let (to_server, from_server) = start_connection_threads(reader, writer);
+
+        LspClient::new(to_server, from_server)
+    }
+
+    /// Connect to a server listening on TCP, e.g. a long running jdtls. The
+    /// server keeps running after the client, see [`LspClient::attached`].
+    pub fn tcp_client(stream: TcpStream) -> LspClient {
+        let (reader, writer) = stream.into_split();
+
+        LspClient::connection_client(reader, writer).attached()
+    }
+
+    /// Connect to a server listening on a unix socket, like
+    /// [`LspClient::tcp_client`].
+    #[cfg(unix)]
+    pub fn unix_socket_client(stream: UnixStream) -> LspClient {
+        let (reader, writer) = stream.into_split();
+
+        LspClient::connection_client(reader, writer).attached()
+    }
+
+    /// Leave the server running when the client is done with it, e.g. a
+    /// server shared by other clients. [`LspClient::shutdown`] and dropping the
+    /// client then only close the connection.
+    pub fn attached(mut self) -> Self {
+        self.attached = true;
+        self
+    }
+
     pub async fn notify<N: Notification>(&mut self, params: &N::Params) {
         let notification = build_notification::<N>(params);
 
@@ -52,12 +173,139 @@ impl LspClient {
             std::str::from_utf8(&notification).unwrap()
         );
 
-        self.to_server
-            .send(notification)
-            .expect("failed to send request to server");
+        if self.to_server.send(notification).is_err() {
+            warn!("Failed to send notification, server is not running");
+        }
     }
 
-    pub async fn call<R: Request>(&mut self, params: &R::Params) -> Result<R::Result, LspError> {
+    /// Send a request and wait for its response, see [`LspClient::with_request_timeout`].
+    pub async fn call<R: Request>(
+        &mut self,
+        params: &R::Params,
+    ) -> Result<R::Result, CodeDepthError> {
+        self.call_with_timeout::<R>(params, self.request_timeout)
+            .await
+    }
+
+    /// Like [`LspClient::call`], but with its own timeout. A request that timed
+    /// out is cancelled, and its late response ignored.
+    pub async fn call_with_timeout<R: Request>(
+        &mut self,
+        params: &R::Params,
+        timeout: Option<Duration>,
+    ) -> Result<R::Result, CodeDepthError> {
+        if let Some(exited) = &self.exited {
+            return Err(exited.clone());
+        }
+
+        let request_id = self.send_request::<R>(params);
+
+        let Some(timeout) = timeout else {
+            return self.receive_response::<R>(request_id).await;
+        };
+
+        match tokio::time::timeout(timeout, self.receive_response::<R>(request_id)).await {
+            Ok(result) => result,
+            Err(_) => {
+                self.cancel(request_id).await;
+
+                Err(CodeDepthError::Timeout {
+                    method: R::METHOD.to_string(),
+                    timeout,
+                })
+            }
+        }
+    }
+
+    /// Send a request for each of `params`, with at most `concurrency` of them
+    /// waiting for their response at once. Results are in the order of `params`.
+    ///
+    /// Each request has the default timeout, see [`LspClient::with_request_timeout`].
+    pub async fn call_many<R: Request>(
+        &mut self,
+        params: Vec<R::Params>,
+        concurrency: usize,
+    ) -> Vec<Result<R::Result, CodeDepthError>> {
+        let mut results = params.iter().map(|_| None).collect::<Vec<_>>();
+        let mut unsent = params.into_iter().enumerate();
+        // request id -> index of its params and the time it times out at
+        let mut pending = HashMap::new();
+
+        loop {
+            if let Some(exited) = &self.exited {
+                let pending = pending.into_values().map(|(index, _)| index);
+                for index in pending.chain(unsent.map(|(index, _)| index)) {
+                    results[index] = Some(Err(exited.clone()));
+                }
+
+                break;
+            }
+
+            while pending.len() < concurrency.max(1) {
+                let Some((index, params)) = unsent.next() else {
+                    break;
+                };
+
+                let request_id = self.send_request::<R>(&params);
+                let deadline = self
+                    .request_timeout
+                    .map(|timeout| tokio::time::Instant::now() + timeout);
+                pending.insert(request_id, (index, deadline));
+            }
+
+            if pending.is_empty() {
+                break;
+            }
+
+            let deadline = pending.values().filter_map(|(_, deadline)| *deadline).min();
+            let message = match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline, self.next_message()).await,
+                None => Ok(self.next_message().await),
+            };
+
+            match message {
+                Ok(Ok(message)) => {
+                    let Some(out) = self.handle_message(message) else {
+                        continue;
+                    };
+                    let Some((response_id, result)) = parse_response::<R>(out) else {
+                        continue;
+                    };
+
+                    match pending.remove(&response_id) {
+                        Some((index, _)) => results[index] = Some(result),
+                        None => warn!("Received unexpected response id: {}", response_id),
+                    }
+                }
+                // the exit is handled at the start of the next iteration
+                Ok(Err(_)) => {}
+                Err(_) => {
+                    let now = tokio::time::Instant::now();
+                    let timed_out = pending
+                        .iter()
+                        .filter(|(_, (_, deadline))| deadline.is_some_and(|d| d <= now))
+                        .map(|(request_id, _)| *request_id)
+                        .collect::<Vec<_>>();
+
+                    for request_id in timed_out {
+                        let (index, _) = pending.remove(&request_id).unwrap();
+                        self.cancel(request_id).await;
+                        results[index] = Some(Err(CodeDepthError::Timeout {
+                            method: R::METHOD.to_string(),
+                            timeout: self.request_timeout.unwrap_or_default(),
+                        }));
+                    }
+                }
+            }
+        }
+
+        results
+            .into_iter()
+            .map(|result| result.expect("every request got a result"))
+            .collect()
+    }
+
+    fn send_request<R: Request>(&mut self, params: &R::Params) -> usize {
         let request_id = self.request_count;
         let request = build_request::<R>(request_id, params);
         self.request_count += 1;
@@ -67,36 +315,30 @@ impl LspClient {
             std::str::from_utf8(&request).unwrap()
         );
 
-        self.to_server
-            .send(request)
-            .expect("failed to send request to server");
-
-        loop {
-            let Some(from_server) = self.from_server.recv().await else { continue };
+        // if this fails the server exited, which we'll receive when waiting
+        // for the response
+        let _ = self.to_server.send(request);
 
-            let out = match from_server {
-                Ok(out) => out,
-                Err(err) => {
-                    error!("Received error: {}", err);
-                    continue;
-                }
-            };
+        request_id
+    }
 
-            debug!(
-                "Received LSP response:\n{}",
-                serde_json::to_string_pretty(&out).unwrap()
-            );
+    async fn cancel(&mut self, request_id: usize) {
+        let params = CancelParams {
+            id: NumberOrString::Number(request_id as i32),
+        };
+        self.notify::<Cancel>(&params).await;
+    }
 
-            let lsp_response = match serde_json::from_value::<LspResponse<R::Result>>(out) {
-                Ok(response) => response,
-                Err(err) => {
-                    error!("Received malformed response, err: {}", err);
-                    continue;
-                }
+    async fn receive_response<R: Request>(
+        &mut self,
+        request_id: usize,
+    ) -> Result<R::Result, CodeDepthError> {
+        loop {
+            let message = self.next_message().await?;
+            let Some(out) = self.handle_message(message) else {
+                continue;
             };
-
-            let Some(response_id) = lsp_response.id else {
-                warn!("Received unexpected response without id");
+            let Some((response_id, result)) = parse_response::<R>(out) else {
                 continue;
             };
 
@@ -108,20 +350,176 @@ impl LspClient {
                 continue;
             }
 
-            match lsp_response.response {
-                ResponseContents::Result { result } => return Ok(result),
-                ResponseContents::Error { error } => return Err(error),
-                ResponseContents::UnknownResult { result: _ } => {
-                    error!("Received unknown result type (this is probably fatal)");
+            return result;
+        }
+    }
+
+    async fn next_message(&mut self) -> Result<Value, CodeDepthError> {
+        loop {
+            match self.from_server.recv().await {
+                Some(ServerMessage::Message(message)) => return Ok(message),
+                Some(ServerMessage::Stderr(line)) => {
+                    if self.stderr_tail.len() == STDERR_TAIL_LINES {
+                        self.stderr_tail.pop_front();
+                    }
+                    self.stderr_tail.push_back(line);
+                }
+                Some(ServerMessage::Exited(status)) => return Err(self.server_exited(status)),
+                Some(ServerMessage::Disconnected(reason)) => {
+                    let disconnected = CodeDepthError::Transport(reason);
+                    self.exited = Some(disconnected.clone());
+
+                    return Err(disconnected);
+                }
+                None => return Err(self.server_exited("unknown status".to_string())),
+            }
+        }
+    }
+
+    /// Handle the requests and notifications the server sent, returns the
+    /// responses to our requests.
+    fn handle_message(&mut self, message: Value) -> Option<Value> {
+        let Some(method) = message.get("method").and_then(Value::as_str) else {
+            return Some(message);
+        };
+
+        if message.get("id").is_some() {
+            let response = self.handle_server_request(method, &message);
+            // if this fails the server exited, which we'll receive later
+            let _ = self.to_server.send(response);
+        } else if method == Progress::METHOD {
+            self.handle_progress(message);
+        } else {
+            debug!("Ignoring LSP notification: {}", method);
+        }
+
+        None
+    }
+
+    /// Build the response to a request the server sent. Servers wait for these
+    /// responses, e.g. gopls doesn't start before it got its configuration.
+    fn handle_server_request(&mut self, method: &str, request: &Value) -> Vec<u8> {
+        debug!("Received LSP server request: {}", method);
+
+        let id = &request["id"];
+        match method {
+            // we have no configuration, let the server use its defaults
+            WorkspaceConfiguration::METHOD => {
+                let items = request["params"]["items"]
+                    .as_array()
+                    .map_or(0, |items| items.len());
+
+                json_rpc::build_response(id, &Value::Array(vec![Value::Null; items]))
+            }
+            WorkspaceFoldersRequest::METHOD => {
+                let folders = serde_json::to_value(&self.workspace_folders).unwrap_or_default();
+
+                json_rpc::build_response(id, &folders)
+            }
+            RegisterCapability::METHOD | WorkDoneProgressCreate::METHOD => {
+                json_rpc::build_response(id, &Value::Null)
+            }
+            _ => json_rpc::build_error_response(
+                id,
+                METHOD_NOT_FOUND,
+                &format!("unsupported method {}", method),
+            ),
+        }
+    }
+
+    fn handle_progress(&mut self, message: Value) {
+        let params = match serde_json::from_value::<ProgressParams>(message["params"].clone()) {
+            Ok(params) => params,
+            Err(err) => {
+                error!("Received malformed progress, err: {}", err);
+                return;
+            }
+        };
+
+        debug!("Received LSP progress: {:?}", params);
+
+        let ProgressParamsValue::WorkDone(progress) = &params.value;
+        match progress {
+            WorkDoneProgress::Begin(_) => {
+                self.seen_progress = true;
+                self.active_progress.insert(params.token.clone());
+            }
+            WorkDoneProgress::End(_) => {
+                self.active_progress.remove(&params.token);
+            }
+            WorkDoneProgress::Report(_) => {}
+        }
+
+        self.progress_subscribers
+            .retain(|subscriber| subscriber.send(params.clone()).is_ok());
+    }
+
+    /// Receive the progress notifications of the server from now on.
+    pub fn subscribe_progress(&mut self) -> mpsc::UnboundedReceiver<ProgressParams> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.progress_subscribers.push(sender);
+
+        receiver
+    }
+
+    /// Wait until all work the server reported progress for has ended, and no
+    /// new work began for `grace_period`. Servers index the project this way,
+    /// so requests sent after this see the whole project.
+    ///
+    /// Returns whether the server reported any progress, servers that never
+    /// do return after `grace_period`. Gives up waiting after `max_wait`.
+    pub async fn wait_for_progress(
+        &mut self,
+        grace_period: Duration,
+        max_wait: Duration,
+    ) -> Result<bool, CodeDepthError> {
+        let deadline = tokio::time::Instant::now() + max_wait;
+
+        loop {
+            let wait_until = if self.active_progress.is_empty() {
+                deadline.min(tokio::time::Instant::now() + grace_period)
+            } else {
+                deadline
+            };
+
+            let message = match tokio::time::timeout_at(wait_until, self.next_message()).await {
+                Ok(message) => message?,
+                Err(_) => {
+                    if !self.active_progress.is_empty() {
+                        warn!("Gave up waiting for progress {:?}", self.active_progress);
+                    }
+
+                    return Ok(self.seen_progress);
                 }
+            };
+
+            if let Some(message) = self.handle_message(message) {
+                warn!("Received unexpected message while waiting: {}", message);
             }
         }
     }
 
+    fn server_exited(&mut self, status: String) -> CodeDepthError {
+        let exited = CodeDepthError::ServerExited {
+            status,
+            stderr: self
+                .stderr_tail
+                .iter()
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join(""),
+        };
+
+        self.exited = Some(exited.clone());
+
+        exited
+    }
+
     pub async fn initialize(
         &mut self,
         params: &InitializeParams,
-    ) -> Result<InitializeResult, LspError> {
+    ) -> Result<InitializeResult, CodeDepthError> {
+        self.workspace_folders = params.workspace_folders.clone();
         let result = self.call::<Initialize>(params).await?;
 
         self.notify::<Initialized>(&InitializedParams {}).await;
@@ -129,10 +527,34 @@ impl LspClient {
         Ok(result)
     }
 
+    /// Ask the server to shut down and then to exit, waiting at most
+    /// [`SHUTDOWN_TIMEOUT`] for it to acknowledge the shutdown.
+    ///
+    /// The server is told to exit even if the shutdown failed, so it doesn't
+    /// outlive the client. [`LspClient::attached`] servers are left running.
+    pub async fn shutdown(&mut self) -> Result<(), CodeDepthError> {
+        if self.shut_down {
+            return Ok(());
+        }
+        self.shut_down = true;
+
+        if self.attached {
+            return Ok(());
+        }
+
+        let result = self
+            .call_with_timeout::<Shutdown>(&(), Some(SHUTDOWN_TIMEOUT))
+            .await;
+
+        self.notify::<Exit>(&()).await;
+
+        result
+    }
+
     pub async fn workspace_symbol(
         &mut self,
         query: &str,
-    ) -> Result<Option<Vec<SymbolInformation>>, LspError> {
+    ) -> Result<Option<Vec<SymbolInformation>>, CodeDepthError> {
         let params = WorkspaceSymbolParams {
             query: query.to_string(),
             ..Default::default()
@@ -141,10 +563,58 @@ impl LspClient {
         self.call::<WorkspaceSymbol>(&params).await
     }
 
+    /// Tell the server a document is open, with its full text.
+    pub async fn did_open(&mut self, uri: Url, language_id: &str, text: String) {
+        let params = DidOpenTextDocumentParams {
+            text_document: TextDocumentItem::new(uri.clone(), language_id.to_string(), 0, text),
+        };
+
+        self.notify::<DidOpenTextDocument>(&params).await;
+        self.open_documents.insert(uri, 0);
+    }
+
+    pub fn is_open(&self, uri: &Url) -> bool {
+        self.open_documents.contains_key(uri)
+    }
+
+    /// Tell the server an open document's full text changed on disk, and was
+    /// saved. Documents that aren't open are skipped, see
+    /// [`LspClient::did_change_watched_files`].
+    pub async fn did_change(&mut self, uri: Url, text: String) {
+        let Some(version) = self.open_documents.get_mut(&uri) else {
+            return;
+        };
+        *version += 1;
+
+        let params = DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier::new(uri.clone(), *version),
+            content_changes: vec![TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text,
+            }],
+        };
+        self.notify::<DidChangeTextDocument>(&params).await;
+
+        let params = DidSaveTextDocumentParams {
+            text_document: TextDocumentIdentifier { uri },
+            text: None,
+        };
+        self.notify::<DidSaveTextDocument>(&params).await;
+    }
+
+    /// Tell the server files changed on disk, for the files it reads itself
+    /// instead of getting their text from the client.
+    pub async fn did_change_watched_files(&mut self, changes: Vec<FileEvent>) {
+        let params = DidChangeWatchedFilesParams { changes };
+
+        self.notify::<DidChangeWatchedFiles>(&params).await;
+    }
+
     pub async fn document_symbol(
         &mut self,
         uri: Url,
-    ) -> Result<Option<DocumentSymbolResponse>, LspError> {
+    ) -> Result<Option<DocumentSymbolResponse>, CodeDepthError> {
         let params = DocumentSymbolParams {
             text_document: TextDocumentIdentifier { uri },
             partial_result_params: PartialResultParams::default(),
@@ -154,10 +624,62 @@ impl LspClient {
         self.call::<DocumentSymbolRequest>(&params).await
     }
 
+    /// Like [`LspClient::document_symbol`] for many files, see [`LspClient::call_many`].
+    pub async fn document_symbols(
+        &mut self,
+        uris: Vec<Url>,
+        concurrency: usize,
+    ) -> Vec<Result<Option<DocumentSymbolResponse>, CodeDepthError>> {
+        let params = uris
+            .into_iter()
+            .map(|uri| DocumentSymbolParams {
+                text_document: TextDocumentIdentifier { uri },
+                partial_result_params: PartialResultParams::default(),
+                work_done_progress_params: WorkDoneProgressParams::default(),
+            })
+            .collect();
+
+        self.call_many::<DocumentSymbolRequest>(params, concurrency)
+            .await
+    }
+
+    pub async fn implementation(
+        &mut self,
+        uri: Url,
+        position: Position,
+    ) -> Result<Option<GotoImplementationResponse>, CodeDepthError> {
+        let params = GotoImplementationParams {
+            text_document_position_params: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri },
+                position,
+            },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        };
+
+        self.call::<GotoImplementation>(&params).await
+    }
+
+    pub async fn prepare_call_hierarchy(
+        &mut self,
+        uri: Url,
+        position: Position,
+    ) -> Result<Option<Vec<CallHierarchyItem>>, CodeDepthError> {
+        let params = CallHierarchyPrepareParams {
+            text_document_position_params: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri },
+                position,
+            },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        };
+
+        self.call::<CallHierarchyPrepare>(&params).await
+    }
+
     pub async fn call_hierarchy_incoming_calls(
         &mut self,
         item: CallHierarchyItem,
-    ) -> Result<Option<Vec<CallHierarchyIncomingCall>>, LspError> {
+    ) -> Result<Option<Vec<CallHierarchyIncomingCall>>, CodeDepthError> {
         let params = CallHierarchyIncomingCallsParams {
             item,
             work_done_progress_params: WorkDoneProgressParams::default(),
@@ -166,75 +688,483 @@ impl LspClient {
 
         self.call::<CallHierarchyIncomingCalls>(&params).await
     }
+
+    pub async fn call_hierarchy_outgoing_calls(
+        &mut self,
+        item: CallHierarchyItem,
+    ) -> Result<Option<Vec<CallHierarchyOutgoingCall>>, CodeDepthError> {
+        let params = CallHierarchyOutgoingCallsParams {
+            item,
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        };
+
+        self.call::<CallHierarchyOutgoingCalls>(&params).await
+    }
+}
+
+/// The id and result of a response to one of our requests, `None` for
+/// responses that can't be parsed.
+fn parse_response<R: Request>(
+    response: Value,
+) -> Option<(usize, Result<R::Result, CodeDepthError>)> {
+    debug!(
+        "Received LSP response:\n{}",
+        serde_json::to_string_pretty(&response).unwrap()
+    );
+
+    let lsp_response = match serde_json::from_value::<LspResponse<R::Result>>(response) {
+        Ok(response) => response,
+        Err(err) => {
+            error!("Received malformed response, err: {}", err);
+            return None;
+        }
+    };
+
+    let Some(response_id) = lsp_response.id else {
+        warn!("Received unexpected response without id");
+        return None;
+    };
+
+    match lsp_response.response {
+        ResponseContents::Result { result } => Some((response_id, Ok(result))),
+        ResponseContents::Error { error } => Some((response_id, Err(error.into()))),
+        ResponseContents::UnknownResult { result } => Some((
+            response_id,
+            Err(CodeDepthError::UnexpectedResponse {
+                method: R::METHOD.to_string(),
+                response: result.to_string(),
+            }),
+        )),
+    }
+}
+
+impl Drop for LspClient {
+    /// Best effort shutdown for clients dropped without [`LspClient::shutdown`],
+    /// e.g. on error paths. Doesn't wait for the server to respond.
+    fn drop(&mut self) {
+        if self.shut_down || self.attached || self.exited.is_some() {
+            return;
+        }
+
+        let _ = self
+            .to_server
+            .send(build_request::<Shutdown>(self.request_count, &()));
+        let _ = self.to_server.send(build_notification::<Exit>(&()));
+    }
 }
 
 pub fn start_io_threads(
     mut server: Child,
+    name: &str,
 ) -> (
     mpsc::UnboundedSender<Vec<u8>>,
-    mpsc::UnboundedReceiver<Result<Value, Value>>,
+    mpsc::UnboundedReceiver<ServerMessage>,
 ) {
-    let (to_server, mut to_server_receiver) = mpsc::unbounded_channel::<Vec<u8>>();
+    let stdin = server
+        .stdin
+        .take()
+        .expect("failed to acquire stdin of server process");
+    let stdout = server
+        .stdout
+        .take()
+        .expect("failed to acquire stdout of server process");
+    let stderr = server
+        .stderr
+        .take()
+        .expect("failed to acquire stderr of server process");
+
+    let (out_sender, responses) = mpsc::unbounded_channel::<ServerMessage>();
+    let err_sender = out_sender.clone();
+    let exit_sender = out_sender.clone();
+
+    let (to_server, stdout_task) = start_message_tasks(stdout, stdin, out_sender);
+
+    let name = name.to_string();
+    let stderr_task = tokio::spawn(async move {
+        let mut stderr = BufReader::new(stderr);
+        let mut line = vec![];
+        while let Ok(len) = stderr.read_until(b'\n', &mut line).await {
+            if len == 0 {
+                break;
+            }
+
+            let line_str = String::from_utf8_lossy(&line).to_string();
+            // logged as soon as it's written, the client only reads it when
+            // waiting for a response
+            debug!("[{}] {}", name, line_str.trim_end());
+            let _ = err_sender.send(ServerMessage::Stderr(line_str));
+            line.clear();
+        }
+    });
+
+    // report the exit only after all output of the server was forwarded
+    tokio::spawn(async move {
+        let _ = stdout_task.await;
+        let _ = stderr_task.await;
+
+        let status = match server.wait().await {
+            Ok(status) => status.to_string(),
+            Err(e) => format!("unknown status ({})", e),
+        };
+
+        let _ = exit_sender.send(ServerMessage::Exited(status));
+    });
+
+    (to_server, responses)
+}
+
+/// Like [`start_io_threads`], but for a server reached over a connection
+/// instead of a child process's stdio.
+pub fn start_connection_threads<R, W>(
+    reader: R,
+    writer: W,
+) -> (
+    mpsc::UnboundedSender<Vec<u8>>,
+    mpsc::UnboundedReceiver<ServerMessage>,
+)
+where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    let (out_sender, responses) = mpsc::unbounded_channel::<ServerMessage>();
+    let exit_sender = out_sender.clone();
+
+    let (to_server, reader_task) = start_message_tasks(reader, writer, out_sender);
+
     tokio::spawn(async move {
-        let stdin = server
-            .stdin
-            .as_mut()
-            .take()
-            .expect("failed to acquire stdout of server process");
+        let _ = reader_task.await;
+        let _ = exit_sender.send(ServerMessage::Disconnected("connection closed".to_string()));
+    });
 
+    (to_server, responses)
+}
+
+/// Frame the messages sent to the returned sender into `writer`, and forward
+/// the messages read from `reader` to `out_sender`, until `reader` ends.
+fn start_message_tasks<R, W>(
+    reader: R,
+    mut writer: W,
+    out_sender: mpsc::UnboundedSender<ServerMessage>,
+) -> (mpsc::UnboundedSender<Vec<u8>>, JoinHandle<()>)
+where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    let (to_server, mut to_server_receiver) = mpsc::unbounded_channel::<Vec<u8>>();
+    tokio::spawn(async move {
         while let Some(buf) = to_server_receiver.recv().await {
-            stdin
-                .write_all(&buf)
-                .await
-                .expect("failed to write to stdin");
+            if let Err(e) = writer.write_all(&buf).await {
+                debug!("Failed to write to server: {}", e);
+                break;
+            }
         }
     });
 
-    let from_server = {
-        let (out_sender, responses) = mpsc::unbounded_channel::<Result<Value, Value>>();
-        let err_sender = out_sender.clone();
-
-        tokio::spawn(async move {
-            let stdout = server
-                .stdout
-                .as_mut()
-                .take()
-                .expect("failed to acquire stdout of server process");
-
-            while let Ok(buf) = json_rpc::get_next_response(stdout).await {
-                if let Ok(msg) = serde_json::from_slice::<Value>(&buf) {
-                    out_sender
-                        .send(Ok(msg))
-                        .expect("failed to send response to from_server queue");
+    let reader_task = tokio::spawn(async move {
+        let mut reader = BufReader::new(reader);
+        loop {
+            let buf = match json_rpc::get_next_response(&mut reader).await {
+                Ok(buf) => buf,
+                // a bad frame doesn't break the ones after it
+                Err(e @ CodeDepthError::Lsp(_)) => {
+                    error!("Received malformed message, err: {}", e);
+                    continue;
                 }
+                Err(_) => break,
+            };
+
+            if let Ok(msg) = serde_json::from_slice::<Value>(&buf) {
+                // the client might have been dropped, nothing to do then
+                let _ = out_sender.send(ServerMessage::Message(msg));
             }
+        }
+    });
+
+    (to_server, reader_task)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use lsp_types::{InitializeParams, ProgressParamsValue, WorkDoneProgress};
+    use serde_json::json;
+    use tokio::{
+        io::{AsyncRead, AsyncWrite},
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::LspClient;
+    use crate::{
+        error::CodeDepthError,
+        lsp::test_server::{self, read_message, respond, write_message},
+    };
+
+    // the server is a shell script
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_server_exited() {
+        let server = tokio::process::Command::new("sh")
+            .arg("-c")
+            .arg("echo 'fatal: out of memory' >&2; exit 3")
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        let mut client = LspClient::stdio_client(server);
+
+        let result = client.initialize(&InitializeParams::default()).await;
+        let Err(CodeDepthError::ServerExited { status, stderr }) = result else {
+            panic!("expected server exited error, got: {:?}", result);
+        };
+        assert!(status.contains('3'), "unexpected status: {}", status);
+        assert_eq!(stderr, "fatal: out of memory\n");
+
+        // later requests fail immediately instead of waiting for a response
+        let result = client.workspace_symbol("").await;
+        assert!(matches!(result, Err(CodeDepthError::ServerExited { .. })));
+    }
+
+    /// answer a single initialize request, then close the connection
+    async fn serve_initialize<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S) {
+        let request = read_message(&mut stream).await.unwrap();
+        assert_eq!(request["method"], "initialize");
+
+        respond(&mut stream, &request, json!({ "capabilities": {} })).await;
+    }
+
+    async fn assert_initializes_once(mut client: LspClient) {
+        let result = client.initialize(&InitializeParams::default()).await;
+        assert!(result.is_ok(), "unexpected result: {:?}", result);
+
+        let result = client.workspace_symbol("").await;
+        let Err(CodeDepthError::Transport(reason)) = result else {
+            panic!("expected transport error, got: {:?}", result);
+        };
+        assert_eq!(reason, "connection closed");
+    }
+
+    #[tokio::test]
+    async fn test_connection_client() {
+        let (client_stream, server_stream) = tokio::io::duplex(64);
+        let server = tokio::spawn(serve_initialize(server_stream));
+
+        let (reader, writer) = tokio::io::split(client_stream);
+        assert_initializes_once(LspClient::connection_client(reader, writer)).await;
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tcp_client() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            serve_initialize(listener.accept().await.unwrap().0).await;
         });
 
-        tokio::spawn(async move {
-            let stderr = server
-                .stderr
-                .as_mut()
-                .take()
-                .expect("failed to acquire stderr of server process");
+        let stream = TcpStream::connect(addr).await.unwrap();
+        assert_initializes_once(LspClient::tcp_client(stream)).await;
+        server.await.unwrap();
+    }
 
-            let mut buf = vec![];
-            while let Ok(byte) = stderr.read_u8().await {
-                buf.push(byte);
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_unix_socket_client() {
+        let (client_stream, server_stream) = tokio::net::UnixStream::pair().unwrap();
+        let server = tokio::spawn(serve_initialize(server_stream));
 
-                let Ok(err) = std::str::from_utf8(&buf) else { continue };
-                let Some(last_char) = err.chars().last() else { continue };
+        assert_initializes_once(LspClient::unix_socket_client(client_stream)).await;
+        server.await.unwrap();
+    }
 
-                if last_char == '\n' {
-                    err_sender
-                        .send(Err(json!({ "err": err })))
-                        .expect("failed to send error to from_server queue");
+    #[tokio::test]
+    async fn test_shutdown() {
+        let (mut client, server_stream) = test_server::connect();
+        let server = tokio::spawn(test_server::serve(server_stream, |_| Some(Ok(json!(null)))));
+
+        client.shutdown().await.unwrap();
+        let methods = server
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|message| message["method"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(methods, ["shutdown", "exit"]);
+    }
+
+    #[tokio::test]
+    async fn test_call_many() {
+        let (mut client, mut server_stream) = test_server::connect();
+        // a server that responds to each pair of requests in reverse order
+        let server = tokio::spawn(async move {
+            for _ in 0..2 {
+                let mut requests = vec![];
+                for _ in 0..2 {
+                    requests.push(read_message(&mut server_stream).await.unwrap());
+                }
 
-                    buf.clear();
+                for request in requests.iter().rev() {
+                    let result = json!([{
+                        "name": request["params"]["query"],
+                        "kind": 12,
+                        "location": {
+                            "uri": "file:///project/src/main.rs",
+                            "range": {
+                                "start": { "line": 0, "character": 0 },
+                                "end": { "line": 0, "character": 0 },
+                            },
+                        },
+                    }]);
+                    respond(&mut server_stream, request, result).await;
                 }
             }
         });
-        responses
-    };
 
-    (to_server, from_server)
+        let params = ["a", "b", "c", "d"]
+            .map(|query| lsp_types::WorkspaceSymbolParams {
+                query: query.to_string(),
+                ..Default::default()
+            })
+            .to_vec();
+
+        let results = tokio::time::timeout(
+            Duration::from_secs(5),
+            client.call_many::<lsp_types::request::WorkspaceSymbol>(params, 2),
+        )
+        .await
+        .expect("requests were sent concurrently");
+        server.await.unwrap();
+
+        let names = results
+            .into_iter()
+            .map(|result| result.unwrap().unwrap()[0].name.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a", "b", "c", "d"]);
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout() {
+        let (client, mut server_stream) = test_server::connect();
+        // a server that never responds
+        let server = tokio::spawn(async move {
+            let mut methods = vec![];
+            for _ in 0..2 {
+                let message = read_message(&mut server_stream).await.unwrap();
+                methods.push(message["method"].as_str().unwrap().to_string());
+            }
+
+            methods
+        });
+
+        let mut client = client.with_request_timeout(Some(Duration::from_millis(50)));
+
+        let result = client.workspace_symbol("").await;
+        let Err(CodeDepthError::Timeout { method, .. }) = result else {
+            panic!("expected timeout error, got: {:?}", result);
+        };
+        assert_eq!(method, "workspace/symbol");
+        assert_eq!(
+            server.await.unwrap(),
+            ["workspace/symbol", "$/cancelRequest"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_progress() {
+        let (mut client, mut server_stream) = test_server::connect();
+        let server = tokio::spawn(async move {
+            let progress = |kind: &str| {
+                json!({
+                    "jsonrpc": "2.0",
+                    "method": "$/progress",
+                    "params": { "token": "indexing", "value": { "kind": kind, "title": "Indexing" } },
+                })
+            };
+
+            for message in [progress("begin"), progress("report"), progress("end")] {
+                write_message(&mut server_stream, &message).await;
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+
+            server_stream
+        });
+
+        let mut progress = client.subscribe_progress();
+
+        let grace_period = Duration::from_millis(100);
+        let reported = client
+            .wait_for_progress(grace_period, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert!(reported);
+        assert!(server.is_finished());
+
+        let mut kinds = vec![];
+        while let Ok(params) = progress.try_recv() {
+            let ProgressParamsValue::WorkDone(progress) = params.value;
+            kinds.push(progress);
+        }
+        assert!(matches!(
+            kinds[..],
+            [
+                WorkDoneProgress::Begin(_),
+                WorkDoneProgress::Report(_),
+                WorkDoneProgress::End(_)
+            ]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_progress_without_progress() {
+        let (mut client, _server_stream) = test_server::connect();
+
+        let reported = client
+            .wait_for_progress(Duration::from_millis(50), Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert!(!reported);
+    }
+
+    #[tokio::test]
+    async fn test_server_requests() {
+        let (mut client, mut server_stream) = test_server::connect();
+        let server = tokio::spawn(async move {
+            let request = read_message(&mut server_stream).await.unwrap();
+
+            // requests the server sends before responding
+            let server_requests = [
+                ("workspace/configuration", json!({ "items": [{}, {}] })),
+                ("client/registerCapability", json!({ "registrations": [] })),
+                ("unknown/method", json!(null)),
+            ];
+            let mut responses = vec![];
+            for (id, (method, params)) in server_requests.into_iter().enumerate() {
+                let message = json!({
+                    "jsonrpc": "2.0",
+                    "id": format!("server-{}", id),
+                    "method": method,
+                    "params": params,
+                });
+                write_message(&mut server_stream, &message).await;
+
+                responses.push(read_message(&mut server_stream).await.unwrap());
+            }
+
+            respond(&mut server_stream, &request, json!([])).await;
+
+            responses
+        });
+
+        let result = client.workspace_symbol("").await;
+        assert!(matches!(result, Ok(Some(symbols)) if symbols.is_empty()));
+
+        let responses = server.await.unwrap();
+        assert_eq!(responses[0]["id"], "server-0");
+        assert_eq!(responses[0]["result"], json!([null, null]));
+        assert_eq!(responses[1]["result"], json!(null));
+        assert_eq!(responses[2]["error"]["code"], -32601);
+    }
 }
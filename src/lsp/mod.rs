@@ -1,4 +1,6 @@
 pub mod json_rpc;
 pub mod lsp_client;
+#[cfg(test)]
+pub(crate) mod test_server;
 
 pub use lsp_client::LspClient;
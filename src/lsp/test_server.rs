@@ -0,0 +1,67 @@
+//! Helpers for the crate's tests that play the lang server of an
+//! [`LspClient`] over an in-memory stream.
+
+use serde_json::{json, Value};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, DuplexStream};
+
+use super::{json_rpc, LspClient};
+
+/// A client connected to the returned stream, which the test reads the
+/// client's messages from and writes the server's messages to.
+pub fn connect() -> (LspClient, DuplexStream) {
+    let (client_stream, server_stream) = tokio::io::duplex(1024);
+    let (reader, writer) = tokio::io::split(client_stream);
+
+    (LspClient::connection_client(reader, writer), server_stream)
+}
+
+/// The next message the client sent, `None` once the connection is closed.
+pub async fn read_message<S: AsyncRead + Unpin>(stream: &mut S) -> Option<Value> {
+    let message = json_rpc::get_next_response(stream).await.ok()?;
+
+    Some(serde_json::from_slice(&message).unwrap())
+}
+
+/// Send `message` to the client, with its `Content-Length` header.
+pub async fn write_message<S: AsyncWrite + Unpin>(stream: &mut S, message: &Value) {
+    let message = message.to_string();
+    let message = format!("Content-Length: {}\r\n\r\n{}", message.len(), message);
+    stream.write_all(message.as_bytes()).await.unwrap();
+}
+
+/// Respond to the client's `request` with `result`.
+pub async fn respond<S: AsyncWrite + Unpin>(stream: &mut S, request: &Value, result: Value) {
+    let response = json!({ "jsonrpc": "2.0", "id": request["id"], "result": result });
+    write_message(stream, &response).await;
+}
+
+/// Answer each request of the client with the result (or the error object)
+/// `handler` returns for it, leaving the requests it returns `None` for
+/// unanswered. Runs until the client exits or disconnects, and returns every
+/// message it got.
+pub async fn serve<F>(mut stream: DuplexStream, mut handler: F) -> Vec<Value>
+where
+    F: FnMut(&Value) -> Option<Result<Value, Value>>,
+{
+    let mut messages = vec![];
+    while let Some(message) = read_message(&mut stream).await {
+        messages.push(message.clone());
+        if message["method"] == "exit" {
+            break;
+        }
+
+        // notifications have no id and get no response
+        if message.get("id").is_none() {
+            continue;
+        }
+
+        let response = match handler(&message) {
+            Some(Ok(result)) => json!({ "jsonrpc": "2.0", "id": message["id"], "result": result }),
+            Some(Err(error)) => json!({ "jsonrpc": "2.0", "id": message["id"], "error": error }),
+            None => continue,
+        };
+        write_message(&mut stream, &response).await;
+    }
+
+    messages
+}
@@ -1,64 +1,1065 @@
-use std::{collections::HashSet, path::PathBuf, process::Stdio, time::Duration};
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    fmt::Display,
+    io::{ErrorKind, IsTerminal, Write},
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::{Duration, Instant},
+};
 
-use clap::Parser;
-use log::LevelFilter;
+use clap::{CommandFactory, Parser, Subcommand};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use log::{info, warn, LevelFilter};
 use lsp_types::{CallHierarchyItem, Url};
+use notify::{RecursiveMode, Watcher};
 use regex::Regex;
-use serde_json::{json, Value};
-use tokio::process::{Child, Command};
+use serde::Serialize;
+use tokio::{
+    net::TcpStream,
+    process::{Child, Command},
+    sync::mpsc,
+};
 
-use code_depth::{hashable_call_hierarchy_item::HashableCallHierarchyItem, lsp::LspClient, Depths};
+#[cfg(unix)]
+use tokio::net::UnixStream;
+
+use code_depth::{
+    baseline::Baseline,
+    call_graph::CallGraph,
+    calls_cache::CallsCache,
+    codeowners::{CodeOwners, CODEOWNERS_LOCATIONS},
+    config_file,
+    graph_import::ImportedGraph,
+    hashable_call_hierarchy_item::HashableCallHierarchyItem,
+    lsp::LspClient,
+    parse_symbol_query,
+    preset::{parse_symbol_kind, Preset},
+    project_root::{find_project_root, is_in_project, DEFAULT_ROOT_MARKERS},
+    render::{render, OutputFormat, RenderInput},
+    report::{GroupBy, OwnerFilter, ReportOptions, SortBy},
+    snapshot::Snapshot,
+    Call, CallDirection, Depths, DiscoveryMode, DiscoveryOptions, FunctionCalls,
+    FunctionCallsOptions, InitOptions, NameFormat, NameStyle, RetryOptions, SymbolQuery,
+    SymbolQueryStrategy, DEFAULT_MAX_DEPTH, DEFAULT_QUALIFIED_NAME_SEPARATOR,
+};
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
-struct Args {
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    args_override_self = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<SubCommand>,
+
+    #[command(flatten)]
+    args: Option<Args>,
+
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+}
+
+#[derive(Subcommand, Debug)]
+enum SubCommand {
+    /// Check that the lang server can be initialized and answers a single
+    /// workspace/symbol query, without running the full analysis
+    Ping(PingArgs),
+
+    /// Query the call graph of the project, by default prints all calls
+    #[command(args_override_self = true)]
+    Graph(GraphArgs),
+
+    /// Run the analysis with two lang servers and print the calls only one
+    /// of them found
+    #[command(args_override_self = true)]
+    CompareServers(CompareServersArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct PingArgs {
     #[arg(short, long)]
     project_path: PathBuf,
 
     #[arg(short, long)]
     lang_server_exe: String,
 
+    #[arg(short, long, default_value = "")]
+    query: String,
+}
+
+#[derive(clap::Args, Debug)]
+struct GraphArgs {
+    #[command(flatten)]
+    args: Args,
+
+    /// print the minimum call depth from each function matching <FROM> to
+    /// each function matching <TO>
+    #[arg(long, requires_all = ["from", "to"])]
+    reach: bool,
+
+    #[arg(long)]
+    from: Option<String>,
+
+    #[arg(long)]
+    to: Option<String>,
+
+    /// print the functions calling this function (e.g. `src/db.rs:execute`)
+    #[arg(long, conflicts_with = "reach")]
+    callers_of: Option<String>,
+
+    /// print the functions this function (e.g. `src/main.rs:main`) calls
+    #[arg(long, conflicts_with_all = ["reach", "callers_of"])]
+    callees_of: Option<String>,
+
+    /// with `--callers-of` or `--callees-of`, also print the functions
+    /// linked to the printed functions, and so on
+    #[arg(long)]
+    transitive: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct CompareServersArgs {
+    #[command(flatten)]
+    args: Args,
+
+    /// command to start the lang server compared against the one given by
+    /// `--lang-server-exe`, `--server-addr`, `--server-socket` or the preset
+    #[arg(long)]
+    other_lang_server_exe: String,
+}
+
+#[derive(clap::Args, Debug)]
+struct Args {
+    /// defaults to the closest directory above the current one containing a
+    /// project root marker (e.g. `Cargo.toml` or `.git`). Can be repeated to
+    /// analyze several folders as workspace folders of one project
+    #[arg(short, long)]
+    project_path: Vec<PathBuf>,
+
+    /// read args from this TOML file, defaults to `code-depth.toml` in the
+    /// project path. Args given on the command line take precedence
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// defaults to the preset's lang server
+    #[arg(
+        short,
+        long,
+        required_unless_present_any = ["preset", "server_addr", "server_socket", "servers", "load_calls", "import_graph"]
+    )]
+    lang_server_exe: Option<String>,
+
+    /// connect to a lang server listening on this address (`host:port`)
+    /// instead of starting one
+    #[arg(long, conflicts_with = "lang_server_exe")]
+    server_addr: Option<String>,
+
+    /// connect to a lang server listening on this unix socket instead of
+    /// starting one
+    #[arg(long, conflicts_with_all = ["lang_server_exe", "server_addr"])]
+    server_socket: Option<PathBuf>,
+
+    /// analyze the files with these comma separated extensions with their own
+    /// lang server, as `<EXTENSIONS>=<LANG_SERVER_EXE>` (e.g. `py,pyi=pylsp`).
+    /// Can be repeated, the calls all servers find are merged into one graph
+    #[arg(
+        long = "server",
+        value_parser = parse_extension_server,
+        conflicts_with_all = ["lang_server_exe", "server_addr", "server_socket", "file_suffixes"]
+    )]
+    servers: Vec<(Vec<String>, String)>,
+
+    /// use the defaults of a known lang server setup
+    #[arg(long, value_enum)]
+    preset: Option<Preset>,
+
+    /// comma separated document symbol kinds that count as definitions,
+    /// overrides the preset's kinds (e.g. `constructor` with the clangd preset
+    /// measures how deep object construction chains go)
+    #[arg(long, value_delimiter = ',', value_parser = parse_symbol_kind)]
+    symbol_kinds: Option<Vec<lsp_types::SymbolKind>>,
+
+    /// only analyze calls to these functions, given as comma separated names
+    /// or as `@path` to a file with a name per line. Names are either short
+    /// names (`src/main.rs:foo`) or just function names (`foo`)
+    #[arg(long)]
+    functions: Option<String>,
+
+    /// comma separated extensions of the files to analyze, overrides the
+    /// preset's extensions. Without both, all discovered files are analyzed
+    #[arg(long, value_delimiter = ',')]
+    file_suffixes: Option<Vec<String>>,
+
+    /// find the files to analyze from the symbols the lang server reports, or
+    /// by scanning the project directory for files with the analyzed
+    /// extensions (requires `--file-suffixes` or `--preset`)
+    #[arg(long, value_enum, default_value_t = DiscoveryMode::WorkspaceSymbol)]
+    discovery: DiscoveryMode,
+
+    /// only analyze files matching this glob, relative to the project root,
+    /// e.g. `src/**`. Can be repeated
+    #[arg(long)]
+    include: Vec<String>,
+
+    /// don't analyze files matching this glob or under a directory matching
+    /// it, e.g. `vendor`. Can be repeated, and wins over `--include`
+    #[arg(long)]
+    exclude: Vec<String>,
+
+    /// with `--discovery filesystem`, also analyze the files `.gitignore` and
+    /// `.ignore` files exclude
+    #[arg(long)]
+    no_ignore: bool,
+
+    /// comma separated `workspace/symbol` queries finding the files to
+    /// analyze: `empty` for the empty query, `alphabet` for a query per
+    /// letter, or a fixed query. Defaults to `#,empty,alphabet`
+    #[arg(long, value_delimiter = ',', value_parser = parse_symbol_query)]
+    symbol_query: Option<Vec<SymbolQuery>>,
+
     #[arg(short, long, default_value = ".*test.*")]
     ignore_re: Option<String>,
 
-    #[arg(short, long, action = clap::ArgAction::Count)]
-    verbose: u8,
+    /// only analyze calls where the caller or the callee matches this regex,
+    /// applied before `--ignore-re`
+    #[arg(long)]
+    include_re: Option<String>,
+
+    /// drop edges from a function to itself
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+    drop_self_loops: bool,
+
+    #[arg(long, alias = "output-format", value_enum, default_value_t = OutputFormat::Json)]
+    format: OutputFormat,
+
+    /// write the output to this file instead of stdout, creating its parent
+    /// directories if needed
+    #[arg(long, alias = "output")]
+    output_file: Option<PathBuf>,
+
+    /// describe each function once in a node table and reference it by a
+    /// stable id everywhere else
+    #[arg(long)]
+    normalized_output: bool,
+
+    /// write JSON output on a single line instead of indented, for piping to
+    /// other tools
+    #[arg(long)]
+    compact: bool,
+
+    /// include the source line of each call along the reported paths
+    #[arg(long)]
+    show_snippets: bool,
+
+    /// max amount of files read from disk at once
+    #[arg(long, default_value_t = 16)]
+    io_concurrency: usize,
+
+    /// max amount of document symbol requests sent to the lang server before
+    /// it responded to them
+    #[arg(long, default_value_t = 8)]
+    concurrency: usize,
+
+    /// measure depths from the functions matching this regex (e.g. `:main$`)
+    /// instead of from the functions without callers
+    #[arg(long)]
+    root_re: Option<String>,
+
+    /// merge all roots matching this regex into a single root, so depth is
+    /// measured from one entry point instead of each of them
+    #[arg(long)]
+    merge_roots_re: Option<String>,
+
+    /// group the report by this key
+    #[arg(long, value_enum)]
+    group_by: Option<GroupBy>,
+
+    /// order of the reported functions
+    #[arg(long, value_enum, default_value_t = SortBy::Name)]
+    sort_by: SortBy,
+
+    /// include statistics beyond the basic summary, e.g. the median depth, and
+    /// per group stats when grouping
+    #[arg(long)]
+    with_stats: bool,
+
+    /// times to retry a call hierarchy request the server cancelled
+    #[arg(long, default_value_t = 3)]
+    retries: u32,
+
+    /// delay before the first retry in milliseconds, doubled on each retry
+    #[arg(long, default_value_t = 100)]
+    retry_backoff_ms: u64,
+
+    /// build the call graph from the callers (incoming) or the callees
+    /// (outgoing) of each function
+    #[arg(long, value_enum, default_value_t = CallDirection::Incoming, conflicts_with = "check_directions")]
+    direction: CallDirection,
+
+    /// also build the call graph from outgoing calls, and report functions
+    /// whose depth differs between the two graphs
+    #[arg(long)]
+    check_directions: bool,
+
+    /// only report functions whose max depth is more than this many levels
+    /// deeper than their min depth as problems
+    #[arg(long, default_value_t = 0)]
+    depth_tolerance: usize,
+
+    /// max depth difference between the incoming and outgoing call graphs
+    /// allowed by `--check-directions`
+    #[arg(long, default_value_t = 0, requires = "check_directions")]
+    direction_tolerance: usize,
+
+    /// describe each reported path with its weight (amount of call sites along
+    /// it), and whether it crosses a cycle or a function with missing callers
+    #[arg(long)]
+    path_metadata: bool,
+
+    /// wait this long after initializing the lang server before discovering
+    /// files, e.g. `30s` or `500ms`, for servers with a known indexing time
+    #[arg(long, value_parser = parse_duration)]
+    index_wait: Option<Duration>,
+
+    /// server specific `initializationOptions` to initialize the lang server
+    /// with, as JSON or the path of a JSON file
+    #[arg(long, value_name = "JSON|PATH")]
+    init_options: Option<String>,
+
+    /// how long to wait for the lang server to index the project while
+    /// discovering files, e.g. `30s` or `2m`. This is the budget of the whole
+    /// wait, not a per-request timeout, see `--request-timeout`
+    #[arg(long, value_parser = parse_duration, default_value = "60s")]
+    max_duration: Duration,
+
+    /// give up on a request the lang server didn't respond to in this long,
+    /// e.g. `30s`. Functions whose calls timed out are analyzed without them
+    #[arg(long, value_parser = parse_duration)]
+    request_timeout: Option<Duration>,
+
+    /// only report problems in files owned by this owner (e.g. `@my-team`)
+    /// according to the project's CODEOWNERS file
+    #[arg(long)]
+    owner: Option<String>,
+
+    /// only report problems in files changed since this git ref (e.g.
+    /// `origin/main`), per `git diff`. The depths still come from the whole
+    /// project
+    #[arg(long)]
+    changed_since: Option<String>,
+
+    /// don't report the problems listed in this file, written by
+    /// `--write-baseline`, so only new problems are reported and fail the run
+    #[arg(long)]
+    baseline: Option<PathBuf>,
+
+    /// write the problems found to this file, for suppressing them in later
+    /// runs with `--baseline`
+    #[arg(long, conflicts_with = "baseline")]
+    write_baseline: Option<PathBuf>,
+
+    /// save the calls the lang server finds to this file, so later runs can
+    /// skip querying the server with `--load-calls`
+    #[arg(long)]
+    save_calls: Option<PathBuf>,
+
+    /// analyze the calls saved by `--save-calls` instead of querying the lang
+    /// server. The filters (e.g. `--ignore-re`) still apply
+    #[arg(long, conflicts_with_all = ["save_calls", "check_directions"])]
+    load_calls: Option<PathBuf>,
+
+    /// analyze the call graph in this file, made by another tool, instead of
+    /// querying a lang server. See the README for the format
+    #[arg(long, conflicts_with_all = ["save_calls", "load_calls", "check_directions"])]
+    import_graph: Option<PathBuf>,
+
+    /// write the depths and problems of each function to this file, for
+    /// comparing later runs against with `--diff`
+    #[arg(long)]
+    write_snapshot: Option<PathBuf>,
+
+    /// instead of the analysis, print the problems added and removed since
+    /// the run that wrote this snapshot, and the functions whose depths
+    /// changed. Supports `--format json` and `--format text`
+    #[arg(long)]
+    diff: Option<PathBuf>,
+
+    /// never report functions without callers as problems
+    #[arg(long)]
+    exclude_roots_from_problems: bool,
+
+    /// only report the functions whose depths differ on their own, not the
+    /// functions they call that inherit their differing depths
+    #[arg(long)]
+    root_causes_only: bool,
+
+    /// exit with a nonzero code when problems are found, for use as a CI check
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+    fail_on_problems: bool,
+
+    /// exit with 0 even when problems are found, same as `--fail-on-problems false`
+    #[arg(long, conflicts_with = "fail_on_problems")]
+    no_fail: bool,
+
+    /// name functions by just their name (`src/cache.rs:get`), like earlier
+    /// versions did, instead of qualifying it with the names of the symbols
+    /// containing the function (`src/cache.rs:Cache.get`)
+    #[arg(long)]
+    unqualified_names: bool,
+
+    /// separator between the path, the function name and the location in
+    /// short names, defaults to `:` (`#` on windows)
+    #[arg(long)]
+    name_separator: Option<String>,
+
+    /// what short names are made of, `location` appends the line and column
+    /// of each function (`src/main.rs:main:42:8`)
+    #[arg(long, value_enum, default_value_t = NameStyle::Compact)]
+    name_format: NameStyle,
+
+    /// abort if any call path is deeper than this, such depths usually mean
+    /// a cycle or a misconfiguration. Only takes effect below `--max-depth`,
+    /// as deeper paths aren't followed
+    #[arg(long)]
+    max_depth_abort: Option<usize>,
+
+    /// stop following call paths at this depth, the functions they stop at
+    /// are marked as truncated in the report
+    #[arg(long, default_value_t = DEFAULT_MAX_DEPTH)]
+    max_depth: usize,
+
+    /// report at most this many paths per function, the shortest, the longest
+    /// and a sample of the others
+    #[arg(long)]
+    max_paths: Option<usize>,
+
+    /// only report this function (e.g. `src/db.rs:execute`) and the functions
+    /// on its paths from the roots, instead of the whole project
+    #[arg(long)]
+    target: Option<String>,
+
+    /// merge overriding methods into the method they override, using the lang
+    /// server's implementations. Defaults to the preset's choice, or false
+    #[arg(long, action = clap::ArgAction::Set)]
+    unify_overrides: Option<bool>,
+
+    /// keep the lang server running and analyze again each time a file
+    /// changes, overwriting the previous output. Runs until interrupted
+    #[arg(long, conflicts_with_all = ["check_directions", "load_calls", "import_graph", "diff"])]
+    watch: bool,
 }
 
-impl Args {
-    fn unpack() -> (Url, String, Regex, LevelFilter) {
-        let args = Args::parse();
+/// how long the watched files must stay unchanged before analyzing again,
+/// editors and formatters often write a file several times in a row
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// exit code used when the given arguments are invalid
+const INVALID_ARGS_EXIT_CODE: i32 = 2;
+
+/// exit code used when the analysis found problems, see `--fail-on-problems`
+const PROBLEMS_FOUND_EXIT_CODE: i32 = 1;
+
+/// how to reach the lang server
+#[derive(Debug, Clone)]
+enum LangServer {
+    /// command starting the server, which is then talked to over stdio
+    Exe(String),
+    /// address of a server listening on TCP
+    Tcp(String),
+    /// path of a unix socket a server is listening on
+    Unix(PathBuf),
+}
+
+impl Display for LangServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LangServer::Exe(exe) => write!(f, "{}", exe),
+            LangServer::Tcp(addr) => write!(f, "tcp://{}", addr),
+            LangServer::Unix(path) => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+/// a lang server and the extensions of the files it analyzes
+#[derive(Debug, Clone)]
+struct ServerConfig {
+    lang_server: LangServer,
+    /// `None` for the extensions of `--file-suffixes` or the preset
+    extensions: Option<Vec<String>>,
+}
+
+/// validated [`Args`]
+struct Config {
+    project_url: Url,
+    lang_servers: Vec<ServerConfig>,
+    test_re: Regex,
+    include_re: Option<Regex>,
+    root_re: Option<Regex>,
+    merge_roots_re: Option<Regex>,
+    calls_options: FunctionCallsOptions,
+    discovery: DiscoveryOptions,
+    format: OutputFormat,
+    output_file: Option<PathBuf>,
+    normalized_output: bool,
+    compact: bool,
+    show_snippets: bool,
+    io_concurrency: usize,
+    max_depth_abort: Option<usize>,
+    max_depth: usize,
+    max_paths: Option<usize>,
+    target: Option<String>,
+    watch: bool,
+    group_by: Option<GroupBy>,
+    sort_by: SortBy,
+    with_stats: bool,
+    direction: CallDirection,
+    check_directions: bool,
+    direction_tolerance: usize,
+    depth_tolerance: usize,
+    name_format: NameFormat,
+    exclude_roots_from_problems: bool,
+    root_causes_only: bool,
+    fail_on_problems: bool,
+    path_metadata: bool,
+    index_wait: Option<Duration>,
+    init_options: InitOptions,
+    max_duration: Duration,
+    request_timeout: Option<Duration>,
+    problems_owner: Option<OwnerFilter>,
+    problems_files: Option<HashSet<Url>>,
+    baseline: Option<Baseline>,
+    write_baseline: Option<PathBuf>,
+    write_snapshot: Option<PathBuf>,
+    diff: Option<Snapshot>,
+    save_calls: Option<PathBuf>,
+    load_calls: Option<PathBuf>,
+    import_graph: Option<PathBuf>,
+    unify_overrides: bool,
+}
+
+impl Config {
+    /// the lang server of a run that supports only one, exits if `--server`
+    /// configured several
+    fn single_lang_server(&self, run: &str) -> &ServerConfig {
+        match self.lang_servers.as_slice() {
+            [lang_server] => lang_server,
+            _ => exit_with_invalid_args(&format!("{} supports a single lang server", run)),
+        }
+    }
+}
 
-        let project_path = args
-            .project_path
-            .canonicalize()
-            .expect("given <project_path> couldn't be canonicalized");
+impl Args {
+    fn unpack(self) -> Result<Config, String> {
+        let args = self;
 
-        let project_url =
-            Url::from_file_path(project_path).expect("failed to convert project path to URL");
+        let project_paths = match args.project_path.as_slice() {
+            [] => vec![infer_project_path(args.preset)?],
+            project_paths => project_paths.to_vec(),
+        };
+        let project_path = common_project_path(&project_paths)?;
+        let project_url = to_project_url(&project_path)?;
+        // a single folder is the project itself
+        let workspace_folders = match project_paths.as_slice() {
+            [_] => vec![],
+            project_paths => project_paths
+                .iter()
+                .map(|project_path| to_project_url(project_path))
+                .collect::<Result<Vec<_>, _>>()?,
+        };
 
-        let lang_server_exe = args.lang_server_exe;
+        let lang_server = if let Some(addr) = args.server_addr {
+            Some(LangServer::Tcp(addr))
+        } else if let Some(path) = args.server_socket {
+            Some(LangServer::Unix(path))
+        } else if let Some(exe) = args.lang_server_exe {
+            Some(LangServer::Exe(exe))
+        } else if args.servers.is_empty() {
+            args.preset
+                .map(|preset| LangServer::Exe(preset.lang_server_exe().to_string()))
+        } else {
+            None
+        };
+        let lang_servers = match lang_server {
+            Some(lang_server) => vec![ServerConfig {
+                lang_server,
+                extensions: None,
+            }],
+            None if !args.servers.is_empty() => args
+                .servers
+                .into_iter()
+                .map(|(extensions, exe)| ServerConfig {
+                    lang_server: LangServer::Exe(exe),
+                    extensions: Some(extensions),
+                })
+                .collect(),
+            // the calls are loaded instead of collected from a lang server
+            None if args.load_calls.is_some() || args.import_graph.is_some() => vec![],
+            None => return Err("no lang server exe or preset given".to_string()),
+        };
+        if lang_servers.len() > 1 && args.check_directions {
+            return Err("--check-directions supports a single lang server".to_string());
+        }
 
         let test_re = if let Some(test_str) = args.ignore_re {
-            Regex::new(&test_str).unwrap_or_else(|_| panic!("invalid regex: '{}'", test_str))
+            parse_regex(&test_str)?
         } else {
             Regex::new(".*test.*").unwrap()
         };
 
-        let verbose: LevelFilter = match args.verbose {
-            0 => LevelFilter::Off,
-            1 => LevelFilter::Info,
-            2 => LevelFilter::Debug,
-            _ => LevelFilter::Trace,
+        let include_re = args.include_re.as_deref().map(parse_regex).transpose()?;
+        let root_re = args.root_re.as_deref().map(parse_regex).transpose()?;
+
+        let merge_roots_re = args
+            .merge_roots_re
+            .as_deref()
+            .map(parse_regex)
+            .transpose()?;
+
+        if args.diff.is_some() && !matches!(args.format, OutputFormat::Json | OutputFormat::Text) {
+            return Err("--diff supports only --format json or --format text".to_string());
+        }
+
+        if args.format == OutputFormat::Sqlite {
+            if !cfg!(feature = "sqlite") {
+                return Err("--format sqlite requires building with the sqlite feature".to_string());
+            }
+
+            if args.output_file.is_none() {
+                return Err("--format sqlite requires --output-file".to_string());
+            }
+        }
+
+        if args.group_by == Some(GroupBy::Module) && !args.with_stats {
+            return Err("--group-by module requires --with-stats".to_string());
+        }
+
+        let name_format = match args.name_separator {
+            Some(separator) if separator.is_empty() => {
+                return Err("name separator can't be empty".to_string())
+            }
+            Some(separator) => NameFormat {
+                separator,
+                style: args.name_format,
+            },
+            None => NameFormat {
+                style: args.name_format,
+                ..Default::default()
+            },
+        };
+
+        let problems_owner = args
+            .owner
+            .map(|owner| read_owner_filter(&project_path, owner))
+            .transpose()?;
+        let problems_files = args
+            .changed_since
+            .map(|git_ref| read_changed_files(&project_url, &git_ref))
+            .transpose()?;
+
+        let defaults = FunctionCallsOptions::default();
+        let calls_options = FunctionCallsOptions {
+            drop_self_loops: args.drop_self_loops,
+            symbol_kinds: args
+                .symbol_kinds
+                .or_else(|| args.preset.map(|preset| preset.symbol_kinds()))
+                .unwrap_or(defaults.symbol_kinds),
+            qualified_name_separator: (!args.unqualified_names).then(|| {
+                args.preset
+                    .and_then(|preset| preset.qualified_name_separator())
+                    .unwrap_or(DEFAULT_QUALIFIED_NAME_SEPARATOR)
+                    .to_string()
+            }),
+            retry: RetryOptions {
+                max_retries: args.retries,
+                backoff: Duration::from_millis(args.retry_backoff_ms),
+            },
+            functions: args.functions.as_deref().map(parse_functions).transpose()?,
+            name_format: name_format.clone(),
+            io_concurrency: args.io_concurrency,
+            concurrency: args.concurrency,
+            workspace_folders: workspace_folders.clone(),
+        };
+
+        let discovery = DiscoveryOptions {
+            mode: args.discovery,
+            extensions: args.file_suffixes.or_else(|| {
+                args.preset.map(|preset| {
+                    preset
+                        .file_suffixes()
+                        .iter()
+                        .map(|suffix| suffix.to_string())
+                        .collect()
+                })
+            }),
+            include: parse_globs(&args.include)?,
+            exclude: parse_globs(&args.exclude)?,
+            no_ignore: args.no_ignore,
+            symbol_query: args
+                .symbol_query
+                .map(SymbolQueryStrategy)
+                .unwrap_or_default(),
+            workspace_folders: workspace_folders.clone(),
         };
+        if discovery.mode == DiscoveryMode::Filesystem && discovery.extensions.is_none() {
+            return Err("--discovery filesystem requires --file-suffixes or --preset".to_string());
+        }
+
+        Ok(Config {
+            project_url,
+            lang_servers,
+            test_re,
+            include_re,
+            root_re,
+            merge_roots_re,
+            calls_options,
+            discovery,
+            format: args.format,
+            output_file: args.output_file,
+            normalized_output: args.normalized_output,
+            compact: args.compact,
+            show_snippets: args.show_snippets,
+            io_concurrency: args.io_concurrency,
+            max_depth_abort: args.max_depth_abort,
+            max_depth: args.max_depth,
+            max_paths: args.max_paths,
+            target: args.target,
+            watch: args.watch,
+            group_by: args.group_by,
+            sort_by: args.sort_by,
+            with_stats: args.with_stats,
+            direction: args.direction,
+            check_directions: args.check_directions,
+            direction_tolerance: args.direction_tolerance,
+            depth_tolerance: args.depth_tolerance,
+            name_format,
+            exclude_roots_from_problems: args.exclude_roots_from_problems,
+            root_causes_only: args.root_causes_only,
+            fail_on_problems: args.fail_on_problems && !args.no_fail,
+            path_metadata: args.path_metadata,
+            index_wait: args.index_wait,
+            init_options: InitOptions {
+                initialization_options: args
+                    .init_options
+                    .as_deref()
+                    .map(parse_init_options)
+                    .transpose()?,
+                workspace_folders,
+            },
+            max_duration: args.max_duration,
+            request_timeout: args.request_timeout,
+            problems_owner,
+            problems_files,
+            baseline: args.baseline.as_deref().map(read_baseline).transpose()?,
+            write_baseline: args.write_baseline,
+            write_snapshot: args.write_snapshot,
+            diff: args.diff.as_deref().map(read_snapshot).transpose()?,
+            save_calls: args.save_calls,
+            load_calls: args.load_calls,
+            import_graph: args.import_graph,
+            unify_overrides: args
+                .unify_overrides
+                .or_else(|| args.preset.map(|preset| preset.unify_overrides()))
+                .unwrap_or(false),
+        })
+    }
+}
+
+fn parse_regex(re: &str) -> Result<Regex, String> {
+    Regex::new(re).map_err(|e| format!("invalid regex '{}': {}", re, e))
+}
+
+fn parse_globs(patterns: &[String]) -> Result<GlobSet, String> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern).map_err(|e| format!("invalid glob '{}': {}", pattern, e))?);
+    }
 
-        (project_url, lang_server_exe, test_re, verbose)
+    builder
+        .build()
+        .map_err(|e| format!("invalid globs {:?}: {}", patterns, e))
+}
+
+fn read_owner_filter(project_path: &Path, owner: String) -> Result<OwnerFilter, String> {
+    let path = CodeOwners::find(project_path).ok_or_else(|| {
+        format!(
+            "--owner requires a CODEOWNERS file in the project, looked in: {}",
+            CODEOWNERS_LOCATIONS.join(", ")
+        )
+    })?;
+    let contents = std::fs::read_to_string(&path)
+        .map_err(|e| format!("failed to read '{}': {}", path.display(), e))?;
+
+    Ok(OwnerFilter {
+        codeowners: CodeOwners::parse(&contents),
+        owner,
+    })
+}
+
+/// The files under the project changed since `git_ref`, committed or not.
+fn read_changed_files(project_url: &Url, git_ref: &str) -> Result<HashSet<Url>, String> {
+    let project_path = project_url
+        .to_file_path()
+        .map_err(|()| "--changed-since requires a local project path".to_string())?;
+    let output = std::process::Command::new("git")
+        .args(["diff", "--name-only", "--relative", git_ref, "--"])
+        .current_dir(&project_path)
+        .output()
+        .map_err(|e| format!("failed to run git diff: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "git diff against '{}' failed: {}",
+            git_ref,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            Url::from_file_path(project_path.join(line))
+                .map_err(|()| format!("changed file '{}' can't be converted to a URL", line))
+        })
+        .collect()
+}
+
+/// `initializationOptions` given inline as JSON, or in a JSON file.
+fn parse_init_options(init_options: &str) -> Result<serde_json::Value, String> {
+    if init_options.trim_start().starts_with('{') {
+        return serde_json::from_str(init_options)
+            .map_err(|e| format!("invalid --init-options: {}", e));
     }
+
+    let contents = std::fs::read_to_string(init_options)
+        .map_err(|e| format!("failed to read init options '{}': {}", init_options, e))?;
+
+    serde_json::from_str(&contents)
+        .map_err(|e| format!("invalid init options '{}': {}", init_options, e))
+}
+
+fn read_baseline(path: &Path) -> Result<Baseline, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("failed to read baseline '{}': {}", path.display(), e))?;
+
+    serde_json::from_str(&contents)
+        .map_err(|e| format!("invalid baseline '{}': {}", path.display(), e))
+}
+
+fn read_snapshot(path: &Path) -> Result<Snapshot, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("failed to read snapshot '{}': {}", path.display(), e))?;
+
+    serde_json::from_str(&contents)
+        .map_err(|e| format!("invalid snapshot '{}': {}", path.display(), e))
+}
+
+/// parse a duration like `500ms`, `30s` or `2m`, plain numbers are seconds
+fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let split = duration
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(duration.len());
+    let (amount, unit) = duration.split_at(split);
+
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| format!("invalid duration '{}'", duration))?;
+
+    match unit {
+        "ms" => Ok(Duration::from_millis(amount)),
+        "" | "s" => Ok(Duration::from_secs(amount)),
+        "m" => Ok(Duration::from_secs(amount * 60)),
+        _ => Err(format!(
+            "invalid duration unit '{}', expected one of: ms, s, m",
+            unit
+        )),
+    }
+}
+
+/// parse `--server`, e.g. `py,pyi=pylsp` to the extensions and the server
+fn parse_extension_server(server: &str) -> Result<(Vec<String>, String), String> {
+    let (extensions, exe) = server
+        .split_once('=')
+        .ok_or_else(|| format!("expected <EXTENSIONS>=<LANG_SERVER_EXE>, got '{}'", server))?;
+
+    let extensions = extensions
+        .split(',')
+        .map(|extension| extension.trim().trim_start_matches('.').to_string())
+        .filter(|extension| !extension.is_empty())
+        .collect::<Vec<_>>();
+    if extensions.is_empty() || exe.trim().is_empty() {
+        return Err(format!(
+            "expected <EXTENSIONS>=<LANG_SERVER_EXE>, got '{}'",
+            server
+        ));
+    }
+
+    Ok((extensions, exe.trim().to_string()))
+}
+
+fn parse_functions(functions: &str) -> Result<HashSet<String>, String> {
+    let names = match functions.strip_prefix('@') {
+        Some(path) => std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read functions file '{}': {}", path, e))?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect(),
+        None => functions
+            .split(',')
+            .map(|name| name.trim().to_string())
+            .collect(),
+    };
+
+    Ok(names)
+}
+
+fn infer_project_path(preset: Option<Preset>) -> Result<PathBuf, String> {
+    let markers = preset.map_or(DEFAULT_ROOT_MARKERS, |preset| preset.root_markers());
+    let current_dir = std::env::current_dir()
+        .map_err(|e| format!("current directory is not accessible: {}", e))?;
+
+    find_project_root(&current_dir, markers).ok_or_else(|| {
+        format!(
+            "no project path given and no project root marker ({}) found above '{}'",
+            markers.join(", "),
+            current_dir.display()
+        )
+    })
+}
+
+/// The project path of several workspace folders, the closest directory
+/// containing all of them.
+fn common_project_path(project_paths: &[PathBuf]) -> Result<PathBuf, String> {
+    let [first, rest @ ..] = project_paths else {
+        return Err("no project path given".to_string());
+    };
+    if rest.is_empty() {
+        return Ok(first.clone());
+    }
+
+    let mut common = canonicalize_project_path(first)?;
+    for project_path in rest {
+        let project_path = canonicalize_project_path(project_path)?;
+        while !project_path.starts_with(&common) {
+            if !common.pop() {
+                return Err(format!(
+                    "project paths '{}' and '{}' have no common directory",
+                    first.display(),
+                    project_path.display()
+                ));
+            }
+        }
+    }
+
+    Ok(common)
+}
+
+fn canonicalize_project_path(project_path: &Path) -> Result<PathBuf, String> {
+    project_path.canonicalize().map_err(|e| match e.kind() {
+        ErrorKind::NotFound => format!("project path '{}' does not exist", project_path.display()),
+        _ => format!(
+            "project path '{}' is not accessible: {}",
+            project_path.display(),
+            e
+        ),
+    })
+}
+
+fn to_project_url(project_path: &Path) -> Result<Url, String> {
+    let canonical_path = canonicalize_project_path(project_path)?;
+
+    Url::from_file_path(&canonical_path).map_err(|_| {
+        format!(
+            "project path '{}' can't be converted to a URL",
+            canonical_path.display()
+        )
+    })
+}
+
+fn exit_with_invalid_args(message: &str) -> ! {
+    eprintln!("error: {}", message);
+    std::process::exit(INVALID_ARGS_EXIT_CODE);
+}
+
+fn exit_with_error(context: &str, error: impl Display) -> ! {
+    eprintln!("error: {}: {}", context, error);
+    std::process::exit(1);
+}
+
+/// `raw_args` with the args of the config file inserted before the analysis
+/// args, so the ones given on the command line override them.
+fn with_config_file_args(raw_args: Vec<OsString>) -> Vec<OsString> {
+    // the command line alone may miss required args the config file has
+    let matches = match Cli::command()
+        .ignore_errors(true)
+        .try_get_matches_from(&raw_args)
+    {
+        Ok(matches) => matches,
+        Err(_) => return raw_args,
+    };
+    let (subcommand, matches) = match matches.subcommand() {
+        Some((name @ ("graph" | "compare-servers"), matches)) => (Some(name), matches),
+        Some(_) => return raw_args,
+        None => (None, &matches),
+    };
+
+    let path = match matches.get_one::<PathBuf>("config") {
+        Some(path) => path.clone(),
+        None => {
+            let project_paths = matches
+                .get_many::<PathBuf>("project_path")
+                .map(|project_paths| project_paths.cloned().collect::<Vec<_>>());
+            let project_path = match project_paths {
+                Some(project_paths) => common_project_path(&project_paths).ok(),
+                None => infer_project_path(matches.get_one::<Preset>("preset").copied()).ok(),
+            };
+            match project_path.and_then(|project_path| config_file::find(&project_path)) {
+                Some(path) => path,
+                None => return raw_args,
+            }
+        }
+    };
+
+    let contents = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+        exit_with_invalid_args(&format!(
+            "config file '{}' is not readable: {}",
+            path.display(),
+            e
+        ))
+    });
+    let command = <Args as clap::Args>::augment_args(clap::Command::new("code_depth"));
+    let file_args = config_file::to_cli_args(&contents, &command).unwrap_or_else(|e| {
+        exit_with_invalid_args(&format!("invalid config file '{}': {}", path.display(), e))
+    });
+
+    let insert_at = subcommand
+        .and_then(|name| raw_args.iter().skip(1).position(|arg| arg == name))
+        .map_or(1, |position| position + 2);
+    let mut args = raw_args;
+    args.splice(
+        insert_at..insert_at,
+        file_args.into_iter().map(OsString::from),
+    );
+
+    args
 }
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
-    let (project_url, lang_server_exe, test_re, log_level) = Args::unpack();
+    let cli = Cli::parse_from(with_config_file_args(std::env::args_os().collect()));
+
+    let log_level: LevelFilter = match cli.verbose {
+        0 => LevelFilter::Off,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
 
     simple_logger::SimpleLogger::new()
         .with_level(log_level)
@@ -66,88 +1067,835 @@ async fn main() {
         .init()
         .unwrap();
 
-    let server = run_cmd(&lang_server_exe).await;
-    let mut client = LspClient::stdio_client(server);
+    match (cli.command, cli.args) {
+        (Some(SubCommand::Ping(ping_args)), _) => ping(ping_args).await,
+        (Some(SubCommand::Graph(graph_args)), _) => graph(graph_args).await,
+        (Some(SubCommand::CompareServers(compare_args)), _) => compare_servers(compare_args).await,
+        (None, Some(args)) => analyze(args).await,
+        (None, None) => unreachable!("clap requires either a subcommand or the analysis args"),
+    }
+}
+
+async fn ping(args: PingArgs) {
+    let project_url =
+        to_project_url(&args.project_path).unwrap_or_else(|e| exit_with_invalid_args(&e));
+
+    let server = start_lang_server(&args.lang_server_exe).await;
+    let mut client = LspClient::named_stdio_client(server, server_name(&args.lang_server_exe));
+
+    let start = Instant::now();
+    code_depth::init(&mut client, project_url)
+        .await
+        .unwrap_or_else(|e| exit_with_error("failed to init lang server", e));
+    println!("initialized lang server in {:?}", start.elapsed());
+
+    let start = Instant::now();
+    match client.workspace_symbol(&args.query).await {
+        Ok(symbols) => println!(
+            "got {} symbols for query '{}' in {:?}",
+            symbols.map(|symbols| symbols.len()).unwrap_or_default(),
+            args.query,
+            start.elapsed()
+        ),
+        Err(e) => println!(
+            "got error for query '{}' in {:?}: {}",
+            args.query,
+            start.elapsed(),
+            e
+        ),
+    }
 
-    let response = code_depth::init(&mut client, project_url.clone()).await;
+    shutdown_client(client).await;
+}
 
-    response.expect("failed to init lang server");
+async fn analyze(args: Args) {
+    let config = args.unpack().unwrap_or_else(|e| exit_with_invalid_args(&e));
+    let project_url = &config.project_url;
 
-    let workspace_files =
-        code_depth::get_workspace_files(&mut client, &project_url, Duration::from_secs(5))
-            .await
-            .unwrap();
+    if config.watch {
+        watch(&config).await;
+        return;
+    }
 
-    let calls = code_depth::get_function_calls(&mut client, &workspace_files, &project_url)
+    let (calls, reverse_depths) = if config.check_directions {
+        let lang_server = config.single_lang_server("--check-directions");
+        let (mut client, workspace_files) = start_client(&config, lang_server).await;
+        let calls = code_depth::collect_function_calls(
+            &mut client,
+            &workspace_files,
+            project_url,
+            &config.calls_options,
+        )
         .await
-        .unwrap();
+        .unwrap_or_else(|e| exit_with_error("failed to get function calls", e));
+        let outgoing_calls = code_depth::get_function_callees(
+            &mut client,
+            &workspace_files,
+            project_url,
+            &config.calls_options,
+        )
+        .await
+        .unwrap_or_else(|e| exit_with_error("failed to get outgoing function calls", e));
 
-    let non_test_calls = filter_calls(calls, &test_re, |call: &CallHierarchyItem| {
-        code_depth::build_call_hierarchy_item_name(call, &project_url)
-    });
+        let calls = FunctionCalls {
+            calls: unify_overrides(&config, &mut client, calls.calls).await,
+            ..calls
+        };
+        let outgoing_calls = unify_overrides(&config, &mut client, outgoing_calls).await;
+        shutdown_client(client).await;
 
-    let depths = code_depth::get_function_depths(non_test_calls);
-    let results_json = build_results_json(&depths, &project_url);
+        let outgoing_calls = process_calls(&config, outgoing_calls);
+        let reverse_depths = code_depth::get_function_depths(&outgoing_calls);
 
-    println!("{}", serde_json::to_string_pretty(&results_json).unwrap());
+        (process_function_calls(&config, calls), Some(reverse_depths))
+    } else {
+        (get_calls(&config).await, None)
+    };
+
+    let num_problems = analyze_calls(&config, calls, reverse_depths).await;
+    if config.fail_on_problems && num_problems > 0 {
+        std::process::exit(PROBLEMS_FOUND_EXIT_CODE);
+    }
 }
 
-async fn run_cmd(cmd: &str) -> Child {
-    let cmd_parts = cmd.split_ascii_whitespace().collect::<Vec<_>>();
+/// Analyze the project again each time one of its files changes, keeping the
+/// lang server running between runs so it doesn't index the project again.
+/// Runs until interrupted.
+async fn watch(config: &Config) {
+    let lang_server = config.single_lang_server("--watch");
+    let project_path = config
+        .project_url
+        .to_file_path()
+        .unwrap_or_else(|()| exit_with_invalid_args("--watch requires a local project path"));
 
-    let mut child = Command::new(cmd_parts[0]);
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let mut watcher =
+        notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+            // reading the files to analyze them emits access events
+            Ok(event) if event.kind.is_access() => {}
+            Ok(event) => {
+                let _ = events_tx.send(event.paths);
+            }
+            Err(e) => warn!("failed to watch files: {}", e),
+        })
+        .unwrap_or_else(|e| exit_with_error("failed to watch the project", e));
+    watcher
+        .watch(&project_path, RecursiveMode::Recursive)
+        .unwrap_or_else(|e| exit_with_error("failed to watch the project", e));
 
-    let mut child = child
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped());
+    let (mut client, workspace_files) = start_client(config, lang_server).await;
+
+    let runs = async {
+        let mut workspace_files = workspace_files;
+        loop {
+            let calls = collect_calls(config, &mut client, &workspace_files).await;
+            if config.output_file.is_none() && std::io::stdout().is_terminal() {
+                // clear the screen, so it only shows the latest run
+                print!("\x1b[2J\x1b[H");
+            }
+            analyze_calls(config, process_function_calls(config, calls), None).await;
 
-    if cmd_parts.len() > 1 {
-        child = child.args(cmd_parts.iter().skip(1).collect::<Vec<_>>())
+            let extensions = server_extensions(config, lang_server);
+            let changed = wait_for_changes(
+                &mut events,
+                &config.project_url,
+                &workspace_files,
+                extensions.as_deref(),
+            )
+            .await;
+            if changed.is_empty() {
+                break;
+            }
+            info!("{} files changed, analyzing again", changed.len());
+            code_depth::notify_changed_files(&mut client, &changed, config.io_concurrency).await;
+
+            // files may have been added or removed
+            workspace_files = discover_files(config, lang_server, &mut client).await;
+        }
     };
 
-    child
-        .spawn()
-        .unwrap_or_else(|_| panic!("failed to run: '{}'", cmd))
+    let mut interrupted = tokio::spawn(tokio::signal::ctrl_c());
+    tokio::select! {
+        _ = runs => {}
+        _ = &mut interrupted => {}
+    }
+
+    shutdown_client(client).await;
 }
 
-fn build_results_json(depths: &Depths<CallHierarchyItem>, project_url: &Url) -> Value {
-    let mut results_json = json!({});
+/// The files changed by the next batch of file events, once no event came for
+/// [`WATCH_DEBOUNCE`]. These are the workspace files, and the project's other
+/// files with one of `extensions`, e.g. new source files. Empty only if the
+/// watcher stopped.
+async fn wait_for_changes(
+    events: &mut mpsc::UnboundedReceiver<Vec<PathBuf>>,
+    project_url: &Url,
+    workspace_files: &HashSet<Url>,
+    extensions: Option<&[String]>,
+) -> HashSet<Url> {
+    let to_changed_files = |paths: Vec<PathBuf>| {
+        let (known, others): (HashSet<_>, HashSet<_>) = paths
+            .into_iter()
+            .filter_map(|path| Url::from_file_path(path).ok())
+            .filter(|file| is_in_project(file, project_url))
+            .partition(|file| workspace_files.contains(file));
+        // without extensions, nothing tells which other files are analyzed
+        let added = extensions
+            .map(|extensions| code_depth::filter_files_by_suffix(others, extensions))
+            .unwrap_or_default();
+
+        known.into_iter().chain(added)
+    };
+
+    let mut changed = HashSet::new();
+    while changed.is_empty() {
+        let Some(paths) = events.recv().await else {
+            return changed;
+        };
+        changed.extend(to_changed_files(paths));
+    }
+
+    while let Ok(Some(paths)) = tokio::time::timeout(WATCH_DEBOUNCE, events.recv()).await {
+        changed.extend(to_changed_files(paths));
+    }
 
-    results_json["ok"] = json!({});
-    results_json["problems"] = json!({});
+    changed
+}
+
+/// Analyze the calls and write the analysis, returning its amount of problems.
+async fn analyze_calls(
+    config: &Config,
+    calls: FunctionCalls,
+    reverse_depths: Option<Depths<CallHierarchyItem>>,
+) -> usize {
+    let project_url = &config.project_url;
+    let FunctionCalls {
+        mut calls,
+        incomplete,
+        mut definitions,
+    } = calls;
 
-    // find all items with different depths
-    let problem_items =
-        code_depth::find_items_with_different_depths::<_, HashableCallHierarchyItem>(depths)
+    let roots = config.root_re.as_ref().map(|root_re| {
+        let roots = calls
             .iter()
-            .map(|item| code_depth::build_call_hierarchy_item_name(&item.0, project_url))
+            .flat_map(|call| [&call.caller, &call.callee])
+            .filter(|item| root_re.is_match(&config.name_format.build(item, project_url)))
+            .map(|item| HashableCallHierarchyItem(item.clone()))
             .collect::<HashSet<_>>();
+        if roots.is_empty() {
+            warn!("no function matches --root-re '{}'", root_re);
+        }
 
-    code_depth::build_short_fn_depths(project_url, depths)
-        .iter()
-        .for_each(|(item_name, item_depths_from_roots)| {
-            let item_depths_from_roots = serde_json::to_value(item_depths_from_roots).unwrap();
+        roots
+    });
+    let (mut depths, truncation) = code_depth::get_truncated_function_depths(
+        &calls,
+        roots.as_ref(),
+        config.max_depth_abort,
+        config.max_depth,
+    )
+    .unwrap_or_else(|e| exit_with_error("analysis aborted", e));
+    if !truncation.is_empty() {
+        warn!(
+            "call paths truncated at max depth {}: {} functions truncated, {} cut off",
+            config.max_depth,
+            truncation.truncated.len(),
+            truncation.cut_off.len()
+        );
+    }
+    if let Some(target) = &config.target {
+        depths = code_depth::get_target_depths::<_, HashableCallHierarchyItem>(depths, |item| {
+            config.name_format.matches(item, project_url, target)
+        });
+        if depths.is_empty() {
+            warn!("no function is named --target '{}'", target);
+        }
 
-            if problem_items.contains(item_name) {
-                results_json["problems"][item_name] = item_depths_from_roots;
-            } else {
-                results_json["ok"][item_name] = item_depths_from_roots;
-            }
+        // the other functions aren't reported, not even as unreachable
+        let reported = depths
+            .iter()
+            .map(|(item, _)| HashableCallHierarchyItem(item.clone()))
+            .collect::<HashSet<_>>();
+        let is_reported = |item: &CallHierarchyItem| reported.contains(&item.clone().into());
+        calls.retain(|call| is_reported(&call.caller) && is_reported(&call.callee));
+        definitions.retain(is_reported);
+    }
+    let report_options = ReportOptions {
+        snippets: if config.show_snippets {
+            Some(code_depth::snippets::read_call_snippets(&calls, config.io_concurrency).await)
+        } else {
+            None
+        },
+        group_by: config.group_by,
+        sort_by: config.sort_by,
+        with_stats: config.with_stats,
+        reverse_depths,
+        direction_tolerance: config.direction_tolerance,
+        depth_tolerance: config.depth_tolerance,
+        name_format: config.name_format.clone(),
+        exclude_roots_from_problems: config.exclude_roots_from_problems,
+        root_causes_only: config.root_causes_only,
+        path_metadata: config.path_metadata,
+        incomplete_items: incomplete.into_iter().map(Into::into).collect(),
+        definitions,
+        problems_owner: config.problems_owner.clone(),
+        problems_files: config.problems_files.clone(),
+        roots,
+        baseline: config.baseline.clone(),
+        truncation,
+        max_paths: config.max_paths,
+    };
+
+    if let Some(write_baseline) = &config.write_baseline {
+        let problems =
+            code_depth::report::find_problems(&calls, &depths, project_url, &report_options);
+        let baseline = Baseline::new(problems, project_url);
+        std::fs::write(
+            write_baseline,
+            format!("{}\n", serde_json::to_string_pretty(&baseline).unwrap()),
+        )
+        .unwrap_or_else(|e| exit_with_error("failed to write baseline", e));
+    }
+
+    if config.write_snapshot.is_some() || config.diff.is_some() {
+        write_snapshot_diff(config, &calls, &depths, &report_options)
+    } else {
+        write_analysis(config, &calls, &depths, &report_options)
+    }
+}
+
+/// Write the analysis in the configured format, returning its amount of problems.
+fn write_analysis(
+    config: &Config,
+    calls: &[Call],
+    depths: &Depths<CallHierarchyItem>,
+    report_options: &ReportOptions,
+) -> usize {
+    let project_url = &config.project_url;
+
+    if let Some(parent) = config.output_file.as_ref().and_then(|path| path.parent()) {
+        std::fs::create_dir_all(parent).unwrap_or_else(|e| {
+            let context = format!("failed to create output directory {}", parent.display());
+            exit_with_error(&context, e)
         });
+    }
+
+    #[cfg(feature = "sqlite")]
+    if config.format == OutputFormat::Sqlite {
+        let output_file = config.output_file.as_ref().unwrap();
+        let report = code_depth::report::build_report(calls, depths, project_url, report_options);
+        code_depth::sqlite::write_database(
+            output_file,
+            calls,
+            depths,
+            &report,
+            project_url,
+            &config.name_format,
+        )
+        .unwrap_or_else(|e| exit_with_error("failed to write database", e));
+
+        return report.summary.num_problems;
+    }
+
+    if config.normalized_output && config.format == OutputFormat::Json {
+        let report =
+            code_depth::report::build_normalized_report(calls, depths, project_url, report_options);
+        write_output(config, &to_json(config, &report));
+
+        return report.summary.num_problems;
+    }
+
+    let report = code_depth::report::build_report(calls, depths, project_url, report_options);
+    let input = RenderInput {
+        report: &report,
+        calls,
+        depths,
+        root: project_url,
+        name_format: &config.name_format,
+        compact: config.compact,
+        group_by: config.group_by,
+    };
+    let rendered = match &config.output_file {
+        Some(output_file) => std::fs::File::create(output_file)
+            .map(std::io::BufWriter::new)
+            .and_then(|mut writer| {
+                render(config.format, &input, &mut writer)?;
+                writer.flush()
+            }),
+        None => render(config.format, &input, &mut std::io::stdout().lock()),
+    };
+    rendered.unwrap_or_else(|e| exit_with_error("failed to write output", e));
+
+    report.summary.num_problems
+}
+
+/// Write the snapshot of the analysis and its diff if configured to, returning
+/// the amount of problems, or of added problems when diffing.
+fn write_snapshot_diff(
+    config: &Config,
+    calls: &[Call],
+    depths: &Depths<CallHierarchyItem>,
+    report_options: &ReportOptions,
+) -> usize {
+    let project_url = &config.project_url;
+    let problems = code_depth::report::find_problems(calls, depths, project_url, report_options);
+    let snapshot = Snapshot::new(depths, problems, project_url, &config.name_format);
+
+    if let Some(write_snapshot) = &config.write_snapshot {
+        std::fs::write(
+            write_snapshot,
+            format!("{}\n", serde_json::to_string_pretty(&snapshot).unwrap()),
+        )
+        .unwrap_or_else(|e| exit_with_error("failed to write snapshot", e));
+    }
+
+    let Some(old_snapshot) = &config.diff else {
+        return write_analysis(config, calls, depths, report_options);
+    };
+    let diff = code_depth::snapshot::diff_snapshots(old_snapshot, &snapshot);
+    match config.format {
+        OutputFormat::Text => write_output(config, diff.to_text().trim_end()),
+        _ => write_output(config, &to_json(config, &diff)),
+    }
+
+    diff.added_problems.len()
+}
+
+/// `value` as JSON, on a single line with `--compact`
+fn to_json(config: &Config, value: &impl Serialize) -> String {
+    if config.compact {
+        serde_json::to_string(value).unwrap()
+    } else {
+        serde_json::to_string_pretty(value).unwrap()
+    }
+}
+
+fn write_output(config: &Config, output: &str) {
+    match &config.output_file {
+        Some(output_file) => std::fs::write(output_file, format!("{}\n", output))
+            .unwrap_or_else(|e| exit_with_error("failed to write output file", e)),
+        None => println!("{}", output),
+    }
+}
+
+/// [`CallGraph::callers_of`] or [`CallGraph::callees_of`]
+type LinkedFn = for<'a> fn(&'a CallGraph, &CallHierarchyItem, bool) -> Vec<&'a CallHierarchyItem>;
+
+async fn graph(args: GraphArgs) {
+    let from_re = args.from.as_deref().map(parse_regex).transpose();
+    let to_re = args.to.as_deref().map(parse_regex).transpose();
+    let (from_re, to_re) = match (from_re, to_re) {
+        (Ok(from_re), Ok(to_re)) => (from_re, to_re),
+        (Err(e), _) | (_, Err(e)) => exit_with_invalid_args(&e),
+    };
+
+    let config = args
+        .args
+        .unpack()
+        .unwrap_or_else(|e| exit_with_invalid_args(&e));
+    let project_url = &config.project_url;
+    let name = |item: &CallHierarchyItem| config.name_format.build(item, project_url);
+
+    let calls = get_calls(&config).await.calls;
+
+    let linked_query = match (&args.callers_of, &args.callees_of) {
+        (Some(function), _) => Some(("--callers-of", function, CallGraph::callers_of as LinkedFn)),
+        (_, Some(function)) => Some(("--callees-of", function, CallGraph::callees_of as LinkedFn)),
+        (None, None) => None,
+    };
+    if let Some((flag, function, linked)) = linked_query {
+        let graph = CallGraph::new(calls);
+        // overloads can share a name
+        let mut seen = HashSet::new();
+        let items = graph
+            .calls()
+            .iter()
+            .flat_map(|call| [&call.caller, &call.callee])
+            .filter(|item| config.name_format.matches(item, project_url, function))
+            .filter(|item| seen.insert(HashableCallHierarchyItem((*item).clone())))
+            .collect::<Vec<_>>();
+        if items.is_empty() {
+            warn!("no function is named {} '{}'", flag, function);
+        }
+
+        let mut printed = HashSet::new();
+        for item in items {
+            for linked_item in linked(&graph, item, args.transitive) {
+                if printed.insert(HashableCallHierarchyItem(linked_item.clone())) {
+                    println!("{}", name(linked_item));
+                }
+            }
+        }
+
+        return;
+    }
+
+    if !args.reach {
+        for call in &calls {
+            println!("{} -> {}", name(&call.caller), name(&call.callee));
+        }
+
+        return;
+    }
+
+    let (from_re, to_re) = (from_re.unwrap(), to_re.unwrap());
+    let matrix = code_depth::reachability::get_reachability(
+        &calls,
+        |item| from_re.is_match(&name(item)),
+        |item| to_re.is_match(&name(item)),
+    );
+
+    let header = matrix.targets.iter().map(name).collect::<Vec<_>>();
+    println!("from\\to\t{}", header.join("\t"));
+    for (source, distances) in matrix.sources.iter().zip(matrix.distances) {
+        let cells = distances
+            .iter()
+            .map(|distance| match distance {
+                Some(distance) => distance.to_string(),
+                None => "-".to_string(),
+            })
+            .collect::<Vec<_>>();
+        println!("{}\t{}", name(source), cells.join("\t"));
+    }
+}
+
+async fn compare_servers(args: CompareServersArgs) {
+    let config = args
+        .args
+        .unpack()
+        .unwrap_or_else(|e| exit_with_invalid_args(&e));
+    let first_server = config.single_lang_server("compare-servers");
+    let second_server = &ServerConfig {
+        lang_server: LangServer::Exe(args.other_lang_server_exe),
+        ..first_server.clone()
+    };
+
+    let (first_calls, second_calls) = futures::join!(
+        get_calls_with(&config, first_server),
+        get_calls_with(&config, second_server)
+    );
+    let first_calls = process_function_calls(&config, first_calls);
+    let second_calls = process_function_calls(&config, second_calls);
+
+    let diff = code_depth::compare::diff_calls(&first_calls.calls, &second_calls.calls);
+    let report = code_depth::compare::build_comparison_report(
+        &diff,
+        &first_server.lang_server.to_string(),
+        &second_server.lang_server.to_string(),
+        &config.project_url,
+        &config.name_format,
+    );
+
+    println!("{}", to_json(&config, &report));
+}
+
+/// run the lang servers over the project, or load their calls from
+/// `--load-calls` or `--import-graph`, and collect the filtered calls. The
+/// calls of each server are merged into one graph
+async fn get_calls(config: &Config) -> FunctionCalls {
+    let calls = match (&config.load_calls, &config.import_graph) {
+        (Some(path), _) => read_calls_cache(path, &config.project_url),
+        (_, Some(path)) => FunctionCalls {
+            calls: read_imported_graph(path, config),
+            ..Default::default()
+        },
+        (None, None) => {
+            let calls = config
+                .lang_servers
+                .iter()
+                .map(|lang_server| get_calls_with(config, lang_server));
+
+            futures::future::join_all(calls).await.into_iter().collect()
+        }
+    };
+
+    if let Some(path) = &config.save_calls {
+        let cache = CallsCache::new(calls.clone(), &config.project_url);
+        std::fs::write(path, serde_json::to_string(&cache).unwrap())
+            .unwrap_or_else(|e| exit_with_error("failed to save calls", e));
+    }
+
+    process_function_calls(config, calls)
+}
 
-    results_json
+fn read_calls_cache(path: &Path, project_url: &Url) -> FunctionCalls {
+    let contents = std::fs::read_to_string(path).unwrap_or_else(|e| {
+        exit_with_error(
+            &format!("failed to read calls from '{}'", path.display()),
+            e,
+        )
+    });
+    let cache: CallsCache = serde_json::from_str(&contents).unwrap_or_else(|e| {
+        exit_with_error(
+            &format!("failed to parse calls from '{}'", path.display()),
+            e,
+        )
+    });
+
+    cache
+        .into_calls(project_url)
+        .unwrap_or_else(|e| exit_with_error(&format!("can't load '{}'", path.display()), e))
+}
+
+fn read_imported_graph(path: &Path, config: &Config) -> Vec<Call> {
+    let contents = std::fs::read_to_string(path).unwrap_or_else(|e| {
+        exit_with_error(
+            &format!("failed to read graph from '{}'", path.display()),
+            e,
+        )
+    });
+    let graph: ImportedGraph = serde_json::from_str(&contents).unwrap_or_else(|e| {
+        exit_with_error(
+            &format!("failed to parse graph from '{}'", path.display()),
+            e,
+        )
+    });
+
+    graph
+        .into_calls(&config.project_url, &config.name_format)
+        .unwrap_or_else(|e| exit_with_error(&format!("can't import '{}'", path.display()), e))
+}
+
+/// the unfiltered calls a single lang server finds, see [`get_calls`]
+async fn get_calls_with(config: &Config, lang_server: &ServerConfig) -> FunctionCalls {
+    let (mut client, workspace_files) = start_client(config, lang_server).await;
+    let calls = collect_calls(config, &mut client, &workspace_files).await;
+    shutdown_client(client).await;
+
+    calls
 }
 
+/// the unfiltered calls in `workspace_files`, in the configured direction
+async fn collect_calls(
+    config: &Config,
+    client: &mut LspClient,
+    workspace_files: &HashSet<Url>,
+) -> FunctionCalls {
+    let calls = match config.direction {
+        CallDirection::Incoming => code_depth::collect_function_calls(
+            client,
+            workspace_files,
+            &config.project_url,
+            &config.calls_options,
+        )
+        .await
+        .unwrap_or_else(|e| exit_with_error("failed to get function calls", e)),
+        CallDirection::Outgoing => code_depth::collect_function_callees(
+            client,
+            workspace_files,
+            &config.project_url,
+            &config.calls_options,
+        )
+        .await
+        .unwrap_or_else(|e| exit_with_error("failed to get function callees", e)),
+    };
+    FunctionCalls {
+        calls: unify_overrides(config, client, calls.calls).await,
+        ..calls
+    }
+}
+
+/// start the lang server and discover the workspace files to analyze
+async fn start_client(config: &Config, server: &ServerConfig) -> (LspClient, HashSet<Url>) {
+    let project_url = &config.project_url;
+
+    let mut client = match &server.lang_server {
+        LangServer::Exe(exe) => {
+            LspClient::named_stdio_client(start_lang_server(exe).await, server_name(exe))
+        }
+        LangServer::Tcp(addr) => {
+            let stream = TcpStream::connect(addr)
+                .await
+                .unwrap_or_else(|e| exit_with_error("failed to connect to lang server", e));
+
+            LspClient::tcp_client(stream)
+        }
+        #[cfg(unix)]
+        LangServer::Unix(path) => {
+            let stream = UnixStream::connect(path)
+                .await
+                .unwrap_or_else(|e| exit_with_error("failed to connect to lang server", e));
+
+            LspClient::unix_socket_client(stream)
+        }
+        #[cfg(not(unix))]
+        LangServer::Unix(_) => exit_with_invalid_args("unix sockets are only supported on unix"),
+    }
+    .with_request_timeout(config.request_timeout);
+
+    let directions: &[CallDirection] = if config.check_directions {
+        &[CallDirection::Incoming, CallDirection::Outgoing]
+    } else {
+        &[config.direction]
+    };
+    code_depth::init_with_options(
+        &mut client,
+        project_url.clone(),
+        directions,
+        &config.init_options,
+    )
+    .await
+    .unwrap_or_else(|e| {
+        exit_with_error(
+            &format!("failed to init lang server {}", server.lang_server),
+            e,
+        )
+    });
+
+    if let Some(index_wait) = config.index_wait {
+        tokio::time::sleep(index_wait).await;
+    }
+
+    let workspace_files = discover_files(config, server, &mut client).await;
+
+    (client, workspace_files)
+}
+
+/// the extensions of the files the lang server analyzes, `None` for all files
+fn server_extensions(config: &Config, server: &ServerConfig) -> Option<Vec<String>> {
+    server
+        .extensions
+        .clone()
+        .or_else(|| config.discovery.extensions.clone())
+}
+
+/// discover the workspace files the lang server analyzes
+async fn discover_files(
+    config: &Config,
+    server: &ServerConfig,
+    client: &mut LspClient,
+) -> HashSet<Url> {
+    let discovery = DiscoveryOptions {
+        extensions: server_extensions(config, server),
+        ..config.discovery.clone()
+    };
+    code_depth::discover_workspace_files(
+        client,
+        &config.project_url,
+        &discovery,
+        config.max_duration,
+    )
+    .await
+    .unwrap_or_else(|e| exit_with_error("failed to get workspace files", e))
+}
+
+/// shut the lang server down, or just disconnect from one listening on a
+/// socket, a server failing to doesn't fail the run
+async fn shutdown_client(mut client: LspClient) {
+    if let Err(e) = client.shutdown().await {
+        warn!("failed to shut down lang server: {}", e);
+    }
+}
+
+/// merge overriding methods into their base methods if configured to
+async fn unify_overrides(config: &Config, client: &mut LspClient, calls: Vec<Call>) -> Vec<Call> {
+    if !config.unify_overrides {
+        return calls;
+    }
+
+    let overrides = code_depth::overrides::get_overrides(client, &calls)
+        .await
+        .unwrap_or_else(|e| exit_with_error("failed to get overrides", e));
+
+    code_depth::overrides::unify_overrides(calls, &overrides)
+}
+
+/// filter and merge the calls found by the lang server like [`process_calls`],
+/// and drop the definitions its filters exclude
+fn process_function_calls(config: &Config, calls: FunctionCalls) -> FunctionCalls {
+    let definitions = calls
+        .definitions
+        .into_iter()
+        .filter(|item| {
+            let name = config.name_format.build(item, &config.project_url);
+            let included = config
+                .include_re
+                .as_ref()
+                .is_none_or(|include_re| include_re.is_match(&name));
+
+            included && !config.test_re.is_match(&name)
+        })
+        .collect();
+
+    FunctionCalls {
+        calls: process_calls(config, calls.calls),
+        incomplete: calls.incomplete,
+        definitions,
+    }
+}
+
+fn process_calls(config: &Config, calls: Vec<Call>) -> Vec<Call> {
+    let project_url = &config.project_url;
+
+    let non_test_calls = filter_calls(
+        calls,
+        config.include_re.as_ref(),
+        &config.test_re,
+        |item: &CallHierarchyItem| config.name_format.build(item, project_url),
+    );
+
+    if let Some(merge_roots_re) = &config.merge_roots_re {
+        code_depth::merge_roots(non_test_calls, project_url, |item| {
+            merge_roots_re.is_match(&config.name_format.build(item, project_url))
+        })
+    } else {
+        non_test_calls
+    }
+}
+
+/// name of the lang server started by `cmd`, without its args
+fn server_name(cmd: &str) -> &str {
+    cmd.split_ascii_whitespace().next().unwrap_or(cmd)
+}
+
+/// why a lang server command couldn't be run
+enum RunCmdError {
+    /// the command has no program, e.g. `-l ""`
+    Empty,
+    Spawn(std::io::Error),
+}
+
+async fn run_cmd(cmd: &str) -> Result<Child, RunCmdError> {
+    let mut cmd_parts = cmd.split_ascii_whitespace();
+    let program = cmd_parts.next().ok_or(RunCmdError::Empty)?;
+
+    Command::new(program)
+        .args(cmd_parts)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(RunCmdError::Spawn)
+}
+
+/// start the lang server `cmd`, exiting with an error if it can't be run
+async fn start_lang_server(cmd: &str) -> Child {
+    run_cmd(cmd).await.unwrap_or_else(|e| match e {
+        RunCmdError::Empty => exit_with_invalid_args("the lang server command is empty"),
+        RunCmdError::Spawn(e) => exit_with_error(&format!("failed to run '{}'", cmd), e),
+    })
+}
+
+/// Keep the calls whose caller or callee matches `include_re` (all calls if
+/// it isn't given), then drop the calls whose caller or callee matches
+/// `test_re`, a call made from a test and a call into a test are both test calls.
 fn filter_calls<F: Fn(&CallHierarchyItem) -> String>(
-    calls: Vec<(CallHierarchyItem, CallHierarchyItem)>,
+    calls: Vec<Call>,
+    include_re: Option<&Regex>,
     test_re: &Regex,
     item_to_str: F,
-) -> Vec<(CallHierarchyItem, CallHierarchyItem)> {
+) -> Vec<Call> {
     calls
         .into_iter()
-        .filter(|(to, from)| {
-            !(test_re.is_match(&item_to_str(to)) || test_re.is_match(&item_to_str(from)))
+        .filter(|call| {
+            include_re.is_none_or(|include_re| {
+                include_re.is_match(&item_to_str(&call.caller))
+                    || include_re.is_match(&item_to_str(&call.callee))
+            })
+        })
+        .filter(|call| {
+            !(test_re.is_match(&item_to_str(&call.caller))
+                || test_re.is_match(&item_to_str(&call.callee)))
         })
         .collect::<Vec<_>>()
 }
@@ -0,0 +1,160 @@
+use std::collections::{HashMap, HashSet};
+
+use log::debug;
+use lsp_types::{CallHierarchyItem, GotoDefinitionResponse, Location, SymbolKind};
+
+use crate::{
+    hashable_call_hierarchy_item::HashableCallHierarchyItem, lsp::LspClient, Call, CodeDepthError,
+};
+
+/// Maps each overriding method to the base method it overrides.
+pub type Overrides = HashMap<HashableCallHierarchyItem, CallHierarchyItem>;
+
+/// Find the overrides of the methods in `calls`, using the implementations the
+/// server reports for each method.
+///
+/// Only implementations that are themselves part of `calls` are found.
+pub async fn get_overrides(
+    client: &mut LspClient,
+    calls: &[Call],
+) -> Result<Overrides, CodeDepthError> {
+    let mut methods = calls
+        .iter()
+        .flat_map(|call| [&call.caller, &call.callee])
+        .filter(|item| item.kind == SymbolKind::METHOD)
+        .map(|item| HashableCallHierarchyItem(item.clone()))
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    methods.sort_by_key(|item| (item.0.uri.to_string(), item.0.selection_range.start));
+
+    let mut overrides = Overrides::new();
+    for method in &methods {
+        let response = client
+            .implementation(method.0.uri.clone(), method.0.selection_range.start)
+            .await;
+
+        let locations = match response {
+            Ok(Some(response)) => to_locations(response),
+            Ok(None) => continue,
+            Err(CodeDepthError::Lsp(e)) => {
+                debug!(
+                    "failed to get implementations of {}: {:?}",
+                    method.0.name, e
+                );
+                continue;
+            }
+            Err(e @ CodeDepthError::Timeout { .. }) => {
+                debug!("failed to get implementations of {}: {}", method.0.name, e);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+
+        for location in locations {
+            let implementation = methods
+                .iter()
+                .find(|item| item.0.uri == location.uri && contains(&item.0, &location));
+
+            if let Some(implementation) = implementation.filter(|&item| item != method) {
+                overrides.insert(implementation.clone(), method.0.clone());
+            }
+        }
+    }
+
+    Ok(overrides)
+}
+
+fn to_locations(response: GotoDefinitionResponse) -> Vec<Location> {
+    match response {
+        GotoDefinitionResponse::Scalar(location) => vec![location],
+        GotoDefinitionResponse::Array(locations) => locations,
+        GotoDefinitionResponse::Link(links) => links
+            .into_iter()
+            .map(|link| Location::new(link.target_uri, link.target_selection_range))
+            .collect(),
+    }
+}
+
+fn contains(item: &CallHierarchyItem, location: &Location) -> bool {
+    item.range.start <= location.range.start && location.range.end <= item.range.end
+}
+
+/// Attribute every call to or from an override to the base method it
+/// overrides, so a method hierarchy is a single node of the graph.
+///
+/// Calls between an override and its base become self loops and are dropped.
+pub fn unify_overrides(calls: Vec<Call>, overrides: &Overrides) -> Vec<Call> {
+    let to_base = |item: CallHierarchyItem| {
+        let mut item = item;
+        let mut seen = HashSet::<HashableCallHierarchyItem>::new();
+        while let Some(base) = overrides.get(&HashableCallHierarchyItem(item.clone())) {
+            // guard against servers reporting methods as implementing each other
+            if !seen.insert(base.clone().into()) {
+                break;
+            }
+
+            item = base.clone();
+        }
+
+        item
+    };
+
+    calls
+        .into_iter()
+        .map(|call| Call {
+            caller: to_base(call.caller),
+            callee: to_base(call.callee),
+            from_ranges: call.from_ranges,
+        })
+        .filter(|call| {
+            HashableCallHierarchyItem(call.caller.clone())
+                != HashableCallHierarchyItem(call.callee.clone())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{CallHierarchyItem, SymbolKind, Url};
+
+    use super::{unify_overrides, Overrides};
+    use crate::test_util::{self, call};
+
+    fn item(name: &str, line: u32) -> CallHierarchyItem {
+        CallHierarchyItem {
+            kind: SymbolKind::METHOD,
+            uri: Url::parse("file:///project/src/Main.java").unwrap(),
+            ..test_util::item(name, line)
+        }
+    }
+
+    #[test]
+    fn test_unify_overrides() {
+        let (main, base, derived, derived_2, helper) = (
+            item("main", 0),
+            item("Base.run", 1),
+            item("Derived.run", 2),
+            item("MoreDerived.run", 3),
+            item("helper", 4),
+        );
+        let overrides: Overrides = [
+            (derived.clone().into(), base.clone()),
+            (derived_2.clone().into(), derived.clone()),
+        ]
+        .into();
+        let calls = vec![
+            call(&main, &base),
+            call(&derived, &helper),
+            call(&derived_2, &derived),
+            call(&main, &derived_2),
+        ];
+
+        let unified = unify_overrides(calls, &overrides);
+
+        assert_eq!(
+            unified,
+            vec![call(&main, &base), call(&base, &helper), call(&main, &base)]
+        );
+    }
+}
@@ -0,0 +1,112 @@
+use clap::ValueEnum;
+use lsp_types::SymbolKind;
+
+/// Known lang server setups, each preset picks the defaults that make sense
+/// for the server and the language it analyzes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Preset {
+    RustAnalyzer,
+    Jdtls,
+    Clangd,
+}
+
+impl Preset {
+    /// command used to start the lang server
+    pub fn lang_server_exe(&self) -> &'static str {
+        match self {
+            Preset::RustAnalyzer => "rust-analyzer",
+            Preset::Jdtls => "jdtls",
+            Preset::Clangd => "clangd",
+        }
+    }
+
+    /// files or directories marking the project root, in order of preference
+    pub fn root_markers(&self) -> &'static [&'static str] {
+        match self {
+            Preset::RustAnalyzer => &["Cargo.toml", ".git"],
+            Preset::Jdtls => &["pom.xml", "build.gradle", "build.gradle.kts", ".git"],
+            Preset::Clangd => &["compile_commands.json", "CMakeLists.txt", ".git"],
+        }
+    }
+
+    /// extensions of the files the lang server analyzes
+    pub fn file_suffixes(&self) -> &'static [&'static str] {
+        match self {
+            Preset::RustAnalyzer => &["rs"],
+            Preset::Jdtls => &["java"],
+            Preset::Clangd => &["c", "cc", "cpp", "cxx", "h", "hh", "hpp", "hxx"],
+        }
+    }
+
+    /// symbol kinds that count as definitions
+    pub fn symbol_kinds(&self) -> Vec<SymbolKind> {
+        match self {
+            Preset::RustAnalyzer | Preset::Jdtls => vec![SymbolKind::FUNCTION, SymbolKind::METHOD],
+            // clangd reports both constructors and destructors as constructors
+            Preset::Clangd => vec![
+                SymbolKind::FUNCTION,
+                SymbolKind::METHOD,
+                SymbolKind::CONSTRUCTOR,
+            ],
+        }
+    }
+
+    /// separator used to qualify definition names with their containers,
+    /// `None` for the default [`crate::DEFAULT_QUALIFIED_NAME_SEPARATOR`]
+    pub fn qualified_name_separator(&self) -> Option<&'static str> {
+        match self {
+            Preset::RustAnalyzer | Preset::Jdtls => None,
+            Preset::Clangd => Some("::"),
+        }
+    }
+
+    /// whether overriding methods are merged into the method they override,
+    /// for languages where most calls go through a base method
+    pub fn unify_overrides(&self) -> bool {
+        matches!(self, Preset::Jdtls)
+    }
+}
+
+const EXPECTED_KINDS: &str = "expected one of: function, method, constructor, operator";
+
+/// Parse a symbol kind by its LSP name, e.g. `function` or `constructor`.
+pub fn parse_symbol_kind(name: &str) -> Result<SymbolKind, String> {
+    let kind = match name.to_ascii_lowercase().as_str() {
+        "function" => SymbolKind::FUNCTION,
+        "method" => SymbolKind::METHOD,
+        "constructor" => SymbolKind::CONSTRUCTOR,
+        "operator" => SymbolKind::OPERATOR,
+        _ => {
+            return Err(format!(
+                "unknown symbol kind '{}', {}",
+                name, EXPECTED_KINDS
+            ))
+        }
+    };
+
+    Ok(kind)
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::SymbolKind;
+
+    use super::{parse_symbol_kind, Preset};
+
+    #[test]
+    fn test_clangd_preset() {
+        assert!(Preset::Clangd
+            .symbol_kinds()
+            .contains(&SymbolKind::CONSTRUCTOR));
+        assert_eq!(Preset::Clangd.qualified_name_separator(), Some("::"));
+    }
+
+    #[test]
+    fn test_parse_symbol_kind() {
+        assert_eq!(
+            parse_symbol_kind("Constructor"),
+            Ok(SymbolKind::CONSTRUCTOR)
+        );
+        assert!(parse_symbol_kind("class").is_err());
+    }
+}
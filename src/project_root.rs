@@ -0,0 +1,213 @@
+use std::path::{Path, PathBuf};
+
+use lsp_types::Url;
+use percent_encoding::percent_decode_str;
+
+/// markers used when no preset is selected
+pub const DEFAULT_ROOT_MARKERS: &[&str] = &["Cargo.toml", "go.mod", "package.json", ".git"];
+
+/// Find the project root containing `start`, by walking up from it to the first
+/// directory containing one of `markers`.
+///
+/// Markers are tried in order of preference: a deeper directory containing a
+/// later marker loses to a higher directory containing an earlier one, so e.g.
+/// `[".git"]` as a fallback doesn't shadow a build file in a parent directory.
+pub fn find_project_root(start: &Path, markers: &[&str]) -> Option<PathBuf> {
+    markers.iter().find_map(|marker| {
+        start
+            .ancestors()
+            .find(|dir| dir.join(marker).exists())
+            .map(Path::to_path_buf)
+    })
+}
+
+/// Whether `uri` is `project_root` or inside it.
+///
+/// Servers don't always spell a path the way the root was spelled, e.g. on
+/// Windows `file:///c%3A/project` for a root of `file:///C:/project`, so paths
+/// are compared decoded, with lowercase drive letters, a segment at a time.
+pub fn is_in_project(uri: &Url, project_root: &Url) -> bool {
+    if uri.scheme() != project_root.scheme() || uri.host_str() != project_root.host_str() {
+        return false;
+    }
+
+    let (path, root) = (normalize_path(uri), normalize_path(project_root));
+
+    path == root || path.starts_with(&format!("{}/", root))
+}
+
+/// Whether `uri` is in the project at `project_root`, or with
+/// `workspace_folders` in any one of those folders of the project.
+pub fn is_in_workspace(uri: &Url, project_root: &Url, workspace_folders: &[Url]) -> bool {
+    if workspace_folders.is_empty() {
+        return is_in_project(uri, project_root);
+    }
+
+    workspace_folders
+        .iter()
+        .any(|folder| is_in_project(uri, folder))
+}
+
+/// The decoded path of `uri` relative to `project_root`, without a leading
+/// slash (e.g. `src/main.rs`), or `None` when `uri` isn't in the project, see
+/// [`is_in_project`].
+pub fn relative_path(uri: &Url, project_root: &Url) -> Option<String> {
+    if !is_in_project(uri, project_root) {
+        return None;
+    }
+
+    let (path, root) = (normalize_path(uri), normalize_path(project_root));
+
+    Some(path[root.len()..].trim_start_matches('/').to_string())
+}
+
+/// `uri` spelled the way [`is_in_project`] compares paths, so two spellings of
+/// the same file get the same key.
+pub(crate) fn file_key(uri: &Url) -> String {
+    format!(
+        "{}://{}{}",
+        uri.scheme(),
+        uri.host_str().unwrap_or_default(),
+        normalize_path(uri)
+    )
+}
+
+fn normalize_path(uri: &Url) -> String {
+    let mut path = percent_decode_str(uri.path())
+        .decode_utf8_lossy()
+        .replace('\\', "/");
+
+    // `/C:/...` and `/c:/...` are the same drive
+    if let [b'/', drive, b':', ..] = path.as_bytes() {
+        if drive.is_ascii_alphabetic() {
+            path[1..2].make_ascii_lowercase();
+        }
+    }
+
+    path.trim_end_matches('/').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use lsp_types::Url;
+
+    use super::{find_project_root, is_in_project, is_in_workspace, relative_path};
+
+    #[test]
+    fn test_find_project_root() {
+        let root = std::env::temp_dir().join("code_depth_test_find_project_root");
+        let nested = root.join("crates").join("inner").join("src");
+        fs::create_dir_all(&nested).unwrap();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::write(root.join("crates").join("inner").join("Cargo.toml"), "").unwrap();
+
+        assert_eq!(
+            find_project_root(&nested, &["Cargo.toml", ".git"]),
+            Some(root.join("crates").join("inner"))
+        );
+        assert_eq!(find_project_root(&nested, &[".git"]), Some(root.clone()));
+        assert_eq!(
+            find_project_root(&nested, &["code_depth_missing_marker"]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_is_in_project() {
+        let url = |url: &str| Url::parse(url).unwrap();
+        let root = url("file:///C:/Users/dev/project");
+
+        for uri in [
+            "file:///C:/Users/dev/project/src/main.rs",
+            "file:///c:/Users/dev/project/src/main.rs",
+            "file:///c%3A/Users/dev/project/src/main.rs",
+            "file:///C%3a/Users/dev/project/src/main.rs",
+            "file:///C:/Users/dev/project",
+        ] {
+            assert!(is_in_project(&url(uri), &root), "{} is in the project", uri);
+        }
+        for uri in [
+            "file:///C:/Users/dev/project2/src/main.rs",
+            "file:///D:/Users/dev/project/src/main.rs",
+            "file:///C:/Users/dev/other/main.rs",
+            "untitled:///C:/Users/dev/project/src/main.rs",
+        ] {
+            assert!(
+                !is_in_project(&url(uri), &root),
+                "{} isn't in the project",
+                uri
+            );
+        }
+
+        // a root with a trailing slash or escaped characters
+        let root = url("file:///home/dev/my%20project/");
+        assert!(is_in_project(
+            &url("file:///home/dev/my project/src/lib.rs"),
+            &root
+        ));
+        assert!(!is_in_project(
+            &url("file:///home/dev/my projects/lib.rs"),
+            &root
+        ));
+    }
+
+    #[test]
+    fn test_relative_path() {
+        let url = |url: &str| Url::parse(url).unwrap();
+        let root = url("file:///C:/project");
+
+        for uri in [
+            "file:///C:/project/src/main.rs",
+            "file:///c%3A/project/src/main.rs",
+            "file:///c:/project/src/main.rs",
+        ] {
+            assert_eq!(
+                relative_path(&url(uri), &root).as_deref(),
+                Some("src/main.rs")
+            );
+        }
+        assert_eq!(
+            relative_path(&url("file:///C:/project/my%20file.rs"), &root).as_deref(),
+            Some("my file.rs")
+        );
+        // a sibling sharing the root's prefix isn't in it
+        assert_eq!(relative_path(&url("file:///C:/project2/a.rs"), &root), None);
+    }
+
+    #[test]
+    fn test_is_in_workspace() {
+        let url = |url: &str| Url::parse(url).unwrap();
+        let root = url("file:///repo");
+        let folders = [url("file:///repo/api"), url("file:///repo/web")];
+
+        assert!(is_in_workspace(
+            &url("file:///repo/api/main.rs"),
+            &root,
+            &folders
+        ));
+        assert!(is_in_workspace(
+            &url("file:///repo/web/app.rs"),
+            &root,
+            &folders
+        ));
+        assert!(!is_in_workspace(
+            &url("file:///repo/docs/build.rs"),
+            &root,
+            &folders
+        ));
+        assert!(!is_in_workspace(
+            &url("file:///other/api/main.rs"),
+            &root,
+            &folders
+        ));
+
+        // without folders the whole project is the workspace
+        assert!(is_in_workspace(
+            &url("file:///repo/docs/build.rs"),
+            &root,
+            &[]
+        ));
+    }
+}
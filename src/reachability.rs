@@ -0,0 +1,91 @@
+use lsp_types::CallHierarchyItem;
+
+use crate::{graph_util::get_distances, to_hashable_calls, Call};
+
+/// Minimum amount of calls needed to get from each source to each target.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReachabilityMatrix {
+    pub sources: Vec<CallHierarchyItem>,
+    pub targets: Vec<CallHierarchyItem>,
+    /// `distances[i][j]` is the distance from `sources[i]` to `targets[j]`,
+    /// `None` if the target can't be reached from the source
+    pub distances: Vec<Vec<Option<usize>>>,
+}
+
+pub fn get_reachability<F, G>(calls: &[Call], is_source: F, is_target: G) -> ReachabilityMatrix
+where
+    F: Fn(&CallHierarchyItem) -> bool,
+    G: Fn(&CallHierarchyItem) -> bool,
+{
+    let hashable_calls = to_hashable_calls(calls);
+
+    let mut items = hashable_calls
+        .iter()
+        .flat_map(|(caller, callee)| [caller, callee])
+        .collect::<Vec<_>>();
+    items.sort_by_key(|item| {
+        (
+            item.0.uri.to_string(),
+            item.0.selection_range.start.line,
+            item.0.selection_range.start.character,
+        )
+    });
+    items.dedup();
+
+    let sources = items
+        .iter()
+        .filter(|item| is_source(&item.0))
+        .collect::<Vec<_>>();
+    let targets = items
+        .iter()
+        .filter(|item| is_target(&item.0))
+        .collect::<Vec<_>>();
+
+    let distances = sources
+        .iter()
+        .map(|source| {
+            let source_distances = get_distances(&hashable_calls, source);
+            targets
+                .iter()
+                .map(|target| source_distances.get(target).copied())
+                .collect()
+        })
+        .collect();
+
+    ReachabilityMatrix {
+        sources: sources.iter().map(|item| item.0.clone()).collect(),
+        targets: targets.iter().map(|item| item.0.clone()).collect(),
+        distances,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::get_reachability;
+    use crate::test_util::{call, item};
+
+    #[test]
+    fn test_get_reachability() {
+        let (handler_a, handler_b) = (item("handler_a", 0), item("handler_b", 1));
+        let (service, exec_sql, log) = (item("service", 2), item("exec_sql", 3), item("log", 4));
+        let calls = vec![
+            call(&handler_a, &service),
+            call(&service, &exec_sql),
+            call(&handler_b, &log),
+            call(&service, &log),
+        ];
+
+        let matrix = get_reachability(
+            &calls,
+            |item| item.name.starts_with("handler"),
+            |item| item.name == "exec_sql" || item.name == "log",
+        );
+
+        assert_eq!(matrix.sources, vec![handler_a, handler_b]);
+        assert_eq!(matrix.targets, vec![exec_sql, log]);
+        assert_eq!(
+            matrix.distances,
+            vec![vec![Some(2), Some(2)], vec![None, Some(1)]]
+        );
+    }
+}
@@ -0,0 +1,662 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    io::{self, Write},
+};
+
+use clap::ValueEnum;
+use lsp_types::{CallHierarchyItem, Url};
+use serde::Serialize;
+
+use crate::{
+    diagram::{to_dot, to_mermaid},
+    hashable_call_hierarchy_item::HashableCallHierarchyItem,
+    project_root::relative_path,
+    report::{build_badge, build_folded_stacks, group_report_by_dir, AnalysisReport, GroupBy},
+    sarif::to_sarif,
+    Call, Depths, NameFormat,
+};
+
+/// Formats the analysis can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// the full analysis report
+    Json,
+    /// a shields.io endpoint badge with the amount of problems
+    Badge,
+    /// folded stacks of each path, for rendering as a flamegraph
+    Folded,
+    /// a Graphviz DOT digraph of the call paths, problems are red
+    Dot,
+    /// a Mermaid flowchart of the call paths, problems have the `problem` class
+    Mermaid,
+    /// the depths of each function as comma separated values
+    Csv,
+    /// the call paths as an indented tree under each root
+    Text,
+    /// a GitHub Actions warning annotation per problem
+    Github,
+    /// a SARIF 2.1.0 log with a result per problem, for code scanning tools
+    Sarif,
+    /// a SQLite database, requires the `sqlite` feature and `--output-file`
+    Sqlite,
+}
+
+/// Everything a renderer can render from, the report and the calls and depths
+/// it was built from.
+pub struct RenderInput<'a> {
+    pub report: &'a AnalysisReport,
+    pub calls: &'a [Call],
+    pub depths: &'a Depths<CallHierarchyItem>,
+    pub root: &'a Url,
+    /// how functions are named in the formats that aren't rendered from the
+    /// report's names
+    pub name_format: &'a NameFormat,
+    /// write JSON formats on a single line instead of indented
+    pub compact: bool,
+    /// with [`GroupBy::Dir`], the JSON report nests the functions by
+    /// directory, see [`group_report_by_dir`]
+    pub group_by: Option<GroupBy>,
+}
+
+/// Writes the analysis in a single output format.
+pub trait OutputRenderer {
+    fn render(&self, input: &RenderInput, writer: &mut dyn Write) -> io::Result<()>;
+}
+
+/// Renderer of each format, formats missing here (e.g. [`OutputFormat::Sqlite`])
+/// are written from the call graph directly.
+const RENDERERS: &[(OutputFormat, &dyn OutputRenderer)] = &[
+    (OutputFormat::Json, &JsonRenderer),
+    (OutputFormat::Badge, &BadgeRenderer),
+    (OutputFormat::Folded, &FoldedRenderer),
+    (OutputFormat::Dot, &DotRenderer),
+    (OutputFormat::Mermaid, &MermaidRenderer),
+    (OutputFormat::Csv, &CsvRenderer),
+    (OutputFormat::Text, &TextRenderer),
+    (OutputFormat::Github, &GithubRenderer),
+    (OutputFormat::Sarif, &SarifRenderer),
+];
+
+/// Find the renderer of `format`, see [`RENDERERS`].
+pub fn get_renderer(format: OutputFormat) -> Option<&'static dyn OutputRenderer> {
+    RENDERERS
+        .iter()
+        .find(|(renderer_format, _)| *renderer_format == format)
+        .map(|(_, renderer)| *renderer)
+}
+
+/// Write `input` in `format`, failing for formats without a renderer.
+pub fn render(format: OutputFormat, input: &RenderInput, writer: &mut dyn Write) -> io::Result<()> {
+    let renderer = get_renderer(format).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{:?} output isn't rendered", format),
+        )
+    })?;
+
+    renderer.render(input, writer)
+}
+
+/// The report as pretty printed JSON.
+pub struct JsonRenderer;
+
+impl OutputRenderer for JsonRenderer {
+    fn render(&self, input: &RenderInput, writer: &mut dyn Write) -> io::Result<()> {
+        match input.group_by {
+            Some(GroupBy::Dir) => write_json(
+                writer,
+                &group_report_by_dir(input.report, input.root),
+                input.compact,
+            ),
+            _ => write_json(writer, input.report, input.compact),
+        }
+    }
+}
+
+/// A shields.io endpoint badge, see [`build_badge`].
+pub struct BadgeRenderer;
+
+impl OutputRenderer for BadgeRenderer {
+    fn render(&self, input: &RenderInput, writer: &mut dyn Write) -> io::Result<()> {
+        write_json(writer, &build_badge(&input.report.summary), input.compact)
+    }
+}
+
+/// Folded stacks for flamegraph tools, see [`build_folded_stacks`].
+pub struct FoldedRenderer;
+
+impl OutputRenderer for FoldedRenderer {
+    fn render(&self, input: &RenderInput, writer: &mut dyn Write) -> io::Result<()> {
+        writeln!(writer, "{}", build_folded_stacks(input.report))
+    }
+}
+
+/// A Graphviz digraph, see [`to_dot`].
+pub struct DotRenderer;
+
+impl OutputRenderer for DotRenderer {
+    fn render(&self, input: &RenderInput, writer: &mut dyn Write) -> io::Result<()> {
+        writeln!(
+            writer,
+            "{}",
+            to_dot(
+                input.depths,
+                input.calls,
+                input.report,
+                input.root,
+                input.name_format
+            )
+        )
+    }
+}
+
+/// A Mermaid flowchart, see [`to_mermaid`].
+pub struct MermaidRenderer;
+
+impl OutputRenderer for MermaidRenderer {
+    fn render(&self, input: &RenderInput, writer: &mut dyn Write) -> io::Result<()> {
+        writeln!(
+            writer,
+            "{}",
+            to_mermaid(
+                input.depths,
+                input.calls,
+                input.report,
+                input.root,
+                input.name_format
+            )
+        )
+    }
+}
+
+#[derive(Serialize)]
+struct CsvRow {
+    function: String,
+    min_depth: usize,
+    max_depth: usize,
+    paths: usize,
+    problem: bool,
+}
+
+/// A row per function, sorted by name, with its min and max depth, its amount
+/// of paths from roots and whether `report` has it as a problem.
+pub fn to_csv(
+    depths: &Depths<CallHierarchyItem>,
+    report: &AnalysisReport,
+    root: &Url,
+    name_format: &NameFormat,
+) -> String {
+    let problems = report.problem_items();
+
+    let mut rows = depths
+        .iter()
+        .map(|(item, paths)| {
+            let lengths = paths.iter().map(|path| path.len() - 1);
+
+            CsvRow {
+                function: name_format.build(item, root),
+                min_depth: lengths.clone().min().unwrap_or_default(),
+                max_depth: lengths.max().unwrap_or_default(),
+                paths: paths.len(),
+                problem: problems.contains(item),
+            }
+        })
+        .collect::<Vec<_>>();
+    rows.sort_by(|a, b| a.function.cmp(&b.function));
+
+    let mut writer = csv::Writer::from_writer(vec![]);
+    for row in rows {
+        writer.serialize(row).expect("rows serialize to csv");
+    }
+    let csv = writer.into_inner().expect("writing to a vec can't fail");
+
+    String::from_utf8(csv).expect("csv of strings is utf-8")
+}
+
+/// Comma separated values, see [`to_csv`].
+pub struct CsvRenderer;
+
+impl OutputRenderer for CsvRenderer {
+    fn render(&self, input: &RenderInput, writer: &mut dyn Write) -> io::Result<()> {
+        write!(
+            writer,
+            "{}",
+            to_csv(input.depths, input.report, input.root, input.name_format)
+        )
+    }
+}
+
+/// marker of the reported problems in [`to_text_tree`]
+pub const TEXT_TREE_PROBLEM_MARKER: &str = "⚠";
+
+/// The call paths in `depths` as an indented tree under each root, one
+/// function per line with its depth in the tree, the problems of `report` are
+/// marked with [`TEXT_TREE_PROBLEM_MARKER`].
+///
+/// Functions whose calls were already printed (e.g. under another root) are
+/// noted with `(see above)` instead of being printed again.
+pub fn to_text_tree(
+    depths: &Depths<CallHierarchyItem>,
+    report: &AnalysisReport,
+    root: &Url,
+    name_format: &NameFormat,
+) -> String {
+    let problems = report.problem_items();
+    let to_node = |item: &CallHierarchyItem| {
+        let id = HashableCallHierarchyItem(item.clone()).stable_id();
+        let name = name_format.build(item, root);
+        let is_problem = problems.contains(item);
+
+        TextTreeNode {
+            name,
+            id,
+            is_problem,
+        }
+    };
+
+    let mut roots = BTreeSet::new();
+    let mut children = HashMap::<String, BTreeSet<TextTreeNode>>::new();
+    for path in depths.iter().flat_map(|(_, paths)| paths) {
+        roots.extend(path.first().map(to_node));
+        for hop in path.windows(2) {
+            children
+                .entry(to_node(&hop[0]).id)
+                .or_default()
+                .insert(to_node(&hop[1]));
+        }
+    }
+
+    let mut tree = TextTree {
+        children,
+        expanded: HashSet::new(),
+        lines: vec![],
+    };
+    for node in &roots {
+        tree.push(node, 0);
+    }
+
+    tree.lines
+        .into_iter()
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct TextTreeNode {
+    name: String,
+    id: String,
+    is_problem: bool,
+}
+
+struct TextTree {
+    children: HashMap<String, BTreeSet<TextTreeNode>>,
+    /// functions whose calls were already printed
+    expanded: HashSet<String>,
+    lines: Vec<String>,
+}
+
+impl TextTree {
+    fn push(&mut self, node: &TextTreeNode, depth: usize) {
+        let mut line = format!("{}{} ({})", "  ".repeat(depth), node.name, depth);
+        if node.is_problem {
+            line.push(' ');
+            line.push_str(TEXT_TREE_PROBLEM_MARKER);
+        }
+
+        let Some(children) = self.children.remove(&node.id) else {
+            if self.expanded.contains(&node.id) {
+                line.push_str(" (see above)");
+            }
+            self.lines.push(line);
+            return;
+        };
+
+        self.expanded.insert(node.id.clone());
+        self.lines.push(line);
+        for child in &children {
+            self.push(child, depth + 1);
+        }
+    }
+}
+
+/// An indented tree of the call paths, see [`to_text_tree`].
+pub struct TextRenderer;
+
+impl OutputRenderer for TextRenderer {
+    fn render(&self, input: &RenderInput, writer: &mut dyn Write) -> io::Result<()> {
+        write!(
+            writer,
+            "{}",
+            to_text_tree(input.depths, input.report, input.root, input.name_format)
+        )
+    }
+}
+
+/// A GitHub Actions `warning` workflow command per problem, which shows up as
+/// an annotation on the line of the problem's name, stating the depths it's
+/// reached at.
+///
+/// Files are relative to `root`, which is expected to be the repository root.
+pub fn to_github_annotations(report: &AnalysisReport, root: &Url) -> String {
+    // workflow commands end at newlines, and their properties at `,` and `:`
+    let escape_data = |s: &str| {
+        s.replace('%', "%25")
+            .replace('\r', "%0D")
+            .replace('\n', "%0A")
+    };
+    let escape_property = |s: &str| escape_data(s).replace(':', "%3A").replace(',', "%2C");
+
+    report
+        .problems
+        .iter()
+        .map(|problem| {
+            let uri = &problem.location.uri;
+            let file = relative_path(uri, root).unwrap_or_else(|| uri.to_string());
+            let start = problem.location.range.start;
+            let depths = problem
+                .depths
+                .iter()
+                .map(|depth| depth.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            let message = format!("{} is reached at depths {}", problem.name, depths);
+
+            format!(
+                "::warning file={},line={},col={},title=Inconsistent call depth::{}\n",
+                escape_property(&file),
+                start.line + 1,
+                start.character + 1,
+                escape_data(&message)
+            )
+        })
+        .collect()
+}
+
+/// GitHub Actions annotations, see [`to_github_annotations`].
+pub struct GithubRenderer;
+
+impl OutputRenderer for GithubRenderer {
+    fn render(&self, input: &RenderInput, writer: &mut dyn Write) -> io::Result<()> {
+        write!(
+            writer,
+            "{}",
+            to_github_annotations(input.report, input.root)
+        )
+    }
+}
+
+/// A SARIF log, see [`to_sarif`].
+pub struct SarifRenderer;
+
+impl OutputRenderer for SarifRenderer {
+    fn render(&self, input: &RenderInput, writer: &mut dyn Write) -> io::Result<()> {
+        write_json(writer, &to_sarif(input.report, input.root), input.compact)
+    }
+}
+
+/// Write `value` as a line of JSON, or indented unless `compact`.
+fn write_json(writer: &mut dyn Write, value: &impl Serialize, compact: bool) -> io::Result<()> {
+    if compact {
+        serde_json::to_writer(&mut *writer, value)?;
+    } else {
+        serde_json::to_writer_pretty(&mut *writer, value)?;
+    }
+
+    writeln!(writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::Url;
+
+    use super::{
+        get_renderer, render, to_csv, to_github_annotations, to_text_tree, OutputFormat,
+        RenderInput, TEXT_TREE_PROBLEM_MARKER,
+    };
+    use crate::{
+        get_function_depths,
+        report::{build_report, ReportOptions},
+        test_util::{call, item},
+        NameFormat, NameStyle,
+    };
+
+    /// Render the analysis of `main` calling `a`, which calls `b`.
+    fn render_sample(format: OutputFormat) -> String {
+        render_sample_with(format, false)
+    }
+
+    fn render_sample_with(format: OutputFormat, compact: bool) -> String {
+        let root = Url::parse("file:///project").unwrap();
+        let (main, a, b) = (item("main", 0), item("a", 1), item("b", 2));
+        let calls = vec![call(&main, &a), call(&a, &b)];
+
+        let depths = get_function_depths(&calls);
+        let report = build_report(&calls, &depths, &root, &Default::default());
+        let input = RenderInput {
+            report: &report,
+            calls: &calls,
+            depths: &depths,
+            root: &root,
+            name_format: &Default::default(),
+            compact,
+            group_by: None,
+        };
+
+        let mut output = vec![];
+        render(format, &input, &mut output).unwrap();
+
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_json_renderer() {
+        assert_eq!(
+            render_sample(OutputFormat::Json),
+            include_str!("../tests/golden/report.json")
+        );
+    }
+
+    #[test]
+    fn test_compact_json_renderer() {
+        let compact = render_sample_with(OutputFormat::Json, true);
+
+        assert_eq!(compact.lines().count(), 1);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&compact).unwrap(),
+            serde_json::from_str::<serde_json::Value>(include_str!("../tests/golden/report.json"))
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_badge_renderer() {
+        assert_eq!(
+            render_sample(OutputFormat::Badge),
+            include_str!("../tests/golden/badge.json")
+        );
+    }
+
+    #[test]
+    fn test_folded_renderer() {
+        assert_eq!(
+            render_sample(OutputFormat::Folded),
+            include_str!("../tests/golden/folded.txt")
+        );
+    }
+
+    #[test]
+    fn test_csv_renderer() {
+        assert_eq!(
+            render_sample(OutputFormat::Csv),
+            include_str!("../tests/golden/depths.csv")
+        );
+    }
+
+    #[test]
+    fn test_to_csv_quotes_names() {
+        let root = Url::parse("file:///project").unwrap();
+        let (main, get) = (item("main", 0), item("Map<K, V>::get", 1));
+        let calls = vec![call(&main, &get)];
+
+        let depths = get_function_depths(&calls);
+        let report = build_report(&calls, &depths, &root, &Default::default());
+
+        let csv = to_csv(&depths, &report, &root, &Default::default());
+
+        assert!(
+            csv.lines()
+                .any(|line| line == "\"/src/main.rs:Map<K, V>::get\",1,1,1,false"),
+            "unexpected csv: {}",
+            csv
+        );
+    }
+
+    #[test]
+    fn test_text_renderer() {
+        assert_eq!(
+            render_sample(OutputFormat::Text),
+            include_str!("../tests/golden/tree.txt")
+        );
+    }
+
+    #[test]
+    fn test_to_text_tree() {
+        let root = Url::parse("file:///project").unwrap();
+        let (r1, r2, y, x, z) = (
+            item("r1", 0),
+            item("r2", 1),
+            item("y", 2),
+            item("x", 3),
+            item("z", 4),
+        );
+        let calls = [call(&r1, &x), call(&r2, &y), call(&y, &x), call(&x, &z)];
+        let depths = get_function_depths(&calls);
+        let report = build_report(&calls, &depths, &root, &Default::default());
+
+        let tree = to_text_tree(&depths, &report, &root, &Default::default());
+
+        // the calls of `x` are printed once, under the first root
+        assert_eq!(
+            tree,
+            "/src/main.rs:r1 (0)
+  /src/main.rs:x (1) ⚠
+    /src/main.rs:z (2) ⚠
+/src/main.rs:r2 (0)
+  /src/main.rs:y (1)
+    /src/main.rs:x (2) ⚠ (see above)
+"
+        );
+    }
+
+    #[test]
+    fn test_to_github_annotations() {
+        let root = Url::parse("file:///project").unwrap();
+        let (r1, r2, y, x) = (item("r1", 0), item("r2", 1), item("y", 2), item("x", 3));
+        let calls = [call(&r1, &x), call(&r2, &y), call(&y, &x)];
+        let depths = get_function_depths(&calls);
+        let report = build_report(&calls, &depths, &root, &Default::default());
+
+        assert_eq!(
+            to_github_annotations(&report, &root),
+            "::warning file=src/main.rs,line=4,col=1,title=Inconsistent call depth::/src/main.rs:x is reached at depths 1, 2\n"
+        );
+    }
+
+    #[test]
+    fn test_renderers_mark_reported_problems() {
+        let root = Url::parse("file:///project").unwrap();
+        let (r1, r2, y, x) = (item("r1", 0), item("r2", 1), item("y", 2), item("x", 3));
+        let calls = vec![call(&r1, &x), call(&r2, &y), call(&y, &x)];
+        let depths = get_function_depths(&calls);
+
+        let render_with = |options: &ReportOptions, format: OutputFormat| {
+            let report = build_report(&calls, &depths, &root, options);
+            let input = RenderInput {
+                report: &report,
+                calls: &calls,
+                depths: &depths,
+                root: &root,
+                name_format: &Default::default(),
+                compact: false,
+                group_by: None,
+            };
+            let mut output = vec![];
+            render(format, &input, &mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        // `x` is reached at depths 1 and 2, which a tolerance of 1 allows
+        let tolerant = ReportOptions {
+            depth_tolerance: 1,
+            ..Default::default()
+        };
+        let markers = [
+            (OutputFormat::Csv, ",true"),
+            (OutputFormat::Text, TEXT_TREE_PROBLEM_MARKER),
+            (OutputFormat::Dot, "color=red"),
+            (OutputFormat::Mermaid, ":::problem"),
+            (OutputFormat::Github, "::warning"),
+            (OutputFormat::Sarif, "\"ruleId\""),
+        ];
+        for (format, marker) in markers {
+            assert!(render_with(&Default::default(), format).contains(marker));
+            let output = render_with(&tolerant, format);
+            assert!(
+                !output.contains(marker),
+                "{:?} has a problem: {}",
+                format,
+                output
+            );
+        }
+    }
+
+    #[test]
+    fn test_renderers_use_name_format() {
+        let root = Url::parse("file:///project").unwrap();
+        let (main, a) = (item("main", 0), item("a", 1));
+        let calls = vec![call(&main, &a)];
+        let depths = get_function_depths(&calls);
+        let name_format = NameFormat {
+            separator: "#".to_string(),
+            style: NameStyle::Location,
+        };
+        let options = ReportOptions {
+            name_format: name_format.clone(),
+            ..Default::default()
+        };
+        let report = build_report(&calls, &depths, &root, &options);
+        let input = RenderInput {
+            report: &report,
+            calls: &calls,
+            depths: &depths,
+            root: &root,
+            name_format: &name_format,
+            compact: false,
+            group_by: None,
+        };
+
+        for format in [
+            OutputFormat::Json,
+            OutputFormat::Folded,
+            OutputFormat::Dot,
+            OutputFormat::Mermaid,
+            OutputFormat::Csv,
+            OutputFormat::Text,
+        ] {
+            let mut output = vec![];
+            render(format, &input, &mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(
+                output.contains("/src/main.rs#a#2#1") && !output.contains("/src/main.rs:a"),
+                "{:?} ignores the name format: {}",
+                format,
+                output
+            );
+        }
+    }
+
+    #[test]
+    fn test_sqlite_has_no_renderer() {
+        assert!(get_renderer(OutputFormat::Sqlite).is_none());
+    }
+}
@@ -0,0 +1,1720 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+};
+
+use clap::ValueEnum;
+use lsp_types::{CallHierarchyItem, Location, Position, SymbolKind, Url};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    baseline::Baseline,
+    call_graph::find_recursion_clusters,
+    codeowners::CodeOwners,
+    find_items_with_depth_spread, find_root_cause_problems,
+    graph_util::{find_cycles, find_roots},
+    hashable_call_hierarchy_item::HashableCallHierarchyItem,
+    project_root::{file_key, relative_path},
+    snippets::CallSnippets,
+    to_hashable_calls, Call, Depths, NameFormat, Truncation,
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnalysisReport {
+    pub ok: Vec<ItemReport>,
+    pub problems: Vec<ItemReport>,
+    /// groups of functions that (possibly indirectly) call each other, see
+    /// [`crate::call_graph::find_recursion_clusters`]
+    pub cycles: Vec<Vec<String>>,
+    /// names of the functions no root reaches, e.g. dead code, see
+    /// [`ReportOptions::definitions`]
+    #[serde(default)]
+    pub unreachable: Vec<String>,
+    pub summary: Summary,
+    /// stats of each top level module, only set when grouping by module with stats
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub module_summary: Option<BTreeMap<String, ModuleSummary>>,
+    /// functions whose depth differs between the incoming and outgoing call
+    /// graphs, only set when the outgoing graph was built
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub directional_mismatch: Option<Vec<DirectionalMismatch>>,
+}
+
+impl AnalysisReport {
+    /// The reported problems, to tell which functions of the analysis are
+    /// problems without finding them again.
+    pub fn problem_items(&self) -> ProblemItems<'_> {
+        ProblemItems(
+            self.problems
+                .iter()
+                .map(|problem| {
+                    let range = problem.location.range;
+                    (&problem.location.uri, range.start, range.end)
+                })
+                .collect(),
+        )
+    }
+}
+
+/// The [`AnalysisReport::problems`] by the location of their names.
+pub struct ProblemItems<'a>(BTreeSet<(&'a Url, Position, Position)>);
+
+impl ProblemItems<'_> {
+    pub fn contains(&self, item: &CallHierarchyItem) -> bool {
+        let range = item.selection_range;
+        self.0.contains(&(&item.uri, range.start, range.end))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ItemReport {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub location: Location,
+    /// distinct depths the item is reached at, a root has depth 0
+    pub depths: Vec<usize>,
+    pub min_depth: usize,
+    pub max_depth: usize,
+    /// amount of distinct depths, more than one for problems
+    pub num_depths: usize,
+    /// amount of distinct functions calling the item
+    pub fan_in: usize,
+    /// amount of distinct functions the item calls
+    pub fan_out: usize,
+    /// the item has no callers
+    pub is_root: bool,
+    /// the paths through the item were cut at the max depth, see
+    /// [`crate::get_truncated_function_depths`]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+    /// a path through the item ended at its call back into a cycle, instead of
+    /// following the cycle, see [`crate::Truncation::cycle_ends`]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cycle_end: bool,
+    /// amount of paths from roots to the item, `paths` has a sample of them
+    /// when there are more than [`ReportOptions::max_paths`]
+    pub num_paths: usize,
+    pub paths: Vec<Vec<String>>,
+    /// source line of each call along `paths`, only set when snippets were read
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_snippets: Option<Vec<Vec<Option<String>>>>,
+    /// metadata of each path in `paths`, only set when requested
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_metadata: Option<Vec<PathMetadata>>,
+}
+
+/// Same analysis as [`AnalysisReport`], but every function is described once in
+/// `nodes` and referenced everywhere else by its stable id.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NormalizedReport {
+    pub nodes: BTreeMap<String, NodeReport>,
+    /// (caller id, callee id)
+    pub edges: Vec<(String, String)>,
+    pub ok: Vec<NormalizedItemReport>,
+    pub problems: Vec<NormalizedItemReport>,
+    pub cycles: Vec<Vec<String>>,
+    /// ids of the functions no root reaches
+    #[serde(default)]
+    pub unreachable: Vec<String>,
+    pub summary: Summary,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub module_summary: Option<BTreeMap<String, ModuleSummary>>,
+    /// functions whose depth differs between the incoming and outgoing call
+    /// graphs, only set when the outgoing graph was built
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub directional_mismatch: Option<Vec<DirectionalMismatch>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeReport {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub location: Location,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NormalizedItemReport {
+    pub id: String,
+    pub depths: Vec<usize>,
+    pub min_depth: usize,
+    pub max_depth: usize,
+    pub num_depths: usize,
+    pub fan_in: usize,
+    pub fan_out: usize,
+    pub is_root: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cycle_end: bool,
+    pub num_paths: usize,
+    pub paths: Vec<Vec<String>>,
+    /// source line of each call along `paths`, only set when snippets were read
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_snippets: Option<Vec<Vec<Option<String>>>>,
+    /// metadata of each path in `paths`, only set when requested
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_metadata: Option<Vec<PathMetadata>>,
+}
+
+impl From<ItemReport> for NormalizedItemReport {
+    /// The item report of a [`build_report`] naming functions by their ids,
+    /// without what the [`NormalizedReport::nodes`] already describe.
+    fn from(item: ItemReport) -> Self {
+        // every field is listed, so new ones can't be left out
+        let ItemReport {
+            name,
+            kind: _,
+            location: _,
+            depths,
+            min_depth,
+            max_depth,
+            num_depths,
+            fan_in,
+            fan_out,
+            is_root,
+            truncated,
+            cycle_end,
+            num_paths,
+            paths,
+            path_snippets,
+            path_metadata,
+        } = item;
+
+        Self {
+            id: name,
+            depths,
+            min_depth,
+            max_depth,
+            num_depths,
+            fan_in,
+            fan_out,
+            is_root,
+            truncated,
+            cycle_end,
+            num_paths,
+            paths,
+            path_snippets,
+            path_metadata,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PathMetadata {
+    /// total amount of call sites of the calls along the path
+    pub weight: usize,
+    /// some function along the path is part of a cycle
+    pub crosses_cycle: bool,
+    /// the callers of some function along the path might be missing
+    pub has_incomplete_node: bool,
+}
+
+/// A function reached at a different max depth in the call graph built from
+/// incoming calls (forward) than in the one built from outgoing calls (reverse).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirectionalMismatch {
+    /// name of the function, or its id in a [`NormalizedReport`]
+    pub item: String,
+    pub forward_depth: usize,
+    pub reverse_depth: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum GroupBy {
+    /// the top level module of the item's file, e.g. `lsp` for `src/lsp/json_rpc.rs`
+    Module,
+    /// the directories of the item's file, nesting the reported functions
+    /// like the project tree, see [`group_report_by_dir`]
+    Dir,
+}
+
+/// The reported functions of a directory's files and subdirectories, or of a
+/// single file, by name.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum DirTree {
+    Dir(BTreeMap<String, DirTree>),
+    File(Vec<ItemReport>),
+}
+
+/// Order of the reported functions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum SortBy {
+    /// by name, or by id in a [`NormalizedReport`]
+    #[default]
+    Name,
+    /// most callers first
+    FanIn,
+    /// most callees first
+    FanOut,
+}
+
+#[derive(Debug, Default)]
+pub struct ReportOptions {
+    /// source lines of calls, see [`crate::snippets::read_call_snippets`]
+    pub snippets: Option<CallSnippets>,
+    pub group_by: Option<GroupBy>,
+    pub sort_by: SortBy,
+    /// include statistics beyond the basic summary
+    pub with_stats: bool,
+    /// depths computed from outgoing calls, compared against the reported depths
+    pub reverse_depths: Option<Depths<CallHierarchyItem>>,
+    /// max allowed difference between the depths and the reverse depths
+    pub direction_tolerance: usize,
+    pub name_format: NameFormat,
+    /// only report items whose max depth is more than this deeper than their
+    /// min depth as problems, see [`find_items_with_depth_spread`]
+    pub depth_tolerance: usize,
+    /// never report roots as problems, even if they are reached at other depths
+    pub exclude_roots_from_problems: bool,
+    /// only report the problems not inherited from an upstream problem, see
+    /// [`find_root_cause_problems`]
+    pub root_causes_only: bool,
+    pub path_metadata: bool,
+    /// functions whose callers might be missing, see [`crate::FunctionCalls`]
+    pub incomplete_items: HashSet<HashableCallHierarchyItem>,
+    /// only report problems in files owned by this owner
+    pub problems_owner: Option<OwnerFilter>,
+    /// only report problems in these files, e.g. the files a change touched
+    pub problems_files: Option<HashSet<Url>>,
+    /// every analyzed function, the ones without depths (e.g. never called)
+    /// are reported as ok without any paths, and as unreachable
+    pub definitions: Vec<CallHierarchyItem>,
+    /// the roots the depths were measured from, when they aren't the functions
+    /// without callers, see [`crate::try_get_function_depths_from_roots`]
+    pub roots: Option<HashSet<HashableCallHierarchyItem>>,
+    /// problems known from an earlier run, which aren't reported again
+    pub baseline: Option<Baseline>,
+    /// the paths the depths were cut at, its cut off functions are reached so
+    /// they aren't reported as unreachable
+    pub truncation: Truncation,
+    /// report at most this many paths per function, see [`sample_paths`]
+    pub max_paths: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct OwnerFilter {
+    pub codeowners: CodeOwners,
+    /// e.g. `@my-team`
+    pub owner: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Summary {
+    pub num_functions: usize,
+    pub num_roots: usize,
+    pub num_problems: usize,
+    pub max_depth: usize,
+    /// amount of functions reached at each depth, functions reached at several
+    /// depths are counted at each of them
+    #[serde(default)]
+    pub depth_histogram: BTreeMap<usize, usize>,
+    /// median of the max depth of each function, only set with stats
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub median_depth: Option<f64>,
+}
+
+/// A [shields.io endpoint](https://shields.io/badges/endpoint-badge) badge
+/// showing the amount of problems.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Badge {
+    pub schema_version: u8,
+    pub label: String,
+    pub message: String,
+    pub color: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModuleSummary {
+    pub num_functions: usize,
+    pub max_depth: usize,
+    /// median of the max depth of each function in the module
+    pub median_depth: f64,
+}
+
+pub fn build_report(
+    calls: &[Call],
+    depths: &Depths<CallHierarchyItem>,
+    root: &Url,
+    options: &ReportOptions,
+) -> AnalysisReport {
+    build_report_with(calls, depths, root, options, |item| {
+        options.name_format.build(item, root)
+    })
+}
+
+/// Like [`build_report`], but with every function named by `to_key`, e.g. by
+/// its stable id for the [`NormalizedReport`].
+fn build_report_with<F>(
+    calls: &[Call],
+    depths: &Depths<CallHierarchyItem>,
+    root: &Url,
+    options: &ReportOptions,
+    to_key: F,
+) -> AnalysisReport
+where
+    F: Fn(&CallHierarchyItem) -> String,
+{
+    let (problem_items, roots) = find_problems_and_roots(calls, depths, root, options);
+    let path_graph = PathGraph::new(calls, options);
+    let fan = FanCounts::new(calls);
+
+    let mut ok = vec![];
+    let mut problems = vec![];
+    for (item, paths) in depths {
+        let is_root = roots.contains(&item.clone().into());
+        let mut item_report = build_item_report(item, paths, is_root, &fan, options, &to_key);
+        let sampled_paths = sample_paths(paths, options.max_paths);
+        item_report.path_metadata = path_graph.build_path_metadata(&sampled_paths, options);
+
+        if problem_items.contains(&item.clone().into()) {
+            problems.push(item_report);
+        } else {
+            ok.push(item_report);
+        }
+    }
+
+    // unreachable functions are still analyzed functions, ok without any paths
+    let unreachable = find_unreachable(calls, depths, options);
+    for item in &unreachable {
+        let is_root = roots.contains(&(*item).clone().into());
+        let mut item_report = build_item_report(item, &[], is_root, &fan, options, &to_key);
+        item_report.path_metadata = path_graph.build_path_metadata(&[], options);
+        ok.push(item_report);
+    }
+    let mut unreachable = unreachable.into_iter().map(&to_key).collect::<Vec<_>>();
+
+    let sort_key = |item: &ItemReport| (item.name.clone(), item.fan_in, item.fan_out);
+    sort_reports(&mut ok, options.sort_by, sort_key);
+    sort_reports(&mut problems, options.sort_by, sort_key);
+    unreachable.sort();
+
+    let cycles = build_cycles(calls, &to_key);
+
+    let summary = build_summary(
+        ok.iter().chain(problems.iter()).map(|item| &item.depths),
+        problems.len(),
+        options,
+    );
+    let module_summary = build_module_summary(depths, root, options);
+    let directional_mismatch = build_directional_mismatch(depths, options, &to_key);
+
+    AnalysisReport {
+        ok,
+        problems,
+        cycles,
+        unreachable,
+        summary,
+        module_summary,
+        directional_mismatch,
+    }
+}
+
+/// The [`build_report`] of the analysis with every function named by its
+/// stable id, and described once in the report's `nodes`.
+pub fn build_normalized_report(
+    calls: &[Call],
+    depths: &Depths<CallHierarchyItem>,
+    root: &Url,
+    options: &ReportOptions,
+) -> NormalizedReport {
+    let to_id = |item: &CallHierarchyItem| HashableCallHierarchyItem(item.clone()).stable_id();
+    let AnalysisReport {
+        ok,
+        problems,
+        cycles,
+        unreachable,
+        summary,
+        module_summary,
+        directional_mismatch,
+    } = build_report_with(calls, depths, root, options, to_id);
+
+    // unreachable definitions aren't in any call or path
+    let unreachable_ids = unreachable.iter().collect::<HashSet<_>>();
+    let unreachable_definitions = options
+        .definitions
+        .iter()
+        .filter(|item| unreachable_ids.contains(&to_id(item)));
+    let items = calls
+        .iter()
+        .flat_map(|call| [&call.caller, &call.callee])
+        .chain(depths.iter().map(|(item, _)| item))
+        .chain(unreachable_definitions);
+
+    let mut nodes = BTreeMap::new();
+    for item in items {
+        nodes.entry(to_id(item)).or_insert_with(|| NodeReport {
+            name: options.name_format.build(item, root),
+            kind: item.kind,
+            location: Location {
+                uri: item.uri.clone(),
+                range: item.selection_range,
+            },
+        });
+    }
+
+    let mut edges = calls
+        .iter()
+        .map(|call| (to_id(&call.caller), to_id(&call.callee)))
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    edges.sort();
+
+    NormalizedReport {
+        nodes,
+        edges,
+        ok: ok.into_iter().map(NormalizedItemReport::from).collect(),
+        problems: problems
+            .into_iter()
+            .map(NormalizedItemReport::from)
+            .collect(),
+        cycles,
+        unreachable,
+        summary,
+        module_summary,
+        directional_mismatch,
+    }
+}
+
+fn find_problems_and_roots(
+    calls: &[Call],
+    depths: &Depths<CallHierarchyItem>,
+    root: &Url,
+    options: &ReportOptions,
+) -> (
+    HashSet<HashableCallHierarchyItem>,
+    HashSet<HashableCallHierarchyItem>,
+) {
+    let mut problem_items = find_items_with_depth_spread::<_, HashableCallHierarchyItem>(
+        depths,
+        options.depth_tolerance,
+    );
+    let roots = match &options.roots {
+        Some(roots) => roots.clone(),
+        None => find_roots(&to_hashable_calls(calls))
+            .into_iter()
+            .cloned()
+            .collect::<HashSet<_>>(),
+    };
+
+    if options.root_causes_only {
+        problem_items = find_root_cause_problems(&problem_items, depths);
+    }
+
+    if options.exclude_roots_from_problems {
+        problem_items.retain(|item| !roots.contains(item));
+    }
+
+    if let Some(OwnerFilter { codeowners, owner }) = &options.problems_owner {
+        problem_items.retain(|item| {
+            relative_path(&item.0.uri, root)
+                .is_some_and(|path| codeowners.is_owned_by(&path, owner))
+        });
+    }
+
+    if let Some(files) = &options.problems_files {
+        // the server may spell the files the way git's paths weren't spelled
+        let files = files.iter().map(file_key).collect::<HashSet<_>>();
+        problem_items.retain(|item| files.contains(&file_key(&item.0.uri)));
+    }
+
+    if let Some(baseline) = &options.baseline {
+        problem_items.retain(|item| !baseline.contains(&item.0, root));
+    }
+
+    (problem_items, roots)
+}
+
+/// The functions [`build_report`] reports as problems, in the order of `depths`.
+pub fn find_problems<'a>(
+    calls: &[Call],
+    depths: &'a Depths<CallHierarchyItem>,
+    root: &Url,
+    options: &ReportOptions,
+) -> Vec<&'a CallHierarchyItem> {
+    let (problem_items, _) = find_problems_and_roots(calls, depths, root, options);
+
+    depths
+        .iter()
+        .map(|(item, _)| item)
+        .filter(|item| problem_items.contains(&HashableCallHierarchyItem((*item).clone())))
+        .collect()
+}
+
+/// The analyzed functions no root reaches, e.g. functions that are never
+/// called, or are only called by functions that aren't reached either.
+fn find_unreachable<'a>(
+    calls: &'a [Call],
+    depths: &Depths<CallHierarchyItem>,
+    options: &'a ReportOptions,
+) -> Vec<&'a CallHierarchyItem> {
+    let mut reached = depths
+        .iter()
+        .map(|(item, _)| HashableCallHierarchyItem(item.clone()))
+        .chain(options.truncation.cut_off.iter().cloned())
+        .collect::<HashSet<_>>();
+
+    options
+        .definitions
+        .iter()
+        .chain(calls.iter().flat_map(|call| [&call.caller, &call.callee]))
+        // reported items are marked as reached, so duplicates are reported once
+        .filter(|item| reached.insert(HashableCallHierarchyItem((*item).clone())))
+        .collect()
+}
+
+fn build_cycles<F>(calls: &[Call], to_key: F) -> Vec<Vec<String>>
+where
+    F: Fn(&CallHierarchyItem) -> String,
+{
+    find_recursion_clusters(calls)
+        .into_iter()
+        .map(|cluster| cluster.into_iter().map(&to_key).collect())
+        .collect()
+}
+
+fn distinct_depths(paths: &[Vec<CallHierarchyItem>]) -> Vec<usize> {
+    let mut depths = paths
+        .iter()
+        .map(|path| path.len() - 1)
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    depths.sort();
+
+    depths
+}
+
+fn build_path_snippets(
+    paths: &[Vec<CallHierarchyItem>],
+    options: &ReportOptions,
+) -> Option<Vec<Vec<Option<String>>>> {
+    let snippets = options.snippets.as_ref()?;
+
+    let path_snippets = paths
+        .iter()
+        .map(|path| {
+            path.windows(2)
+                .map(|call| {
+                    snippets
+                        .get(&(call[0].clone().into(), call[1].clone().into()))
+                        .cloned()
+                })
+                .collect()
+        })
+        .collect();
+
+    Some(path_snippets)
+}
+
+/// Sort `reports` in `sort_by` order, `key` gives each report's name (or id),
+/// fan-in and fan-out. Ties are sorted by name.
+fn sort_reports<T, F>(reports: &mut [T], sort_by: SortBy, key: F)
+where
+    F: Fn(&T) -> (String, usize, usize),
+{
+    reports.sort_by_cached_key(|report| {
+        let (name, fan_in, fan_out) = key(report);
+        let fan = match sort_by {
+            SortBy::Name => 0,
+            SortBy::FanIn => fan_in,
+            SortBy::FanOut => fan_out,
+        };
+
+        (std::cmp::Reverse(fan), name)
+    });
+}
+
+/// amount of distinct callers and callees of each function
+struct FanCounts(HashMap<HashableCallHierarchyItem, (usize, usize)>);
+
+impl FanCounts {
+    fn new(calls: &[Call]) -> Self {
+        let edges = to_hashable_calls(calls).into_iter().collect::<HashSet<_>>();
+
+        let mut counts = HashMap::<_, (usize, usize)>::new();
+        for (caller, callee) in edges {
+            counts.entry(callee).or_default().0 += 1;
+            counts.entry(caller).or_default().1 += 1;
+        }
+
+        Self(counts)
+    }
+
+    fn get(&self, item: &CallHierarchyItem) -> (usize, usize) {
+        self.0
+            .get(&HashableCallHierarchyItem(item.clone()))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// call graph data needed for [`PathMetadata`]
+#[derive(Default)]
+struct PathGraph {
+    weights: HashMap<(HashableCallHierarchyItem, HashableCallHierarchyItem), usize>,
+    in_cycle: HashSet<HashableCallHierarchyItem>,
+}
+
+impl PathGraph {
+    fn new(calls: &[Call], options: &ReportOptions) -> Self {
+        if !options.path_metadata {
+            return Self::default();
+        }
+
+        let mut weights = HashMap::new();
+        for call in calls {
+            *weights
+                .entry((call.caller.clone().into(), call.callee.clone().into()))
+                .or_default() += call.num_call_sites();
+        }
+
+        let in_cycle = find_cycles(&to_hashable_calls(calls))
+            .into_iter()
+            .flatten()
+            .collect();
+
+        Self { weights, in_cycle }
+    }
+
+    fn build_path_metadata(
+        &self,
+        paths: &[Vec<CallHierarchyItem>],
+        options: &ReportOptions,
+    ) -> Option<Vec<PathMetadata>> {
+        if !options.path_metadata {
+            return None;
+        }
+
+        let path_metadata = paths
+            .iter()
+            .map(|path| {
+                let hops = path
+                    .iter()
+                    .map(|hop| HashableCallHierarchyItem(hop.clone()))
+                    .collect::<Vec<_>>();
+
+                PathMetadata {
+                    weight: hops
+                        .windows(2)
+                        .map(|call| {
+                            self.weights
+                                .get(&(call[0].clone(), call[1].clone()))
+                                .copied()
+                                .unwrap_or_default()
+                        })
+                        .sum(),
+                    crosses_cycle: hops.iter().any(|hop| self.in_cycle.contains(hop)),
+                    has_incomplete_node: hops
+                        .iter()
+                        .any(|hop| options.incomplete_items.contains(hop)),
+                }
+            })
+            .collect();
+
+        Some(path_metadata)
+    }
+}
+
+fn build_item_report<F>(
+    item: &CallHierarchyItem,
+    paths: &[Vec<CallHierarchyItem>],
+    is_root: bool,
+    fan: &FanCounts,
+    options: &ReportOptions,
+    to_key: &F,
+) -> ItemReport
+where
+    F: Fn(&CallHierarchyItem) -> String,
+{
+    let depths = distinct_depths(paths);
+    let (fan_in, fan_out) = fan.get(item);
+    let num_paths = paths.len();
+    let paths = sample_paths(paths, options.max_paths);
+    let path_snippets = build_path_snippets(&paths, options);
+
+    let paths = paths
+        .iter()
+        .map(|path| path.iter().map(to_key).collect())
+        .collect();
+
+    ItemReport {
+        name: to_key(item),
+        kind: item.kind,
+        location: Location {
+            uri: item.uri.clone(),
+            range: item.selection_range,
+        },
+        min_depth: depths.first().copied().unwrap_or_default(),
+        max_depth: depths.last().copied().unwrap_or_default(),
+        num_depths: depths.len(),
+        depths,
+        fan_in,
+        fan_out,
+        is_root,
+        truncated: options.truncation.truncated.contains(&item.clone().into()),
+        cycle_end: options.truncation.cycle_ends.contains(&item.clone().into()),
+        num_paths,
+        paths,
+        path_snippets,
+        path_metadata: None,
+    }
+}
+
+/// At most `max_paths` of `paths`, the shortest and the longest ones and the
+/// others spread evenly between them, in order of length. All of them without
+/// a max.
+///
+/// Only the reported paths are sampled, depths and problems are found from all
+/// of them.
+fn sample_paths(
+    paths: &[Vec<CallHierarchyItem>],
+    max_paths: Option<usize>,
+) -> Cow<'_, [Vec<CallHierarchyItem>]> {
+    let max_paths = match max_paths {
+        Some(max_paths) if max_paths < paths.len() => max_paths,
+        _ => return Cow::Borrowed(paths),
+    };
+
+    let mut by_length = paths.iter().collect::<Vec<_>>();
+    by_length.sort_by_key(|path| path.len());
+
+    let last = paths.len() - 1;
+    let sample = match max_paths {
+        0 => vec![],
+        1 => vec![by_length[0].clone()],
+        _ => (0..max_paths)
+            .map(|i| by_length[i * last / (max_paths - 1)].clone())
+            .collect(),
+    };
+
+    Cow::Owned(sample)
+}
+
+/// Summary of the items with `item_depths`, empty for the items no root reaches.
+fn build_summary<'a, I>(item_depths: I, num_problems: usize, options: &ReportOptions) -> Summary
+where
+    I: Iterator<Item = &'a Vec<usize>>,
+{
+    let mut num_functions = 0;
+    let mut num_roots = 0;
+    let mut max_depth = 0;
+    let mut max_depths = vec![];
+    let mut depth_histogram = BTreeMap::new();
+    for depths in item_depths {
+        num_functions += 1;
+        max_depths.extend(depths.last());
+        for &depth in depths {
+            *depth_histogram.entry(depth).or_default() += 1;
+        }
+
+        // an item is a root if it is reached by a path consisting only of itself
+        if depths.first() == Some(&0) {
+            num_roots += 1;
+        }
+
+        if let Some(&depth) = depths.last() {
+            max_depth = max_depth.max(depth);
+        }
+    }
+
+    Summary {
+        num_functions,
+        num_roots,
+        num_problems,
+        max_depth,
+        depth_histogram,
+        median_depth: if options.with_stats {
+            median(max_depths)
+        } else {
+            None
+        },
+    }
+}
+
+fn build_directional_mismatch<F>(
+    depths: &Depths<CallHierarchyItem>,
+    options: &ReportOptions,
+    to_key: F,
+) -> Option<Vec<DirectionalMismatch>>
+where
+    F: Fn(&CallHierarchyItem) -> String,
+{
+    let reverse_depths = options.reverse_depths.as_ref()?;
+    let max_depths = |depths: &Depths<CallHierarchyItem>| {
+        depths
+            .iter()
+            .filter_map(|(item, paths)| {
+                let max_depth = paths.iter().map(|path| path.len() - 1).max()?;
+                Some((HashableCallHierarchyItem(item.clone()), max_depth))
+            })
+            .collect::<HashMap<_, _>>()
+    };
+    let reverse_max_depths = max_depths(reverse_depths);
+
+    let mut mismatches = max_depths(depths)
+        .into_iter()
+        .filter_map(|(item, forward_depth)| {
+            let reverse_depth = *reverse_max_depths.get(&item)?;
+            (forward_depth.abs_diff(reverse_depth) > options.direction_tolerance).then(|| {
+                DirectionalMismatch {
+                    item: to_key(&item.0),
+                    forward_depth,
+                    reverse_depth,
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    mismatches.sort_by(|a, b| a.item.cmp(&b.item));
+
+    Some(mismatches)
+}
+
+/// Serialize each reported path in the folded stacks format used by flamegraph
+/// tools (`root;a;b 1`), so the flamegraph's height shows the reported depths.
+pub fn build_folded_stacks(report: &AnalysisReport) -> String {
+    let mut lines = report
+        .ok
+        .iter()
+        .chain(report.problems.iter())
+        .flat_map(|item| item.paths.iter())
+        .map(|path| {
+            let frames = path
+                .iter()
+                .map(|hop| hop.replace(';', "_"))
+                .collect::<Vec<_>>();
+            format!("{} 1", frames.join(";"))
+        })
+        .collect::<Vec<_>>();
+    lines.sort();
+
+    lines.join("\n")
+}
+
+pub fn build_badge(summary: &Summary) -> Badge {
+    Badge {
+        schema_version: 1,
+        label: "depth problems".to_string(),
+        message: summary.num_problems.to_string(),
+        color: if summary.num_problems == 0 {
+            "brightgreen"
+        } else {
+            "red"
+        }
+        .to_string(),
+    }
+}
+
+fn build_module_summary(
+    depths: &Depths<CallHierarchyItem>,
+    root: &Url,
+    options: &ReportOptions,
+) -> Option<BTreeMap<String, ModuleSummary>> {
+    if !options.with_stats || options.group_by != Some(GroupBy::Module) {
+        return None;
+    }
+
+    let mut module_max_depths: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+    for (item, paths) in depths {
+        if let Some(max_depth) = paths.iter().map(|path| path.len() - 1).max() {
+            module_max_depths
+                .entry(module_of(item, root))
+                .or_default()
+                .push(max_depth);
+        }
+    }
+
+    let module_summary = module_max_depths
+        .into_iter()
+        .map(|(module, max_depths)| {
+            let summary = ModuleSummary {
+                num_functions: max_depths.len(),
+                max_depth: max_depths.iter().copied().max().unwrap_or_default(),
+                median_depth: median(max_depths).unwrap_or_default(),
+            };
+
+            (module, summary)
+        })
+        .collect();
+
+    Some(module_summary)
+}
+
+/// The top level module of the item's file relative to `root`, ignoring a
+/// leading `src` directory. Files directly in the root are their own module.
+fn module_of(item: &CallHierarchyItem, root: &Url) -> String {
+    let path = relative_path(&item.uri, root).unwrap_or_else(|| item.uri.to_string());
+    let path = path.strip_prefix("src/").unwrap_or(&path);
+
+    match path.split_once('/') {
+        Some((module, _)) => module.to_string(),
+        None => path
+            .rsplit_once('.')
+            .map_or(path, |(stem, _)| stem)
+            .to_string(),
+    }
+}
+
+/// The report as JSON, with its `ok` and `problems` functions nested by the
+/// directories of their files relative to `root`, so
+/// `{"src": {"lsp": {"json_rpc.rs": [...]}}}` has the functions of
+/// `src/lsp/json_rpc.rs`.
+pub fn group_report_by_dir(report: &AnalysisReport, root: &Url) -> serde_json::Value {
+    let mut value = serde_json::to_value(report).unwrap();
+    value["ok"] = serde_json::to_value(group_by_dir(&report.ok, root)).unwrap();
+    value["problems"] = serde_json::to_value(group_by_dir(&report.problems, root)).unwrap();
+
+    value
+}
+
+/// `items` nested by the directories of their files, see [`group_report_by_dir`].
+pub fn group_by_dir(items: &[ItemReport], root: &Url) -> BTreeMap<String, DirTree> {
+    let mut tree = BTreeMap::new();
+    for item in items {
+        let uri = &item.location.uri;
+        let path = relative_path(uri, root).unwrap_or_else(|| uri.to_string());
+        let (dirs, file) = match path.rsplit_once('/') {
+            Some((dirs, file)) => (dirs.split('/').collect(), file),
+            None => (vec![], path.as_str()),
+        };
+
+        let mut dir = &mut tree;
+        for name in dirs {
+            let entry = dir
+                .entry(name.to_string())
+                .or_insert_with(|| DirTree::Dir(BTreeMap::new()));
+            dir = match entry {
+                DirTree::Dir(children) => children,
+                // a path can't be both a file and a directory
+                DirTree::File(_) => unreachable!("'{}' is a file and a directory", name),
+            };
+        }
+
+        match dir
+            .entry(file.to_string())
+            .or_insert_with(|| DirTree::File(vec![]))
+        {
+            DirTree::File(file_items) => file_items.push(item.clone()),
+            DirTree::Dir(_) => unreachable!("'{}' is a file and a directory", file),
+        }
+    }
+
+    tree
+}
+
+fn median(mut values: Vec<usize>) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+
+    values.sort();
+    let middle = values.len() / 2;
+    let median = if values.len().is_multiple_of(2) {
+        (values[middle - 1] + values[middle]) as f64 / 2.0
+    } else {
+        values[middle] as f64
+    };
+
+    Some(median)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use lsp_types::{CallHierarchyItem, Range, Url};
+
+    use super::{
+        build_badge, build_folded_stacks, build_normalized_report, build_report, find_problems,
+        group_report_by_dir, DirectionalMismatch, GroupBy, ItemReport, NormalizedItemReport,
+        OwnerFilter, PathMetadata, ReportOptions, SortBy,
+    };
+    use crate::codeowners::CodeOwners;
+    use crate::test_util::{call, item};
+    use crate::{
+        baseline::Baseline, get_function_depths, get_truncated_function_depths,
+        hashable_call_hierarchy_item::HashableCallHierarchyItem, DEFAULT_MAX_DEPTH,
+    };
+
+    #[test]
+    fn test_build_report() {
+        let root = Url::parse("file:///project").unwrap();
+        let (r1, r2, y, x) = (item("r1", 0), item("r2", 1), item("y", 2), item("x", 3));
+        let calls = vec![call(&r1, &x), call(&r2, &y), call(&y, &x)];
+
+        let depths = get_function_depths(&calls);
+        let report = build_report(&calls, &depths, &root, &Default::default());
+
+        let ok = report
+            .ok
+            .iter()
+            .map(|i| i.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ok,
+            vec!["/src/main.rs:r1", "/src/main.rs:r2", "/src/main.rs:y"]
+        );
+
+        assert_eq!(report.problems.len(), 1);
+        assert_eq!(report.problems[0].name, "/src/main.rs:x");
+        assert_eq!(report.problems[0].depths, vec![1, 2]);
+        let problem = &report.problems[0];
+        assert_eq!(
+            (problem.min_depth, problem.max_depth, problem.num_depths),
+            (1, 2, 2)
+        );
+        assert_eq!(report.problems[0].location.range, x.selection_range);
+
+        assert!(report.cycles.is_empty());
+        assert_eq!(report.summary.num_functions, 4);
+        assert_eq!(report.summary.num_roots, 2);
+        assert_eq!(report.summary.num_problems, 1);
+        assert_eq!(report.summary.max_depth, 2);
+        assert_eq!(
+            report.summary.depth_histogram,
+            BTreeMap::from([(0, 2), (1, 2), (2, 1)])
+        );
+
+        // a spread of one level is tolerated
+        let options = ReportOptions {
+            depth_tolerance: 1,
+            ..Default::default()
+        };
+        let report = build_report(&calls, &depths, &root, &options);
+        assert!(report.problems.is_empty());
+        assert_eq!(report.ok.len(), 4);
+    }
+
+    #[test]
+    fn test_fan_in_and_fan_out() {
+        let root = Url::parse("file:///project").unwrap();
+        let (r1, r2, y, x) = (item("r1", 0), item("r2", 1), item("y", 2), item("x", 3));
+        // the same call from two call sites counts once
+        let calls = vec![call(&r1, &x), call(&r2, &y), call(&y, &x), call(&r1, &x)];
+        let depths = get_function_depths(&calls);
+
+        let options = ReportOptions {
+            sort_by: SortBy::FanIn,
+            ..Default::default()
+        };
+        let report = build_report(&calls, &depths, &root, &options);
+
+        let ok = report
+            .ok
+            .iter()
+            .map(|i| (i.name.as_str(), i.fan_in, i.fan_out))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ok,
+            [
+                ("/src/main.rs:y", 1, 1),
+                ("/src/main.rs:r1", 0, 1),
+                ("/src/main.rs:r2", 0, 1)
+            ]
+        );
+        assert_eq!(
+            (report.problems[0].fan_in, report.problems[0].fan_out),
+            (2, 0)
+        );
+
+        let normalized = build_normalized_report(&calls, &depths, &root, &options);
+        let problem = &normalized.problems[0];
+        assert_eq!((problem.fan_in, problem.fan_out), (2, 0));
+    }
+
+    #[test]
+    fn test_build_report_truncation() {
+        let root = Url::parse("file:///project").unwrap();
+        let (main, a, b, c) = (item("main", 0), item("a", 1), item("b", 2), item("c", 3));
+        let calls = vec![call(&main, &a), call(&a, &b), call(&b, &c)];
+        let (depths, truncation) = get_truncated_function_depths(&calls, None, None, 1).unwrap();
+
+        let options = ReportOptions {
+            truncation,
+            ..Default::default()
+        };
+        let report = build_report(&calls, &depths, &root, &options);
+
+        let truncated = report
+            .ok
+            .iter()
+            .map(|i| (i.name.as_str(), i.truncated))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            truncated,
+            [("/src/main.rs:a", true), ("/src/main.rs:main", false)]
+        );
+        // the cut off functions are reached, just too deep
+        assert!(report.unreachable.is_empty());
+    }
+
+    #[test]
+    fn test_build_report_cycle_ends() {
+        let root = Url::parse("file:///project").unwrap();
+        let (main, a, b) = (item("main", 0), item("a", 1), item("b", 2));
+        let calls = vec![call(&main, &a), call(&a, &b), call(&b, &a)];
+        let (depths, truncation) =
+            get_truncated_function_depths(&calls, None, None, DEFAULT_MAX_DEPTH).unwrap();
+
+        let options = ReportOptions {
+            truncation,
+            ..Default::default()
+        };
+        let report = build_report(&calls, &depths, &root, &options);
+
+        // the path through `b` ended at its call back to `a`
+        let cycle_ends = report
+            .ok
+            .iter()
+            .map(|i| (i.name.as_str(), i.cycle_end))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            cycle_ends,
+            [
+                ("/src/main.rs:a", false),
+                ("/src/main.rs:b", true),
+                ("/src/main.rs:main", false)
+            ]
+        );
+
+        let normalized = build_normalized_report(&calls, &depths, &root, &options);
+        assert_eq!(normalized.ok.iter().filter(|i| i.cycle_end).count(), 1);
+    }
+
+    #[test]
+    fn test_build_report_max_paths() {
+        let root = Url::parse("file:///project").unwrap();
+        let x = item("x", 0);
+        // `x` is reached from 4 roots at depths 1 to 4
+        let mut calls = vec![];
+        for root_index in 0..4 {
+            let line = 1 + root_index * 4;
+            let chain = (0..=root_index)
+                .map(|hop| item(&format!("r{}_{}", root_index, hop), line + hop))
+                .chain([x.clone()])
+                .collect::<Vec<_>>();
+            calls.extend(chain.windows(2).map(|hop| call(&hop[0], &hop[1])));
+        }
+        let depths = get_function_depths(&calls);
+
+        let options = ReportOptions {
+            max_paths: Some(3),
+            path_metadata: true,
+            ..Default::default()
+        };
+        let report = build_report(&calls, &depths, &root, &options);
+
+        let x = &report.problems[0];
+        assert_eq!(x.depths, [1, 2, 3, 4]);
+        assert_eq!(x.num_paths, 4);
+        let lengths = x.paths.iter().map(Vec::len).collect::<Vec<_>>();
+        assert_eq!(lengths, [2, 3, 5]);
+        assert_eq!(x.path_metadata.as_ref().map(Vec::len), Some(3));
+
+        let report = build_normalized_report(&calls, &depths, &root, &options);
+        assert_eq!(report.problems[0].num_paths, 4);
+        assert_eq!(report.problems[0].paths.len(), 3);
+    }
+
+    #[test]
+    fn test_build_report_baseline() {
+        let root = Url::parse("file:///project").unwrap();
+        let (r1, r2, y, x) = (item("r1", 0), item("r2", 1), item("y", 2), item("x", 3));
+        let (z, w) = (item("z", 4), item("w", 5));
+        let calls = vec![
+            call(&r1, &x),
+            call(&r2, &y),
+            call(&y, &x),
+            call(&r1, &w),
+            call(&y, &z),
+            call(&z, &w),
+        ];
+        let depths = get_function_depths(&calls);
+
+        let baseline = Baseline::new([&x], &root);
+        assert_eq!(
+            find_problems(&calls, &depths, &root, &Default::default()).len(),
+            2
+        );
+
+        let options = ReportOptions {
+            baseline: Some(baseline),
+            ..Default::default()
+        };
+        let report = build_report(&calls, &depths, &root, &options);
+
+        let problems = report
+            .problems
+            .iter()
+            .map(|i| i.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(problems, ["/src/main.rs:w"]);
+        assert_eq!(report.summary.num_problems, 1);
+        assert!(report.ok.iter().any(|i| i.name == "/src/main.rs:x"));
+        assert_eq!(
+            find_problems(&calls, &depths, &root, &options),
+            [&depths.iter().find(|(item, _)| item.name == "w").unwrap().0]
+        );
+    }
+
+    #[test]
+    fn test_build_badge() {
+        let root = Url::parse("file:///project").unwrap();
+        let (r1, r2, y, x) = (item("r1", 0), item("r2", 1), item("y", 2), item("x", 3));
+        let calls = vec![call(&r1, &x), call(&r2, &y), call(&y, &x)];
+
+        let depths = get_function_depths(&calls);
+        let report = build_report(&calls, &depths, &root, &Default::default());
+        let badge = serde_json::to_value(build_badge(&report.summary)).unwrap();
+
+        assert_eq!(
+            badge,
+            serde_json::json!({
+                "schemaVersion": 1,
+                "label": "depth problems",
+                "message": "1",
+                "color": "red",
+            })
+        );
+
+        let report = build_report(
+            &calls[..1],
+            &get_function_depths(&calls[..1]),
+            &root,
+            &Default::default(),
+        );
+        assert_eq!(build_badge(&report.summary).color, "brightgreen");
+    }
+
+    #[test]
+    fn test_build_report_roots() {
+        let root = Url::parse("file:///project").unwrap();
+        let (r, a, x) = (item("r", 0), item("a", 1), item("x", 2));
+        let calls = vec![call(&r, &x), call(&a, &x)];
+        // depths from a different view of the graph, where `r` is also reached
+        let depths = vec![
+            (r.clone(), vec![vec![r.clone()], vec![a.clone(), r.clone()]]),
+            (x.clone(), vec![vec![r.clone(), x.clone()]]),
+        ];
+
+        let report = build_report(&calls, &depths, &root, &Default::default());
+        assert_eq!(report.problems.len(), 1);
+        assert!(report.problems[0].is_root);
+        let x_report = report.ok.iter().find(|item| item.name.ends_with(":x"));
+        assert!(x_report.is_some_and(|item| !item.is_root));
+
+        let options = ReportOptions {
+            exclude_roots_from_problems: true,
+            ..Default::default()
+        };
+        let report = build_report(&calls, &depths, &root, &options);
+        assert!(report.problems.is_empty());
+        // `a` isn't in the depths, so it's ok without paths
+        assert_eq!(report.ok.len(), 3);
+    }
+
+    #[test]
+    fn test_build_report_unreachable() {
+        let root = Url::parse("file:///project").unwrap();
+        let (r, x, unused) = (item("r", 0), item("x", 1), item("unused", 2));
+        // a cycle no root calls into
+        let (a, b) = (item("a", 3), item("b", 4));
+        let calls = vec![call(&r, &x), call(&a, &b), call(&b, &a)];
+        let depths = get_function_depths(&calls);
+        let options = ReportOptions {
+            definitions: vec![r.clone(), x.clone(), unused.clone(), a.clone()],
+            ..Default::default()
+        };
+
+        let report = build_report(&calls, &depths, &root, &options);
+        assert_eq!(report.summary.num_functions, 5);
+        assert_eq!(
+            report.unreachable,
+            ["/src/main.rs:a", "/src/main.rs:b", "/src/main.rs:unused"]
+        );
+        // they are still ok, without any paths
+        assert_eq!(report.ok.len(), 5);
+        let unused_report = report.ok.iter().find(|item| item.name.ends_with(":unused"));
+        assert!(unused_report.is_some_and(|item| item.paths.is_empty() && !item.is_root));
+
+        let report = build_normalized_report(&calls, &depths, &root, &options);
+        assert_eq!(report.nodes.len(), 5);
+        assert_eq!(report.summary.num_functions, 5);
+        assert_eq!(report.ok.len(), 5);
+        assert_eq!(report.unreachable.len(), 3);
+        assert!(report
+            .unreachable
+            .contains(&HashableCallHierarchyItem(unused).stable_id()));
+    }
+
+    #[test]
+    fn test_build_report_unreachable_roots() {
+        let root = Url::parse("file:///project").unwrap();
+        let (r, x, orphan) = (item("r", 0), item("x", 1), item("orphan", 2));
+        let calls = vec![call(&r, &x), call(&orphan, &x)];
+        // `orphan` has no callers, but only `r` is a root
+        let depths = vec![
+            (r.clone(), vec![vec![r.clone()]]),
+            (x.clone(), vec![vec![r.clone(), x.clone()]]),
+        ];
+        let options = ReportOptions {
+            roots: Some([HashableCallHierarchyItem(r)].into()),
+            ..Default::default()
+        };
+
+        let report = build_report(&calls, &depths, &root, &options);
+        assert_eq!(report.unreachable, ["/src/main.rs:orphan"]);
+        let orphan_report = report.ok.iter().find(|item| item.name.ends_with(":orphan"));
+        assert!(orphan_report.is_some_and(|item| item.fan_in == 0 && !item.is_root));
+        assert_eq!(report.summary.num_roots, 1);
+
+        let report = build_normalized_report(&calls, &depths, &root, &options);
+        assert_eq!(report.ok.iter().filter(|item| item.is_root).count(), 1);
+    }
+
+    #[test]
+    fn test_build_report_path_metadata() {
+        let root = Url::parse("file:///project").unwrap();
+        let (main, a, b, c) = (item("main", 0), item("a", 1), item("b", 2), item("c", 3));
+        let mut calls = vec![call(&main, &a), call(&a, &b), call(&b, &a), call(&main, &c)];
+        calls[0].from_ranges = vec![Range::default(); 2];
+        calls[1].from_ranges = vec![Range::default()];
+
+        let depths = get_function_depths(&calls);
+        let options = ReportOptions {
+            path_metadata: true,
+            incomplete_items: [HashableCallHierarchyItem(c.clone())].into(),
+            ..Default::default()
+        };
+        let report = build_report(&calls, &depths, &root, &options);
+        let path_metadata = |name: &str| {
+            let item = report.ok.iter().find(|item| item.name.ends_with(name));
+            item.unwrap().path_metadata.clone().unwrap()
+        };
+
+        assert_eq!(
+            path_metadata(":b"),
+            vec![PathMetadata {
+                weight: 3,
+                crosses_cycle: true,
+                has_incomplete_node: false,
+            }]
+        );
+        // a call without ranges is still a call site
+        assert_eq!(
+            path_metadata(":c"),
+            vec![PathMetadata {
+                weight: 1,
+                crosses_cycle: false,
+                has_incomplete_node: true,
+            }]
+        );
+
+        let report = build_report(&calls, &depths, &root, &Default::default());
+        assert!(report.ok.iter().all(|item| item.path_metadata.is_none()));
+    }
+
+    #[test]
+    fn test_build_report_problems_owner() {
+        let root = Url::parse("file:///project").unwrap();
+        let (r1, r2, y, x) = (item("r1", 0), item("r2", 1), item("y", 2), item("x", 3));
+        let calls = vec![call(&r1, &x), call(&r2, &y), call(&y, &x)];
+        let depths = get_function_depths(&calls);
+        let options_for = |owner: &str| ReportOptions {
+            problems_owner: Some(OwnerFilter {
+                codeowners: CodeOwners::parse("/src/ @src-team"),
+                owner: owner.to_string(),
+            }),
+            ..Default::default()
+        };
+
+        let report = build_report(&calls, &depths, &root, &options_for("@src-team"));
+        assert_eq!(report.problems.len(), 1);
+
+        let report = build_report(&calls, &depths, &root, &options_for("@other-team"));
+        assert!(report.problems.is_empty());
+        assert_eq!(report.summary.num_problems, 0);
+    }
+
+    #[test]
+    fn test_build_report_problems_files() {
+        let root = Url::parse("file:///project").unwrap();
+        let (r1, r2, y, x) = (item("r1", 0), item("r2", 1), item("y", 2), item("x", 3));
+        let calls = vec![call(&r1, &x), call(&r2, &y), call(&y, &x)];
+        let depths = get_function_depths(&calls);
+        let options_for = |file: &str| ReportOptions {
+            problems_files: Some([Url::parse(file).unwrap()].into()),
+            ..Default::default()
+        };
+
+        let report = build_report(
+            &calls,
+            &depths,
+            &root,
+            &options_for("file:///project/src/main.rs"),
+        );
+        assert_eq!(report.problems.len(), 1);
+
+        let report = build_report(
+            &calls,
+            &depths,
+            &root,
+            &options_for("file:///project/src/db.rs"),
+        );
+        assert!(report.problems.is_empty());
+        assert_eq!(report.ok.len(), 4);
+
+        // files spelled differently than the server's uris still match
+        let mut drive_x = x.clone();
+        drive_x.uri = Url::parse("file:///c%3A/project/src/main.rs").unwrap();
+        let calls = vec![call(&r1, &drive_x), call(&r2, &y), call(&y, &drive_x)];
+        let depths = get_function_depths(&calls);
+        let report = build_report(
+            &calls,
+            &depths,
+            &root,
+            &options_for("file:///C:/project/src/main.rs"),
+        );
+        assert_eq!(report.problems.len(), 1);
+    }
+
+    #[test]
+    fn test_group_report_by_dir() {
+        let root = Url::parse("file:///project").unwrap();
+        let in_file = |name: &str, line: u32, file: &str| CallHierarchyItem {
+            uri: Url::parse(&format!("file:///project/{}", file)).unwrap(),
+            ..item(name, line)
+        };
+        let (main, a, b, build) = (
+            item("main", 0),
+            in_file("a", 1, "src/lsp/client.rs"),
+            in_file("b", 2, "src/lsp/client.rs"),
+            in_file("build", 3, "build.rs"),
+        );
+        let calls = vec![call(&main, &a), call(&a, &b), call(&build, &b)];
+        let depths = get_function_depths(&calls);
+        let report = build_report(&calls, &depths, &root, &Default::default());
+
+        let grouped = group_report_by_dir(&report, &root);
+
+        let names = |items: &serde_json::Value| {
+            items
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|item| item["name"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(&grouped["ok"]["src"]["main.rs"]),
+            ["/src/main.rs:main"]
+        );
+        assert_eq!(
+            names(&grouped["ok"]["src"]["lsp"]["client.rs"]),
+            ["/src/lsp/client.rs:a"]
+        );
+        assert_eq!(names(&grouped["ok"]["build.rs"]), ["/build.rs:build"]);
+        assert_eq!(
+            names(&grouped["problems"]["src"]["lsp"]["client.rs"]),
+            ["/src/lsp/client.rs:b"]
+        );
+        assert_eq!(grouped["summary"]["num_problems"], 1);
+    }
+
+    #[test]
+    fn test_build_folded_stacks() {
+        let root = Url::parse("file:///project").unwrap();
+        let (main, a, b) = (item("main", 0), item("a", 1), item("b", 2));
+        let calls = vec![call(&main, &a), call(&a, &b)];
+
+        let depths = get_function_depths(&calls);
+        let report = build_report(&calls, &depths, &root, &Default::default());
+
+        assert_eq!(
+            build_folded_stacks(&report),
+            "/src/main.rs:main 1\n\
+             /src/main.rs:main;/src/main.rs:a 1\n\
+             /src/main.rs:main;/src/main.rs:a;/src/main.rs:b 1"
+        );
+    }
+
+    #[test]
+    fn test_build_report_cycles() {
+        let root = Url::parse("file:///project").unwrap();
+        let (main, a, b, c) = (item("main", 0), item("a", 1), item("b", 2), item("c", 3));
+        let calls = vec![
+            call(&main, &c),
+            call(&c, &c),
+            call(&main, &b),
+            call(&b, &a),
+            call(&a, &b),
+        ];
+
+        let depths = get_function_depths(&calls);
+        let report = build_report(&calls, &depths, &root, &Default::default());
+
+        // in the order of the functions' first calls
+        assert_eq!(
+            report.cycles,
+            vec![
+                vec!["/src/main.rs:c".to_string()],
+                vec!["/src/main.rs:b".to_string(), "/src/main.rs:a".to_string()]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_report_module_summary() {
+        let root = Url::parse("file:///project").unwrap();
+        let in_file = |name: &str, line: u32, file: &str| CallHierarchyItem {
+            uri: Url::parse(&format!("file:///project/{}", file)).unwrap(),
+            ..item(name, line)
+        };
+        let main = in_file("main", 0, "src/main.rs");
+        let (a, b, c) = (
+            in_file("a", 0, "src/lsp/mod.rs"),
+            in_file("b", 1, "src/lsp/mod.rs"),
+            in_file("c", 0, "src/lsp/client.rs"),
+        );
+        let calls = vec![call(&main, &a), call(&a, &b), call(&b, &c)];
+
+        let depths = get_function_depths(&calls);
+        let options = ReportOptions {
+            group_by: Some(GroupBy::Module),
+            with_stats: true,
+            ..Default::default()
+        };
+        let report = build_report(&calls, &depths, &root, &options);
+
+        let module_summary = report.module_summary.unwrap();
+        assert_eq!(
+            module_summary.keys().collect::<Vec<_>>(),
+            vec!["lsp", "main"]
+        );
+        assert_eq!(module_summary["lsp"].num_functions, 3);
+        assert_eq!(module_summary["lsp"].max_depth, 3);
+        assert_eq!(module_summary["lsp"].median_depth, 2.0);
+        assert_eq!(module_summary["main"].median_depth, 0.0);
+        assert_eq!(report.summary.median_depth, Some(1.5));
+
+        let report = build_report(&calls, &depths, &root, &Default::default());
+        assert!(report.module_summary.is_none());
+        assert!(report.summary.median_depth.is_none());
+    }
+
+    #[test]
+    fn test_build_report_directional_mismatch() {
+        let root = Url::parse("file:///project").unwrap();
+        let (main, a, b) = (item("main", 0), item("a", 1), item("b", 2));
+        let incoming = vec![call(&main, &a), call(&a, &b)];
+        // the outgoing calls of `a` are missing
+        let outgoing = vec![call(&main, &a), call(&main, &b)];
+
+        let options = ReportOptions {
+            reverse_depths: Some(get_function_depths(&outgoing)),
+            ..Default::default()
+        };
+        let report = build_report(&incoming, &get_function_depths(&incoming), &root, &options);
+
+        assert_eq!(
+            report.directional_mismatch,
+            Some(vec![DirectionalMismatch {
+                item: "/src/main.rs:b".to_string(),
+                forward_depth: 2,
+                reverse_depth: 1,
+            }])
+        );
+    }
+
+    #[test]
+    fn test_build_normalized_report() {
+        let root = Url::parse("file:///project").unwrap();
+        let (main, a, b) = (item("main", 0), item("a", 1), item("b", 2));
+        let calls = vec![call(&main, &a), call(&main, &b), call(&a, &b)];
+
+        let depths = get_function_depths(&calls);
+        let report = build_normalized_report(&calls, &depths, &root, &Default::default());
+        let id = |item: &CallHierarchyItem| HashableCallHierarchyItem(item.clone()).stable_id();
+
+        assert_eq!(report.nodes.len(), 3);
+        assert_eq!(report.nodes[&id(&a)].name, "/src/main.rs:a");
+        assert_eq!(report.edges.len(), 3);
+        assert!(report.edges.contains(&(id(&main), id(&a))));
+
+        let b_report = report
+            .ok
+            .iter()
+            .chain(report.problems.iter())
+            .find(|item| item.id == id(&b))
+            .unwrap();
+        assert!(b_report.paths.contains(&vec![id(&main), id(&b)]));
+
+        // every path hop must be described in the node table
+        for item in report.ok.iter().chain(report.problems.iter()) {
+            assert!(report.nodes.contains_key(&item.id));
+            for hop in item.paths.iter().flatten() {
+                assert!(report.nodes.contains_key(hop));
+            }
+        }
+    }
+
+    #[test]
+    fn test_normalized_report_matches_report() {
+        let root = Url::parse("file:///project").unwrap();
+        let (r1, r2, y, x, unused) = (
+            item("r1", 0),
+            item("r2", 1),
+            item("y", 2),
+            item("x", 3),
+            item("unused", 4),
+        );
+        let calls = vec![call(&r1, &x), call(&r2, &y), call(&y, &x)];
+        let depths = get_function_depths(&calls);
+        let options = ReportOptions {
+            definitions: vec![unused.clone()],
+            path_metadata: true,
+            ..Default::default()
+        };
+
+        let report = build_report(&calls, &depths, &root, &options);
+        let normalized = build_normalized_report(&calls, &depths, &root, &options);
+
+        // naming the functions of the normalized report gives the report back
+        let name = |id: &String| normalized.nodes[id].name.clone();
+        let to_names = |items: &[NormalizedItemReport]| {
+            let mut items = items
+                .iter()
+                .map(|item| NormalizedItemReport {
+                    id: name(&item.id),
+                    paths: item
+                        .paths
+                        .iter()
+                        .map(|path| path.iter().map(name).collect())
+                        .collect(),
+                    ..item.clone()
+                })
+                .collect::<Vec<_>>();
+            items.sort_by(|a, b| a.id.cmp(&b.id));
+            items
+        };
+        let from_report = |items: &[ItemReport]| {
+            items
+                .iter()
+                .cloned()
+                .map(NormalizedItemReport::from)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(to_names(&normalized.ok), from_report(&report.ok));
+        assert_eq!(
+            to_names(&normalized.problems),
+            from_report(&report.problems)
+        );
+        assert_eq!(
+            normalized.unreachable.iter().map(name).collect::<Vec<_>>(),
+            report.unreachable
+        );
+        assert_eq!(normalized.summary, report.summary);
+    }
+}
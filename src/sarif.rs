@@ -0,0 +1,190 @@
+use lsp_types::Url;
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use serde_json::{json, Value};
+
+use crate::{
+    project_root::relative_path,
+    report::{AnalysisReport, ItemReport},
+};
+
+/// id of the rule every problem is a result of
+pub const INCONSISTENT_CALL_DEPTH_RULE_ID: &str = "inconsistent-call-depth";
+
+/// base id the result locations are relative to, resolved to the project root
+const PROJECT_ROOT_BASE_ID: &str = "PROJECTROOT";
+
+/// characters escaped in relative artifact uris, the ones urls escape in paths
+const PATH_ESCAPES: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// A [SARIF 2.1.0](https://docs.oasis-open.org/sarif/sarif/v2.1.0/sarif-v2.1.0.html)
+/// log of a single run, with a result per problem located at the problem's
+/// name, for code scanning tools.
+pub fn to_sarif(report: &AnalysisReport, root: &Url) -> Value {
+    let rule = json!({
+        "id": INCONSISTENT_CALL_DEPTH_RULE_ID,
+        "name": "InconsistentCallDepth",
+        "shortDescription": { "text": "Function reached at different depths" },
+        "fullDescription": {
+            "text": "The function is reached from the roots of the call graph by paths of \
+                     different lengths, so it's called from different levels of abstraction."
+        },
+        "defaultConfiguration": { "level": "warning" },
+    });
+
+    let results = report
+        .problems
+        .iter()
+        .map(|problem| to_result(problem, root))
+        .collect::<Vec<_>>();
+
+    // the base uri must end with a slash for the relative uris to resolve
+    // inside the root instead of next to it
+    let root_uri = format!("{}/", root.as_str().trim_end_matches('/'));
+
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": [rule],
+                }
+            },
+            "originalUriBaseIds": {
+                PROJECT_ROOT_BASE_ID: { "uri": root_uri },
+            },
+            "results": results,
+        }],
+    })
+}
+
+fn to_result(problem: &ItemReport, root: &Url) -> Value {
+    let depths = problem
+        .depths
+        .iter()
+        .map(|depth| depth.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let message = format!(
+        "{} is reached at depths {}, a spread of {}",
+        problem.name,
+        depths,
+        problem.max_depth - problem.min_depth
+    );
+
+    // sarif lines and columns are 1-based, lsp ones are 0-based
+    let range = problem.location.range;
+    json!({
+        "ruleId": INCONSISTENT_CALL_DEPTH_RULE_ID,
+        "ruleIndex": 0,
+        "level": "warning",
+        "message": { "text": message },
+        "locations": [{
+            "physicalLocation": {
+                "artifactLocation": artifact_location(&problem.location.uri, root),
+                "region": {
+                    "startLine": range.start.line + 1,
+                    "startColumn": range.start.character + 1,
+                    "endLine": range.end.line + 1,
+                    "endColumn": range.end.character + 1,
+                },
+            }
+        }],
+    })
+}
+
+/// `uri` relative to the project root's base id when it's in the project, or
+/// the absolute `uri` otherwise.
+fn artifact_location(uri: &Url, root: &Url) -> Value {
+    match relative_path(uri, root) {
+        Some(path) => json!({
+            "uri": utf8_percent_encode(&path, PATH_ESCAPES).to_string(),
+            "uriBaseId": PROJECT_ROOT_BASE_ID,
+        }),
+        None => json!({ "uri": uri }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::Url;
+    use serde_json::json;
+
+    use super::{artifact_location, to_sarif};
+    use crate::{
+        get_function_depths,
+        report::build_report,
+        test_util::{call, item},
+    };
+
+    #[test]
+    fn test_to_sarif() {
+        let root = Url::parse("file:///project").unwrap();
+        let (r1, r2, y, x) = (item("r1", 0), item("r2", 1), item("y", 2), item("x", 3));
+        let calls = [call(&r1, &x), call(&r2, &y), call(&y, &x)];
+        let depths = get_function_depths(&calls);
+        let report = build_report(&calls, &depths, &root, &Default::default());
+
+        let sarif = to_sarif(&report, &root);
+
+        assert_eq!(sarif["version"], "2.1.0");
+        let run = &sarif["runs"][0];
+        assert_eq!(run["tool"]["driver"]["name"], "code_depth");
+        assert_eq!(
+            run["tool"]["driver"]["rules"][0]["id"],
+            "inconsistent-call-depth"
+        );
+        assert_eq!(
+            run["originalUriBaseIds"]["PROJECTROOT"]["uri"],
+            "file:///project/"
+        );
+        assert_eq!(
+            run["results"],
+            json!([{
+                "ruleId": "inconsistent-call-depth",
+                "ruleIndex": 0,
+                "level": "warning",
+                "message": { "text": "/src/main.rs:x is reached at depths 1, 2, a spread of 1" },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": "src/main.rs", "uriBaseId": "PROJECTROOT" },
+                        "region": {
+                            "startLine": 4,
+                            "startColumn": 1,
+                            "endLine": 4,
+                            "endColumn": 2,
+                        },
+                    }
+                }],
+            }])
+        );
+    }
+
+    #[test]
+    fn test_artifact_location() {
+        let url = |url: &str| Url::parse(url).unwrap();
+        let root = url("file:///C:/project");
+
+        assert_eq!(
+            artifact_location(&url("file:///c%3A/project/my%20src/main.rs"), &root),
+            json!({ "uri": "my%20src/main.rs", "uriBaseId": "PROJECTROOT" })
+        );
+        // a sibling sharing the root's prefix keeps its absolute uri
+        assert_eq!(
+            artifact_location(&url("file:///C:/project2/main.rs"), &root),
+            json!({ "uri": "file:///C:/project2/main.rs" })
+        );
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use lsp_types::{CallHierarchyItem, Url};
+use serde::{Deserialize, Serialize};
+
+use crate::{hashable_call_hierarchy_item::HashableCallHierarchyItem, Depths, NameFormat};
+
+/// The depths and problems of an analysis run, for comparing later runs
+/// against, see [`diff_snapshots`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// each function by its short name, without its location so moving a
+    /// function doesn't make it a different one, like [`crate::baseline::Baseline`]
+    pub functions: BTreeMap<String, FunctionSnapshot>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FunctionSnapshot {
+    /// the distinct depths the function is reached at, sorted
+    pub depths: Vec<usize>,
+    pub is_problem: bool,
+}
+
+impl Snapshot {
+    pub fn new<'a>(
+        depths: &Depths<CallHierarchyItem>,
+        problems: impl IntoIterator<Item = &'a CallHierarchyItem>,
+        root: &Url,
+        name_format: &NameFormat,
+    ) -> Self {
+        let problems = problems
+            .into_iter()
+            .map(|item| HashableCallHierarchyItem(item.clone()))
+            .collect::<HashSet<_>>();
+
+        let functions = depths
+            .iter()
+            .map(|(item, paths)| {
+                let depths = paths
+                    .iter()
+                    .map(|path| path.len() - 1)
+                    .collect::<BTreeSet<_>>();
+                let function = FunctionSnapshot {
+                    depths: depths.into_iter().collect(),
+                    is_problem: problems.contains(&HashableCallHierarchyItem(item.clone())),
+                };
+
+                (name_format.compact().build(item, root), function)
+            })
+            .collect();
+
+        Self { functions }
+    }
+
+    fn problems(&self) -> BTreeSet<&String> {
+        self.functions
+            .iter()
+            .filter(|(_, function)| function.is_problem)
+            .map(|(name, _)| name)
+            .collect()
+    }
+}
+
+/// What changed between two analysis runs, see [`diff_snapshots`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotDiff {
+    /// problems of the new run the old run didn't have
+    pub added_problems: Vec<String>,
+    /// problems of the old run the new run doesn't have
+    pub removed_problems: Vec<String>,
+    /// functions of both runs reached at different depths in each
+    pub changed_depths: Vec<DepthChange>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DepthChange {
+    pub name: String,
+    pub old_depths: Vec<usize>,
+    pub new_depths: Vec<usize>,
+}
+
+/// Compare two runs, matching their functions by short name.
+pub fn diff_snapshots(old: &Snapshot, new: &Snapshot) -> SnapshotDiff {
+    let (old_problems, new_problems) = (old.problems(), new.problems());
+
+    let changed_depths = new
+        .functions
+        .iter()
+        .filter_map(|(name, new_function)| {
+            let old_function = old.functions.get(name)?;
+
+            (old_function.depths != new_function.depths).then(|| DepthChange {
+                name: name.clone(),
+                old_depths: old_function.depths.clone(),
+                new_depths: new_function.depths.clone(),
+            })
+        })
+        .collect();
+
+    SnapshotDiff {
+        added_problems: new_problems
+            .difference(&old_problems)
+            .map(|name| name.to_string())
+            .collect(),
+        removed_problems: old_problems
+            .difference(&new_problems)
+            .map(|name| name.to_string())
+            .collect(),
+        changed_depths,
+    }
+}
+
+impl SnapshotDiff {
+    /// A line per change, `+` for added problems, `-` for removed ones and `~`
+    /// for changed depths.
+    pub fn to_text(&self) -> String {
+        let added = self.added_problems.iter().map(|name| format!("+ {}", name));
+        let removed = self
+            .removed_problems
+            .iter()
+            .map(|name| format!("- {}", name));
+        let changed = self.changed_depths.iter().map(|change| {
+            format!(
+                "~ {} {:?} -> {:?}",
+                change.name, change.old_depths, change.new_depths
+            )
+        });
+
+        added
+            .chain(removed)
+            .chain(changed)
+            .map(|line| format!("{}\n", line))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::Url;
+
+    use super::{diff_snapshots, DepthChange, Snapshot, SnapshotDiff};
+    use crate::{
+        get_function_depths,
+        test_util::{call, item},
+        NameFormat, NameStyle,
+    };
+
+    #[test]
+    fn test_diff_snapshots() {
+        let root = Url::parse("file:///project").unwrap();
+        let (r1, r2, y, x) = (item("r1", 0), item("r2", 1), item("y", 2), item("x", 3));
+
+        // `x` is reached at depths 1 and 2
+        let old_depths = get_function_depths(&[call(&r1, &x), call(&r2, &y), call(&y, &x)]);
+        let old = Snapshot::new(&old_depths, [&x], &root, &Default::default());
+        // `y` is reached at depths 1 and 2 instead
+        let new_depths = get_function_depths(&[call(&r1, &y), call(&r2, &x), call(&x, &y)]);
+        let new = Snapshot::new(&new_depths, [&y], &root, &Default::default());
+
+        let diff = diff_snapshots(&old, &new);
+
+        assert_eq!(
+            diff,
+            SnapshotDiff {
+                added_problems: vec!["/src/main.rs:y".to_string()],
+                removed_problems: vec!["/src/main.rs:x".to_string()],
+                changed_depths: vec![
+                    DepthChange {
+                        name: "/src/main.rs:x".to_string(),
+                        old_depths: vec![1, 2],
+                        new_depths: vec![1],
+                    },
+                    DepthChange {
+                        name: "/src/main.rs:y".to_string(),
+                        old_depths: vec![1],
+                        new_depths: vec![1, 2],
+                    },
+                ],
+            }
+        );
+        assert_eq!(
+            diff.to_text(),
+            "+ /src/main.rs:y\n- /src/main.rs:x\n~ /src/main.rs:x [1, 2] -> [1]\n~ /src/main.rs:y [1] -> [1, 2]\n"
+        );
+        assert!(diff_snapshots(&new, &new).changed_depths.is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_ignores_locations() {
+        let root = Url::parse("file:///project").unwrap();
+        let name_format = NameFormat {
+            style: NameStyle::Location,
+            ..Default::default()
+        };
+        let snapshot = |lines: [u32; 4]| {
+            let [r1, r2, y, x] = [("r1", 0), ("r2", 1), ("y", 2), ("x", 3)]
+                .map(|(name, index)| item(name, lines[index]));
+            let depths = get_function_depths(&[call(&r1, &x), call(&r2, &y), call(&y, &x)]);
+
+            Snapshot::new(&depths, [&x], &root, &name_format)
+        };
+
+        // an edit above the functions moves all of them
+        let (old, new) = (snapshot([0, 1, 2, 3]), snapshot([10, 11, 12, 13]));
+
+        assert!(old.functions.contains_key("/src/main.rs:x"));
+        assert_eq!(diff_snapshots(&old, &new), SnapshotDiff::default());
+    }
+}
@@ -0,0 +1,100 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
+
+use log::debug;
+
+use crate::{fs_util::read_files, hashable_call_hierarchy_item::HashableCallHierarchyItem, Call};
+
+/// Source line of a call, keyed by (caller, callee).
+pub type CallSnippets = HashMap<(HashableCallHierarchyItem, HashableCallHierarchyItem), String>;
+
+/// Read the trimmed source line of the first call site of each call, reading at
+/// most `io_concurrency` files at once.
+///
+/// Only the line of each call site is used, so the snippets don't depend on the
+/// position encoding negotiated with the server.
+pub async fn read_call_snippets(calls: &[Call], io_concurrency: usize) -> CallSnippets {
+    let mut paths = HashSet::new();
+    for call in calls {
+        match call.caller.uri.to_file_path() {
+            Ok(path) => {
+                paths.insert(path);
+            }
+            Err(_) => debug!("can't read snippets from non file uri: {}", call.caller.uri),
+        }
+    }
+
+    let mut files: HashMap<PathBuf, Vec<String>> = HashMap::new();
+    for (path, text) in read_files(paths.into_iter().collect(), io_concurrency).await {
+        match text {
+            Ok(text) => {
+                files.insert(path, text.lines().map(String::from).collect());
+            }
+            Err(e) => debug!("failed to read snippets from {:?}: {}", path, e),
+        }
+    }
+
+    let mut snippets = HashMap::new();
+    for call in calls {
+        let Some(call_site) = call.from_ranges.first() else {
+            continue;
+        };
+
+        let Some(lines) = call
+            .caller
+            .uri
+            .to_file_path()
+            .ok()
+            .and_then(|path| files.get(&path))
+        else {
+            continue;
+        };
+
+        if let Some(line) = lines.get(call_site.start.line as usize) {
+            snippets.insert(
+                (call.caller.clone().into(), call.callee.clone().into()),
+                format!("{}: {}", call_site.start.line + 1, line.trim()),
+            );
+        }
+    }
+
+    snippets
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{CallHierarchyItem, Position, Range, Url};
+
+    use super::read_call_snippets;
+    use crate::{test_util, Call};
+
+    fn item(uri: &Url, name: &str, line: u32) -> CallHierarchyItem {
+        CallHierarchyItem {
+            uri: uri.clone(),
+            ..test_util::item(name, line)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_call_snippets() {
+        let path = std::env::temp_dir().join("code_depth_test_read_call_snippets.rs");
+        std::fs::write(&path, "fn main() {\n    foo();\n}\n\nfn foo() {}\n").unwrap();
+        let uri = Url::from_file_path(&path).unwrap();
+
+        let (main, foo) = (item(&uri, "main", 0), item(&uri, "foo", 4));
+        let calls = vec![Call {
+            caller: main.clone(),
+            callee: foo.clone(),
+            from_ranges: vec![Range::new(Position::new(1, 4), Position::new(1, 7))],
+        }];
+
+        let snippets = read_call_snippets(&calls, 4).await;
+
+        assert_eq!(
+            snippets.get(&(main.into(), foo.into())),
+            Some(&"2: foo();".to_string())
+        );
+    }
+}
@@ -0,0 +1,220 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
+use lsp_types::{CallHierarchyItem, Url};
+use rusqlite::{params, Connection};
+
+use crate::{
+    hashable_call_hierarchy_item::HashableCallHierarchyItem, item_path, report::AnalysisReport,
+    Call, Depths, NameFormat,
+};
+
+const SCHEMA: &str = "
+DROP TABLE IF EXISTS functions;
+DROP TABLE IF EXISTS edges;
+DROP TABLE IF EXISTS paths;
+DROP TABLE IF EXISTS problems;
+
+CREATE TABLE functions (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    file TEXT NOT NULL,
+    line INTEGER NOT NULL,
+    kind TEXT NOT NULL
+);
+CREATE TABLE edges (
+    caller_id TEXT NOT NULL,
+    callee_id TEXT NOT NULL,
+    weight INTEGER NOT NULL
+);
+CREATE TABLE paths (
+    item_id TEXT NOT NULL,
+    root_id TEXT NOT NULL,
+    length INTEGER NOT NULL
+);
+CREATE TABLE problems (
+    item_id TEXT PRIMARY KEY,
+    min_depth INTEGER NOT NULL,
+    max_depth INTEGER NOT NULL
+);
+";
+
+/// Write the analysis to a SQLite database at `path`, replacing any tables
+/// written to it before.
+///
+/// Functions are identified by their stable ids, `line` is 1-based, an edge's
+/// `weight` is its amount of call sites and a path's `length` is the depth of
+/// `item_id` along it.
+pub fn write_database(
+    path: &Path,
+    calls: &[Call],
+    depths: &Depths<CallHierarchyItem>,
+    report: &AnalysisReport,
+    root: &Url,
+    name_format: &NameFormat,
+) -> rusqlite::Result<()> {
+    let mut connection = Connection::open(path)?;
+    let transaction = connection.transaction()?;
+    transaction.execute_batch(SCHEMA)?;
+
+    let to_id = |item: &CallHierarchyItem| HashableCallHierarchyItem(item.clone()).stable_id();
+
+    let mut functions = BTreeMap::new();
+    let items = calls
+        .iter()
+        .flat_map(|call| [&call.caller, &call.callee])
+        .chain(depths.iter().map(|(item, _)| item));
+    for item in items {
+        functions.entry(to_id(item)).or_insert(item);
+    }
+
+    let mut insert_function =
+        transaction.prepare("INSERT INTO functions VALUES (?1, ?2, ?3, ?4, ?5)")?;
+    for (id, item) in &functions {
+        insert_function.execute(params![
+            id,
+            name_format.build(item, root),
+            item_path(&item.uri, root),
+            item.selection_range.start.line + 1,
+            format!("{:?}", item.kind),
+        ])?;
+    }
+
+    let mut weights: BTreeMap<(String, String), usize> = BTreeMap::new();
+    for call in calls {
+        *weights
+            .entry((to_id(&call.caller), to_id(&call.callee)))
+            .or_default() += call.num_call_sites();
+    }
+
+    let mut insert_edge = transaction.prepare("INSERT INTO edges VALUES (?1, ?2, ?3)")?;
+    for ((caller_id, callee_id), weight) in &weights {
+        insert_edge.execute(params![caller_id, callee_id, weight])?;
+    }
+
+    let mut insert_path = transaction.prepare("INSERT INTO paths VALUES (?1, ?2, ?3)")?;
+    for (item, paths) in depths {
+        for path in paths {
+            insert_path.execute(params![to_id(item), to_id(&path[0]), path.len() - 1])?;
+        }
+    }
+
+    let problem_items = report.problem_items();
+    let problem_depths = depths
+        .iter()
+        .filter(|(item, _)| problem_items.contains(item))
+        .map(|(item, paths)| {
+            let lengths = paths.iter().map(|path| path.len() - 1);
+            let min_depth = lengths.clone().min().unwrap_or_default();
+            let max_depth = lengths.max().unwrap_or_default();
+
+            (to_id(item), (min_depth, max_depth))
+        })
+        .collect::<HashMap<_, _>>();
+
+    let mut insert_problem = transaction.prepare("INSERT INTO problems VALUES (?1, ?2, ?3)")?;
+    for (id, (min_depth, max_depth)) in &problem_depths {
+        insert_problem.execute(params![id, min_depth, max_depth])?;
+    }
+
+    drop((insert_function, insert_edge, insert_path, insert_problem));
+    transaction.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{CallHierarchyItem, Range, Url};
+    use rusqlite::Connection;
+
+    use super::write_database;
+    use crate::{
+        get_function_depths,
+        report::{build_report, ReportOptions},
+        test_util::item,
+        Call,
+    };
+
+    fn call(caller: &CallHierarchyItem, callee: &CallHierarchyItem, sites: usize) -> Call {
+        Call {
+            caller: caller.clone(),
+            callee: callee.clone(),
+            from_ranges: vec![Range::default(); sites],
+        }
+    }
+
+    #[test]
+    fn test_write_database() {
+        let root = Url::parse("file:///project").unwrap();
+        let (r1, r2, y, x) = (item("r1", 0), item("r2", 1), item("y", 2), item("x", 3));
+        // calls without ranges, e.g. imported ones, are still a call site
+        let calls = vec![call(&r1, &x, 2), call(&r2, &y, 0), call(&y, &x, 1)];
+        let depths = get_function_depths(&calls);
+
+        let report = build_report(&calls, &depths, &root, &Default::default());
+
+        let path = std::env::temp_dir().join("code_depth_test_write_database.db");
+        // writing twice must replace the first write
+        write_database(&path, &calls, &depths, &report, &root, &Default::default()).unwrap();
+        write_database(&path, &calls, &depths, &report, &root, &Default::default()).unwrap();
+
+        let connection = Connection::open(&path).unwrap();
+        let count = |table: &str| -> usize {
+            connection
+                .query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+                    row.get(0)
+                })
+                .unwrap()
+        };
+        assert_eq!(count("functions"), 4);
+        assert_eq!(count("edges"), 3);
+        assert_eq!(count("paths"), 5);
+
+        let (name, weight): (String, usize) = connection
+            .query_row(
+                "SELECT f.name, e.weight FROM edges e JOIN functions f ON f.id = e.callee_id
+                 WHERE e.weight > 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((name.as_str(), weight), ("/src/main.rs:x", 2));
+        assert_eq!(
+            connection
+                .query_row("SELECT MIN(weight) FROM edges", [], |row| row
+                    .get::<_, usize>(0))
+                .unwrap(),
+            1
+        );
+
+        let (min_depth, max_depth): (usize, usize) = connection
+            .query_row("SELECT min_depth, max_depth FROM problems", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!((min_depth, max_depth), (1, 2));
+    }
+
+    #[test]
+    fn test_write_database_reported_problems() {
+        let root = Url::parse("file:///project").unwrap();
+        let (r1, r2, y, x) = (item("r1", 0), item("r2", 1), item("y", 2), item("x", 3));
+        let calls = vec![call(&r1, &x, 1), call(&r2, &y, 1), call(&y, &x, 1)];
+        let depths = get_function_depths(&calls);
+        let options = ReportOptions {
+            depth_tolerance: 1,
+            ..Default::default()
+        };
+        let report = build_report(&calls, &depths, &root, &options);
+
+        let path = std::env::temp_dir().join("code_depth_test_write_database_problems.db");
+        write_database(&path, &calls, &depths, &report, &root, &Default::default()).unwrap();
+
+        let connection = Connection::open(&path).unwrap();
+        let problems: usize = connection
+            .query_row("SELECT COUNT(*) FROM problems", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(problems, 0);
+    }
+}
@@ -0,0 +1,31 @@
+//! Fixtures shared by the crate's tests.
+
+use lsp_types::{CallHierarchyItem, Position, Range, SymbolKind, Url};
+
+use crate::Call;
+
+/// A function named `name` on `line` of `/project/src/main.rs`, the line
+/// keeping functions of the same name apart.
+pub fn item(name: &str, line: u32) -> CallHierarchyItem {
+    let range = Range::new(Position::new(line, 0), Position::new(line, 1));
+
+    CallHierarchyItem {
+        name: name.to_string(),
+        kind: SymbolKind::FUNCTION,
+        tags: None,
+        detail: None,
+        uri: Url::parse("file:///project/src/main.rs").unwrap(),
+        range,
+        selection_range: range,
+        data: None,
+    }
+}
+
+/// A call from `caller` to `callee` without call sites.
+pub fn call(caller: &CallHierarchyItem, callee: &CallHierarchyItem) -> Call {
+    Call {
+        caller: caller.clone(),
+        callee: callee.clone(),
+        from_ranges: vec![],
+    }
+}
@@ -3,7 +3,7 @@ use std::{path::Path, process::Stdio, time::Duration};
 use lsp_types::Url;
 use tokio::process::Command;
 
-use code_depth::{self, lsp::LspClient};
+use code_depth::{self, lsp::LspClient, Call};
 
 const SAMPLE_PROJECT_PATH: &str = "tests/jdtls/sample_java_project";
 
@@ -37,34 +37,45 @@ async fn test_lsp_client() {
         .await
         .expect("init failed");
 
-    let workspace_files =
-        code_depth::get_workspace_files(&mut client, &root, Duration::from_secs(5))
+    let workspace_files = code_depth::get_workspace_files(
+        &mut client,
+        &root,
+        &Default::default(),
+        Duration::from_secs(5),
+    )
+    .await
+    .expect("get_function_definitions failed");
+
+    let calls =
+        code_depth::get_function_calls(&mut client, &workspace_files, &root, &Default::default())
             .await
-            .expect("get_function_definitions failed");
-
-    let calls = code_depth::get_function_calls(&mut client, &workspace_files, &root)
-        .await
-        .expect("get_function_calls failed");
+            .expect("get_function_calls failed");
 
     let mut short_calls: Vec<String> = calls
         .iter()
-        .map(|(s, t)| {
-            format!(
-                "{}:{}->{}:{}",
-                Path::new(s.uri.path())
-                    .file_name()
-                    .unwrap()
-                    .to_str()
-                    .unwrap(),
-                s.name.split('(').next().unwrap(),
-                Path::new(t.uri.path())
-                    .file_name()
-                    .unwrap()
-                    .to_str()
-                    .unwrap(),
-                t.name.split('(').next().unwrap()
-            )
-        })
+        .map(
+            |Call {
+                 caller: s,
+                 callee: t,
+                 ..
+             }| {
+                format!(
+                    "{}:{}->{}:{}",
+                    Path::new(s.uri.path())
+                        .file_name()
+                        .unwrap()
+                        .to_str()
+                        .unwrap(),
+                    s.name.split('(').next().unwrap(),
+                    Path::new(t.uri.path())
+                        .file_name()
+                        .unwrap()
+                        .to_str()
+                        .unwrap(),
+                    t.name.split('(').next().unwrap()
+                )
+            },
+        )
         .collect();
 
     short_calls.sort();
@@ -80,7 +91,7 @@ async fn test_lsp_client() {
         "didn't find all function calls"
     );
 
-    let depths = code_depth::get_function_depths(calls);
+    let depths = code_depth::get_function_depths(&calls);
 
     let short_item_depths = code_depth::build_short_fn_depths(&root, &depths);
 
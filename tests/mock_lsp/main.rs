@@ -0,0 +1,562 @@
+use std::{collections::HashSet, path::Path, time::Duration};
+
+use lsp_types::Url;
+use serde_json::{json, Value};
+
+use code_depth::{self, error::CodeDepthError, Call, CallDirection, InitOptions};
+
+mod mock_server;
+
+use mock_server::{methods, MockServer};
+
+const ROOT: &str = "file:///mock/project";
+
+/// lsp error code for a request invalidated by a change in the server's state
+const CONTENT_MODIFIED: i64 = -32801;
+
+fn capabilities() -> Value {
+    json!({
+        "capabilities": {
+            "workspaceSymbolProvider": true,
+            "documentSymbolProvider": true,
+            "callHierarchyProvider": true,
+        }
+    })
+}
+
+fn range(line: u32) -> Value {
+    json!({
+        "start": { "line": line, "character": 3 },
+        "end": { "line": line, "character": 7 },
+    })
+}
+
+fn function(name: &str, line: u32) -> Value {
+    json!({
+        "name": name,
+        "kind": 12,
+        "range": range(line),
+        "selectionRange": range(line),
+    })
+}
+
+fn workspace_symbol(name: &str, uri: &str) -> Value {
+    json!({
+        "name": name,
+        "kind": 12,
+        "location": { "uri": uri, "range": range(0) },
+    })
+}
+
+fn call_hierarchy_item(name: &str, file: &str, line: u32) -> Value {
+    json!({
+        "name": name,
+        "kind": 12,
+        "uri": format!("{}/{}", ROOT, file),
+        "range": range(line),
+        "selectionRange": range(line),
+    })
+}
+
+#[tokio::test]
+async fn test_init() {
+    let root = Url::parse(ROOT).unwrap();
+
+    let (mut client, server) = MockServer::default()
+        .respond("initialize", capabilities())
+        .start();
+    code_depth::init(&mut client, root.clone())
+        .await
+        .expect("init failed");
+    client.shutdown().await.unwrap();
+
+    let messages = server.await.unwrap();
+    assert_eq!(
+        methods(&messages),
+        ["initialize", "initialized", "shutdown", "exit"]
+    );
+    assert_eq!(messages[0]["params"]["rootUri"], ROOT);
+
+    // a server without the call hierarchy can't be analyzed
+    let (mut client, _server) = MockServer::default()
+        .respond(
+            "initialize",
+            json!({ "capabilities": {
+                "workspaceSymbolProvider": true,
+                "documentSymbolProvider": true,
+            }}),
+        )
+        .start();
+    let result = code_depth::init(&mut client, root).await;
+    let Err(CodeDepthError::MissingCapability { methods }) = result else {
+        panic!("expected missing capability error, got: {:?}", result);
+    };
+    assert_eq!(methods, ["callHierarchy/incomingCalls"]);
+}
+
+#[tokio::test]
+async fn test_socket_server_keeps_running() {
+    let root = Url::parse(ROOT).unwrap();
+
+    // neither shutting down nor dropping the client stops a shared server
+    let (mut client, server) = MockServer::default()
+        .respond("initialize", capabilities())
+        .start_tcp()
+        .await;
+    code_depth::init(&mut client, root.clone())
+        .await
+        .expect("init failed");
+    client.shutdown().await.unwrap();
+    drop(client);
+    assert_eq!(
+        methods(&server.await.unwrap()),
+        ["initialize", "initialized"]
+    );
+
+    let (mut client, server) = MockServer::default()
+        .respond("initialize", capabilities())
+        .start_tcp()
+        .await;
+    code_depth::init(&mut client, root)
+        .await
+        .expect("init failed");
+    drop(client);
+    assert_eq!(
+        methods(&server.await.unwrap()),
+        ["initialize", "initialized"]
+    );
+}
+
+#[tokio::test]
+async fn test_init_with_options() {
+    let root = Url::parse(ROOT).unwrap();
+    let options = InitOptions {
+        initialization_options: Some(json!({ "cargo": { "features": "all" } })),
+        ..Default::default()
+    };
+
+    let (mut client, server) = MockServer::default()
+        .respond("initialize", capabilities())
+        .start();
+    code_depth::init_with_options(&mut client, root, &[CallDirection::Incoming], &options)
+        .await
+        .expect("init failed");
+    client.shutdown().await.unwrap();
+
+    let messages = server.await.unwrap();
+    assert_eq!(
+        messages[0]["params"]["initializationOptions"],
+        json!({ "cargo": { "features": "all" } })
+    );
+    assert_eq!(
+        messages[0]["params"]["workspaceFolders"],
+        json!([{ "uri": ROOT, "name": "project" }])
+    );
+}
+
+#[tokio::test]
+async fn test_init_with_workspace_folders() {
+    let root = Url::parse(ROOT).unwrap();
+    let options = InitOptions {
+        workspace_folders: vec![
+            Url::parse(&format!("{}/api", ROOT)).unwrap(),
+            Url::parse(&format!("{}/web", ROOT)).unwrap(),
+        ],
+        ..Default::default()
+    };
+
+    let (mut client, server) = MockServer::default()
+        .respond("initialize", capabilities())
+        .start();
+    code_depth::init_with_options(&mut client, root, &[CallDirection::Incoming], &options)
+        .await
+        .expect("init failed");
+    client.shutdown().await.unwrap();
+
+    let messages = server.await.unwrap();
+    let params = &messages[0]["params"];
+    assert_eq!(params["rootUri"], ROOT);
+    assert_eq!(
+        params["workspaceFolders"],
+        json!([
+            { "uri": format!("{}/api", ROOT), "name": "api" },
+            { "uri": format!("{}/web", ROOT), "name": "web" },
+        ])
+    );
+    assert_eq!(
+        params["capabilities"]["workspace"]["workspaceFolders"],
+        true
+    );
+}
+
+#[tokio::test]
+async fn test_get_workspace_files() {
+    let root = Url::parse(ROOT).unwrap();
+    let symbols = json!([
+        workspace_symbol("main", &format!("{}/src/main.rs", ROOT)),
+        workspace_symbol("execute", &format!("{}/src/db.rs", ROOT)),
+        workspace_symbol("helper", &format!("{}/src/db.rs", ROOT)),
+        workspace_symbol("spawn", "file:///dependency/src/lib.rs"),
+    ]);
+
+    // the server is still indexing when it's first asked
+    let mut indexing = true;
+    let (mut client, server) = MockServer::default()
+        .on("workspace/symbol", move |_| {
+            if std::mem::take(&mut indexing) {
+                Err(CONTENT_MODIFIED)
+            } else {
+                Ok(symbols.clone())
+            }
+        })
+        .start();
+
+    let files = code_depth::get_workspace_files(
+        &mut client,
+        &root,
+        &Default::default(),
+        Duration::from_secs(5),
+    )
+    .await
+    .expect("get_workspace_files failed");
+    client.shutdown().await.unwrap();
+
+    assert_eq!(
+        files,
+        HashSet::from([
+            Url::parse(&format!("{}/src/main.rs", ROOT)).unwrap(),
+            Url::parse(&format!("{}/src/db.rs", ROOT)).unwrap(),
+        ])
+    );
+    let messages = server.await.unwrap();
+    assert_eq!(
+        methods(&messages)[..2],
+        ["workspace/symbol", "workspace/symbol"]
+    );
+}
+
+#[tokio::test]
+async fn test_get_function_calls() {
+    let root = Url::parse(ROOT).unwrap();
+    let main_rs = format!("{}/src/main.rs", ROOT);
+    let db_rs = format!("{}/src/db.rs", ROOT);
+
+    let workspace_symbols = json!([
+        workspace_symbol("main", &main_rs),
+        workspace_symbol("execute", &db_rs),
+    ]);
+    let (main_rs_symbols, db_rs_symbols) = (
+        json!([function("main", 0), function("helper", 4)]),
+        json!([{
+            "name": "Db",
+            "kind": 23,
+            "range": range(0),
+            "selectionRange": range(0),
+            "children": [function("execute", 1)],
+        }]),
+    );
+
+    let (mut client, server) = MockServer::default()
+        .respond("initialize", capabilities())
+        .respond("workspace/symbol", workspace_symbols)
+        .on("textDocument/documentSymbol", move |params| {
+            match params["textDocument"]["uri"].as_str().unwrap() {
+                uri if uri.ends_with("main.rs") => Ok(main_rs_symbols.clone()),
+                _ => Ok(db_rs_symbols.clone()),
+            }
+        })
+        .on("callHierarchy/incomingCalls", |params| {
+            let calls = |callers: &[(&str, &str, u32)]| {
+                callers
+                    .iter()
+                    .map(|(name, file, line)| {
+                        json!({
+                            "from": call_hierarchy_item(name, file, *line),
+                            "fromRanges": [range(*line + 1), range(*line + 2)],
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            };
+
+            let calls = match params["item"]["name"].as_str().unwrap() {
+                // the server names callers its own way, and reports callers
+                // outside the project
+                "execute" => calls(&[
+                    ("main()", "src/main.rs", 0),
+                    ("helper()", "src/main.rs", 4),
+                    ("spawn", "../../dependency/src/lib.rs", 0),
+                ]),
+                "helper" => calls(&[("main()", "src/main.rs", 0)]),
+                _ => vec![],
+            };
+
+            Ok(json!(calls))
+        })
+        .start();
+
+    code_depth::init(&mut client, root.clone())
+        .await
+        .expect("init failed");
+    let files = code_depth::get_workspace_files(
+        &mut client,
+        &root,
+        &Default::default(),
+        Duration::from_secs(5),
+    )
+    .await
+    .expect("get_workspace_files failed");
+    let calls = code_depth::get_function_calls(&mut client, &files, &root, &Default::default())
+        .await
+        .expect("get_function_calls failed");
+    client.shutdown().await.unwrap();
+
+    let mut short_calls = calls
+        .iter()
+        .map(|call: &Call| {
+            let file = |uri: &Url| {
+                Path::new(uri.path())
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string()
+            };
+
+            format!(
+                "{}:{}->{}:{} ({})",
+                file(&call.caller.uri),
+                call.caller.name,
+                file(&call.callee.uri),
+                call.callee.name,
+                call.num_call_sites()
+            )
+        })
+        .collect::<Vec<_>>();
+    short_calls.sort();
+    assert_eq!(
+        short_calls,
+        [
+            "main.rs:helper->db.rs:execute (2)",
+            "main.rs:main->db.rs:execute (2)",
+            "main.rs:main->main.rs:helper (2)",
+        ]
+    );
+
+    // the server's items are prepared before asking for their callers, and
+    // are used as they are when it can't prepare them
+    let messages = server.await.unwrap();
+    let methods = methods(&messages);
+    assert_eq!(
+        methods
+            .iter()
+            .filter(|method| **method == "textDocument/prepareCallHierarchy")
+            .count(),
+        3
+    );
+    assert_eq!(
+        methods
+            .iter()
+            .filter(|method| **method == "callHierarchy/incomingCalls")
+            .count(),
+        3
+    );
+}
+
+#[tokio::test]
+async fn test_get_function_callees_functions() {
+    let root = Url::parse(ROOT).unwrap();
+    let main_rs = Url::parse(&format!("{}/src/main.rs", ROOT)).unwrap();
+    let symbols = json!([
+        function("main", 0),
+        function("helper", 4),
+        function("execute", 8)
+    ]);
+
+    let (mut client, _server) = MockServer::default()
+        .respond("initialize", capabilities())
+        .respond("textDocument/documentSymbol", symbols)
+        .on("callHierarchy/outgoingCalls", |params| {
+            let calls = |callees: &[(&str, u32)]| {
+                callees
+                    .iter()
+                    .map(|(name, line)| {
+                        json!({
+                            "to": call_hierarchy_item(name, "src/main.rs", *line),
+                            "fromRanges": [range(*line + 1)],
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            };
+
+            let calls = match params["item"]["name"].as_str().unwrap() {
+                "main" => calls(&[("helper", 4), ("execute", 8)]),
+                "helper" => calls(&[("execute", 8)]),
+                _ => vec![],
+            };
+
+            Ok(json!(calls))
+        })
+        .start();
+
+    code_depth::init_for_directions(&mut client, root.clone(), &[CallDirection::Outgoing])
+        .await
+        .expect("init failed");
+    let options = code_depth::FunctionCallsOptions {
+        functions: Some(HashSet::from(["execute".to_string()])),
+        ..Default::default()
+    };
+    let calls = code_depth::collect_function_callees(
+        &mut client,
+        &HashSet::from([main_rs]),
+        &root,
+        &options,
+    )
+    .await
+    .expect("collect_function_callees failed");
+    client.shutdown().await.unwrap();
+
+    // only the calls to the listed functions are kept, like the incoming calls
+    let mut short_calls = calls
+        .calls
+        .iter()
+        .map(|call| format!("{}->{}", call.caller.name, call.callee.name))
+        .collect::<Vec<_>>();
+    short_calls.sort();
+    assert_eq!(short_calls, ["helper->execute", "main->execute"]);
+    assert_eq!(
+        calls
+            .definitions
+            .iter()
+            .map(|item| item.name.as_str())
+            .collect::<Vec<_>>(),
+        ["execute"]
+    );
+}
+
+/// a server where `main` calls `fact`, which calls itself
+fn recursive_server() -> MockServer {
+    let item = |name: &str, line: u32| call_hierarchy_item(name, "src/main.rs", line);
+
+    MockServer::default()
+        .respond("initialize", capabilities())
+        .respond(
+            "textDocument/documentSymbol",
+            json!([function("main", 0), function("fact", 4)]),
+        )
+        .on("callHierarchy/incomingCalls", move |params| {
+            let callers = match params["item"]["name"].as_str().unwrap() {
+                "fact" => vec![item("main", 0), item("fact", 4)],
+                _ => vec![],
+            };
+
+            Ok(json!(callers
+                .into_iter()
+                .map(|from| json!({ "from": from, "fromRanges": [range(5)] }))
+                .collect::<Vec<_>>()))
+        })
+        .on("callHierarchy/outgoingCalls", move |params| {
+            let callees = match params["item"]["name"].as_str().unwrap() {
+                "main" => vec![item("fact", 4)],
+                "fact" => vec![item("fact", 4)],
+                _ => vec![],
+            };
+
+            Ok(json!(callees
+                .into_iter()
+                .map(|to| json!({ "to": to, "fromRanges": [range(5)] }))
+                .collect::<Vec<_>>()))
+        })
+}
+
+#[tokio::test]
+async fn test_drop_self_loops() {
+    let root = Url::parse(ROOT).unwrap();
+    let files = HashSet::from([Url::parse(&format!("{}/src/main.rs", ROOT)).unwrap()]);
+    let short_calls = |calls: &[Call]| {
+        let mut calls = calls
+            .iter()
+            .map(|call| format!("{}->{}", call.caller.name, call.callee.name))
+            .collect::<Vec<_>>();
+        calls.sort();
+        calls
+    };
+
+    for (drop_self_loops, expected) in [
+        (true, &["main->fact"][..]),
+        (false, &["fact->fact", "main->fact"][..]),
+    ] {
+        let options = code_depth::FunctionCallsOptions {
+            drop_self_loops,
+            ..Default::default()
+        };
+
+        let (mut client, _server) = recursive_server().start();
+        code_depth::init(&mut client, root.clone())
+            .await
+            .expect("init failed");
+        let calls = code_depth::collect_function_calls(&mut client, &files, &root, &options)
+            .await
+            .expect("collect_function_calls failed");
+        client.shutdown().await.unwrap();
+        assert_eq!(short_calls(&calls.calls), expected);
+
+        let (mut client, _server) = recursive_server().start();
+        code_depth::init_for_directions(&mut client, root.clone(), &[CallDirection::Outgoing])
+            .await
+            .expect("init failed");
+        let calls = code_depth::collect_function_callees(&mut client, &files, &root, &options)
+            .await
+            .expect("collect_function_callees failed");
+        client.shutdown().await.unwrap();
+        assert_eq!(short_calls(&calls.calls), expected);
+    }
+}
+
+#[tokio::test]
+async fn test_notify_changed_files() {
+    let dir = std::env::temp_dir().join(format!("code_depth_watch_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("open.rs"), "fn main() {}").unwrap();
+    std::fs::write(dir.join("closed.rs"), "fn helper() {}").unwrap();
+    let file = |name: &str| Url::from_file_path(dir.join(name)).unwrap();
+
+    let (mut client, server) = MockServer::default().start();
+    client
+        .did_open(file("open.rs"), "rust", String::new())
+        .await;
+    code_depth::notify_changed_files(
+        &mut client,
+        &HashSet::from([file("open.rs"), file("closed.rs"), file("deleted.rs")]),
+        4,
+    )
+    .await;
+    client.shutdown().await.unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // open files get their new text, the server reads the others itself
+    let messages = server.await.unwrap();
+    assert_eq!(
+        methods(&messages),
+        [
+            "textDocument/didOpen",
+            "textDocument/didChange",
+            "textDocument/didSave",
+            "workspace/didChangeWatchedFiles",
+            "shutdown",
+            "exit"
+        ]
+    );
+    assert_eq!(
+        messages[1]["params"],
+        json!({
+            "textDocument": { "uri": file("open.rs"), "version": 1 },
+            "contentChanges": [{ "text": "fn main() {}" }],
+        })
+    );
+    assert_eq!(
+        messages[3]["params"]["changes"],
+        json!([
+            { "uri": file("closed.rs"), "type": 2 },
+            { "uri": file("deleted.rs"), "type": 3 },
+        ])
+    );
+}
@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+use code_depth::lsp::{json_rpc, LspClient};
+
+/// lsp error code for a request of a method the server doesn't know
+pub const METHOD_NOT_FOUND: i64 = -32601;
+
+/// Answers the params of a request with its result, or with the code of the
+/// error to respond with.
+type Handler = Box<dyn FnMut(&Value) -> Result<Value, i64> + Send>;
+
+/// An in-process lang server, connected to the client over an in-memory
+/// stream, answering each request with the handler of its method.
+///
+/// Requests of methods without a handler fail with [`METHOD_NOT_FOUND`], and
+/// notifications are only recorded.
+#[derive(Default)]
+pub struct MockServer {
+    handlers: HashMap<String, Handler>,
+}
+
+impl MockServer {
+    /// Answer requests of `method` with `handler`.
+    pub fn on<F>(mut self, method: &str, handler: F) -> Self
+    where
+        F: FnMut(&Value) -> Result<Value, i64> + Send + 'static,
+    {
+        self.handlers.insert(method.to_string(), Box::new(handler));
+        self
+    }
+
+    /// Answer every request of `method` with `result`.
+    pub fn respond(self, method: &str, result: Value) -> Self {
+        self.on(method, move |_| Ok(result.clone()))
+    }
+
+    /// Connect a client to the server, which runs until the client sends
+    /// `exit` or disconnects and then returns every message it got.
+    pub fn start(self) -> (LspClient, JoinHandle<Vec<Value>>) {
+        let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+        let server = tokio::spawn(self.serve(server_stream));
+
+        let (reader, writer) = tokio::io::split(client_stream);
+
+        (LspClient::connection_client(reader, writer), server)
+    }
+
+    /// Like [`MockServer::start`], with the client connected over TCP like to
+    /// a server started with `--server-addr`.
+    pub async fn start_tcp(self) -> (LspClient, JoinHandle<Vec<Value>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            self.serve(stream).await
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+
+        (LspClient::tcp_client(stream), server)
+    }
+
+    async fn serve<S: AsyncRead + AsyncWrite + Unpin>(mut self, mut stream: S) -> Vec<Value> {
+        let mut messages = vec![];
+        while let Ok(message) = json_rpc::get_next_response(&mut stream).await {
+            let message = serde_json::from_slice::<Value>(&message).unwrap();
+            messages.push(message.clone());
+
+            let method = message["method"].as_str().unwrap_or_default();
+            if method == "exit" {
+                break;
+            }
+
+            // notifications have no id and get no response
+            let Some(id) = message.get("id") else {
+                continue;
+            };
+
+            let result = match (method, self.handlers.get_mut(method)) {
+                ("shutdown", None) => Ok(Value::Null),
+                (_, Some(handler)) => handler(&message["params"]),
+                (_, None) => Err(METHOD_NOT_FOUND),
+            };
+            let response = match result {
+                Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                Err(code) => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": code, "message": format!("mock error of {}", method) },
+                }),
+            };
+
+            let response = response.to_string();
+            let response = format!("Content-Length: {}\r\n\r\n{}", response.len(), response);
+            if stream.write_all(response.as_bytes()).await.is_err() {
+                break;
+            }
+        }
+
+        messages
+    }
+}
+
+/// The methods of `messages`, in the order they were sent.
+pub fn methods(messages: &[Value]) -> Vec<&str> {
+    messages
+        .iter()
+        .filter_map(|message| message["method"].as_str())
+        .collect()
+}
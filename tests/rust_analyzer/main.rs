@@ -3,7 +3,7 @@ use std::{path::Path, process::Stdio, time::Duration};
 use lsp_types::Url;
 use tokio::process::Command;
 
-use code_depth::{self, lsp::LspClient};
+use code_depth::{self, lsp::LspClient, Call};
 
 const SAMPLE_PROJECT_PATH: &str = "tests/rust_analyzer/sample_rust_project";
 
@@ -33,33 +33,45 @@ async fn test_lsp_client() {
         .await
         .expect("init failed");
 
-    let definitions = code_depth::get_workspace_files(&mut client, &root, Duration::from_secs(5))
-        .await
-        .expect("get_function_definitions failed");
+    let definitions = code_depth::get_workspace_files(
+        &mut client,
+        &root,
+        &Default::default(),
+        Duration::from_secs(5),
+    )
+    .await
+    .expect("get_function_definitions failed");
 
-    let calls = code_depth::get_function_calls(&mut client, &definitions, &root)
-        .await
-        .expect("get_function_calls failed");
+    let calls =
+        code_depth::get_function_calls(&mut client, &definitions, &root, &Default::default())
+            .await
+            .expect("get_function_calls failed");
 
     let mut short_calls: Vec<String> = calls
         .iter()
-        .map(|(s, t)| {
-            format!(
-                "{}:{}->{}:{}",
-                Path::new(s.uri.path())
-                    .file_name()
-                    .unwrap()
-                    .to_str()
-                    .unwrap(),
-                s.name,
-                Path::new(t.uri.path())
-                    .file_name()
-                    .unwrap()
-                    .to_str()
-                    .unwrap(),
-                t.name
-            )
-        })
+        .map(
+            |Call {
+                 caller: s,
+                 callee: t,
+                 ..
+             }| {
+                format!(
+                    "{}:{}->{}:{}",
+                    Path::new(s.uri.path())
+                        .file_name()
+                        .unwrap()
+                        .to_str()
+                        .unwrap(),
+                    s.name,
+                    Path::new(t.uri.path())
+                        .file_name()
+                        .unwrap()
+                        .to_str()
+                        .unwrap(),
+                    t.name
+                )
+            },
+        )
         .collect();
 
     short_calls.sort();
@@ -70,13 +82,14 @@ async fn test_lsp_client() {
             "main.rs:foo->main.rs:in_foo",
             "main.rs:impl_method->other_file.rs:other_file_method",
             "main.rs:in_foo->main.rs:impl_method",
+            "main.rs:main->main.rs:countdown",
             "main.rs:main->main.rs:foo",
             "main.rs:main->main.rs:impl_method",
         ],
         "didn't find all function calls"
     );
 
-    let depths = code_depth::get_function_depths(calls);
+    let depths = code_depth::get_function_depths(&calls);
 
     let short_item_depths = code_depth::build_short_fn_depths(&root, &depths);
 
@@ -112,3 +125,38 @@ async fn test_lsp_client() {
         vec![vec!["/src/main.rs:main".into(),],],
     )));
 }
+
+#[tokio::test]
+async fn test_keep_self_loops() {
+    let mut client = start_std_io_lsp_client();
+    let root = get_sample_root();
+
+    code_depth::init(&mut client, root.clone())
+        .await
+        .expect("init failed");
+
+    let definitions = code_depth::get_workspace_files(
+        &mut client,
+        &root,
+        &Default::default(),
+        Duration::from_secs(5),
+    )
+    .await
+    .expect("get_function_definitions failed");
+
+    let options = code_depth::FunctionCallsOptions {
+        drop_self_loops: false,
+        ..Default::default()
+    };
+
+    let calls = code_depth::get_function_calls(&mut client, &definitions, &root, &options)
+        .await
+        .expect("get_function_calls failed");
+
+    assert!(
+        calls
+            .iter()
+            .any(|call| call.caller.name == "countdown" && call.callee.name == "countdown"),
+        "didn't find self call of countdown"
+    );
+}
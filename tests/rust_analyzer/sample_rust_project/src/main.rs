@@ -17,6 +17,13 @@ impl A {
 fn main() {
     foo();
     (A {}).impl_method();
+    countdown(3);
+}
+
+fn countdown(n: u32) {
+    if n > 0 {
+        countdown(n - 1);
+    }
 }
 
 fn foo() {